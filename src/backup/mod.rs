@@ -0,0 +1,358 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::bot::error::{BotResult, BotError};
+use crate::config::BackupConfig;
+use crate::database::models::{GuildSetting, User};
+use crate::database::repositories::Repositories;
+use crate::utils::scheduler::Schedule;
+
+const BACKUP_FORMAT_VERSION: u32 = 2;
+const BACKUP_PREFIX: &str = "backup-";
+const BACKUP_EXTENSION: &str = ".json.gz";
+const CHECKSUM_EXTENSION: &str = ".sha256";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+// Backups written before names had milliseconds.
+const LEGACY_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupArchive {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub guild_settings: Vec<GuildSetting>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub created_at: DateTime<Utc>,
+    pub size: u64,
+}
+
+pub struct BackupManager {
+    config: BackupConfig,
+    repositories: Repositories,
+    directory: PathBuf,
+}
+
+impl BackupManager {
+    pub fn new(config: &BackupConfig, repositories: Repositories) -> BotResult<Self> {
+        let directory = PathBuf::from(&config.directory);
+        std::fs::create_dir_all(&directory)
+            .map_err(|e| BotError::Backup(format!("Failed to create backup directory {}: {}", directory.display(), e)))?;
+
+        Ok(Self {
+            config: config.clone(),
+            repositories,
+            directory,
+        })
+    }
+
+    /// Snapshot of all persisted data, in the same format as a backup.
+    pub async fn export_archive(&self) -> BotResult<BackupArchive> {
+        Ok(BackupArchive {
+            version: BACKUP_FORMAT_VERSION,
+            created_at: Utc::now(),
+            users: self.repositories.users.get_all().await?,
+            guild_settings: self.repositories.guild_settings.export().await?,
+        })
+    }
+
+    /// Replaces all persisted data with the contents of `archive`.
+    pub async fn import_archive(&self, archive: &BackupArchive) -> BotResult<()> {
+        if archive.version > BACKUP_FORMAT_VERSION {
+            return Err(BotError::Backup(format!("Unsupported data format version {}", archive.version)));
+        }
+        self.repositories.archive.replace_all(&archive.users, &archive.guild_settings).await
+    }
+
+    pub async fn create_backup(&self) -> BotResult<PathBuf> {
+        let archive = self.export_archive().await?;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut encoder, &archive)?;
+        let compressed = encoder.finish()?;
+        let checksum = hex::encode(Sha256::digest(&compressed));
+
+        let file_name = format!("{}{}{}", BACKUP_PREFIX, archive.created_at.format(TIMESTAMP_FORMAT), BACKUP_EXTENSION);
+        let path = self.directory.join(&file_name);
+        if tokio::fs::metadata(&path).await.is_ok() {
+            return Err(BotError::Backup(format!("Backup {} already exists", path.display())));
+        }
+        let tmp_path = self.directory.join(format!("{}.tmp", file_name));
+        let checksum_tmp_path = self.directory.join(format!("{}{}.tmp", file_name, CHECKSUM_EXTENSION));
+
+        // The archive is renamed into place last, so a listed backup always has its checksum.
+        tokio::fs::write(&tmp_path, &compressed).await?;
+        tokio::fs::write(&checksum_tmp_path, format!("{}  {}\n", checksum, file_name)).await?;
+        tokio::fs::rename(&checksum_tmp_path, checksum_path(&path)).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        log::info!(
+            "Created backup {} ({} users, {} guild settings, {} bytes)",
+            path.display(),
+            archive.users.len(),
+            archive.guild_settings.len(),
+            compressed.len()
+        );
+        Ok(path)
+    }
+
+    pub async fn verify(&self, path: &Path) -> BotResult<BackupArchive> {
+        let compressed = tokio::fs::read(path).await
+            .map_err(|e| BotError::Backup(format!("Failed to read backup {}: {}", path.display(), e)))?;
+        let checksum_file = tokio::fs::read_to_string(checksum_path(path)).await
+            .map_err(|e| BotError::Backup(format!("Missing checksum for backup {}: {}", path.display(), e)))?;
+
+        let expected = checksum_file.split_whitespace().next().unwrap_or_default();
+        let actual = hex::encode(Sha256::digest(&compressed));
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(BotError::Backup(format!("Checksum mismatch for backup {}: expected {}, got {}", path.display(), expected, actual)));
+        }
+
+        let mut json = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut json)
+            .map_err(|e| BotError::Backup(format!("Failed to decompress backup {}: {}", path.display(), e)))?;
+        let archive: BackupArchive = serde_json::from_slice(&json)
+            .map_err(|e| BotError::Backup(format!("Failed to parse backup {}: {}", path.display(), e)))?;
+
+        if archive.version > BACKUP_FORMAT_VERSION {
+            return Err(BotError::Backup(format!("Backup {} uses unsupported format version {}", path.display(), archive.version)));
+        }
+
+        Ok(archive)
+    }
+
+    pub async fn restore(&self, path: &Path) -> BotResult<()> {
+        let archive = self.verify(path).await?;
+        self.import_archive(&archive).await?;
+
+        log::info!(
+            "Restored backup {} from {} ({} users, {} guild settings)",
+            path.display(),
+            archive.created_at,
+            archive.users.len(),
+            archive.guild_settings.len()
+        );
+        Ok(())
+    }
+
+    pub async fn restore_latest(&self) -> BotResult<PathBuf> {
+        let latest = self.list_backups().await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| BotError::Backup("No backups available".to_string()))?;
+        self.restore(&latest.path).await?;
+        Ok(latest.path)
+    }
+
+    pub async fn list_backups(&self) -> BotResult<Vec<BackupInfo>> {
+        let mut backups = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let created_at = match path.file_name().and_then(|n| n.to_str()).and_then(parse_backup_timestamp) {
+                Some(created_at) => created_at,
+                None => continue,
+            };
+            let size = entry.metadata().await?.len();
+            backups.push(BackupInfo { path, created_at, size });
+        }
+
+        backups.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(backups)
+    }
+
+    pub async fn apply_retention(&self) -> BotResult<usize> {
+        let backups = self.list_backups().await?;
+        let keep = select_retained(&backups, self.config.keep_daily, self.config.keep_weekly);
+        let mut removed = 0;
+
+        for backup in backups.iter().filter(|b| !keep.contains(&b.path)) {
+            tokio::fs::remove_file(&backup.path).await?;
+            if let Err(e) = tokio::fs::remove_file(checksum_path(&backup.path)).await {
+                log::warn!("Failed to remove checksum for {}: {:?}", backup.path.display(), e);
+            }
+            removed += 1;
+        }
+
+        if removed > 0 {
+            log::info!("Removed {} expired backups", removed);
+        }
+        Ok(removed)
+    }
+
+    pub fn schedule(&self) -> BotResult<Schedule> {
+        match &self.config.schedule {
+            Some(expression) => Schedule::cron(expression),
+            None => Ok(Schedule::every(std::time::Duration::from_secs(self.config.interval_hours * 3600))),
+        }
+    }
+
+    pub async fn run_scheduled_backup(&self) -> BotResult<()> {
+        self.create_backup().await?;
+        if let Err(e) = self.apply_retention().await {
+            log::error!("Failed to apply backup retention: {:?}", e);
+        }
+        Ok(())
+    }
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(CHECKSUM_EXTENSION);
+    path.with_file_name(file_name)
+}
+
+fn parse_backup_timestamp(file_name: &str) -> Option<DateTime<Utc>> {
+    let timestamp = file_name.strip_prefix(BACKUP_PREFIX)?.strip_suffix(BACKUP_EXTENSION)?;
+    let naive = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(timestamp, LEGACY_TIMESTAMP_FORMAT))
+        .ok()?;
+    Some(Utc.from_utc_datetime(&naive))
+}
+
+/// `backups` must be sorted newest first, as `list_backups` returns them.
+fn select_retained(backups: &[BackupInfo], keep_daily: usize, keep_weekly: usize) -> HashSet<PathBuf> {
+    let mut keep = HashSet::new();
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+
+    for backup in backups {
+        let day = backup.created_at.date_naive();
+        if days.len() < keep_daily && days.insert(day) {
+            keep.insert(backup.path.clone());
+        }
+
+        let week = backup.created_at.iso_week();
+        if weeks.len() < keep_weekly && weeks.insert((week.year(), week.week())) {
+            keep.insert(backup.path.clone());
+        }
+    }
+
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::id::GuildId;
+    use super::*;
+    use crate::testing::test_bot;
+
+    fn backup(year: i32, month: u32, day: u32, hour: u32) -> BackupInfo {
+        let created_at = Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap();
+        let path = PathBuf::from(format!("{}{}{}", BACKUP_PREFIX, created_at.format(TIMESTAMP_FORMAT), BACKUP_EXTENSION));
+        BackupInfo { path, created_at, size: 0 }
+    }
+
+    fn retained(backups: &[BackupInfo], keep_daily: usize, keep_weekly: usize) -> Vec<DateTime<Utc>> {
+        let keep = select_retained(backups, keep_daily, keep_weekly);
+        backups.iter().filter(|backup| keep.contains(&backup.path)).map(|backup| backup.created_at).collect()
+    }
+
+    // Two backups a day from Monday 2026-10-05 to Wednesday 2026-10-21, newest first.
+    fn three_weeks() -> Vec<BackupInfo> {
+        let mut backups: Vec<BackupInfo> = (5..=21)
+            .flat_map(|day| [backup(2026, 10, day, 6), backup(2026, 10, day, 18)])
+            .collect();
+        backups.reverse();
+        backups
+    }
+
+    #[test]
+    fn the_newest_backup_of_each_recent_day_is_kept() {
+        let backups = three_weeks();
+        assert_eq!(retained(&backups, 3, 0), vec![
+            backup(2026, 10, 21, 18).created_at,
+            backup(2026, 10, 20, 18).created_at,
+            backup(2026, 10, 19, 18).created_at,
+        ]);
+        assert!(retained(&backups, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn the_newest_backup_of_each_recent_week_is_kept() {
+        let backups = three_weeks();
+        // ISO weeks start on Monday: the 19th, 12th and 5th.
+        assert_eq!(retained(&backups, 0, 2), vec![
+            backup(2026, 10, 21, 18).created_at,
+            backup(2026, 10, 18, 18).created_at,
+        ]);
+        assert_eq!(retained(&backups, 2, 3), vec![
+            backup(2026, 10, 21, 18).created_at,
+            backup(2026, 10, 20, 18).created_at,
+            backup(2026, 10, 18, 18).created_at,
+            backup(2026, 10, 11, 18).created_at,
+        ]);
+    }
+
+    #[test]
+    fn backup_names_keep_milliseconds() {
+        let created_at = Utc.with_ymd_and_hms(2026, 10, 18, 12, 30, 5).unwrap() + chrono::Duration::milliseconds(250);
+        let name = format!("{}{}{}", BACKUP_PREFIX, created_at.format(TIMESTAMP_FORMAT), BACKUP_EXTENSION);
+        assert_eq!(name, "backup-20261018T123005.250Z.json.gz");
+        assert_eq!(parse_backup_timestamp(&name), Some(created_at));
+        assert_eq!(
+            parse_backup_timestamp("backup-20261018T123005Z.json.gz"),
+            Some(Utc.with_ymd_and_hms(2026, 10, 18, 12, 30, 5).unwrap()),
+        );
+        assert_eq!(parse_backup_timestamp("backup-20261018T123005.250Z.json.gz.sha256"), None);
+        assert_eq!(parse_backup_timestamp("backup-20261018T123005.250Z.json.gz.tmp"), None);
+    }
+
+    #[tokio::test]
+    async fn backups_are_written_with_their_checksum() {
+        let bot = test_bot("");
+        let path = bot.backup_manager.create_backup().await.unwrap();
+        assert!(checksum_path(&path).exists());
+        let names: Vec<String> = std::fs::read_dir(path.parent().unwrap()).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(names.iter().all(|name| !name.ends_with(".tmp")), "{:?}", names);
+        bot.backup_manager.verify(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn restore_replaces_users_and_guild_settings() {
+        let bot = test_bot("");
+        let repositories = &bot.repositories;
+        repositories.users.create(1, "alice").await.unwrap();
+        repositories.guild_settings.set(GuildId(10), "welcome_message", "hi").await.unwrap();
+        let path = bot.backup_manager.create_backup().await.unwrap();
+
+        repositories.users.create(2, "bob").await.unwrap();
+        repositories.guild_settings.set(GuildId(10), "goodbye_message", "bye").await.unwrap();
+        bot.backup_manager.restore(&path).await.unwrap();
+
+        let users = repositories.users.get_all().await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].username, "alice");
+        let settings = repositories.guild_settings.get_all(GuildId(10)).await.unwrap();
+        assert_eq!(settings.len(), 1);
+        assert_eq!(settings["welcome_message"], "hi");
+    }
+
+    #[tokio::test]
+    async fn tampered_backups_are_rejected() {
+        let bot = test_bot("");
+        bot.repositories.users.create(1, "alice").await.unwrap();
+        let path = bot.backup_manager.create_backup().await.unwrap();
+        let mut contents = std::fs::read(&path).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        std::fs::write(&path, contents).unwrap();
+
+        bot.repositories.users.create(2, "bob").await.unwrap();
+        assert!(bot.backup_manager.restore(&path).await.is_err());
+        assert_eq!(bot.repositories.users.get_all().await.unwrap().len(), 2);
+    }
+}
@@ -9,9 +9,15 @@ pub enum BotError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
+    #[error("Migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("Unknown command: {0}")]
+    UnknownCommand(String),
+
     #[error("Command error: {0}")]
     Command(String),
 
@@ -27,12 +33,34 @@ pub enum BotError {
     #[error("Cache error: {0}")]
     Cache(String),
 
+    #[error("Settings error: {0}")]
+    Settings(String),
+
+    #[error("Backup error: {0}")]
+    Backup(String),
+
+    #[error("Scheduler error: {0}")]
+    Scheduler(String),
+
+    #[error("Invalid arguments: {0}")]
+    Cli(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
 
 pub type BotResult<T> = Result<T, BotError>;
 
+impl BotError {
+    // Errors caused by what the user asked for, as opposed to a fault in the bot or its dependencies.
+    pub fn is_user_error(&self) -> bool {
+        matches!(
+            self,
+            BotError::UnknownCommand(_) | BotError::Command(_) | BotError::Settings(_) | BotError::RateLimit(_)
+        )
+    }
+}
+
 impl From<std::io::Error> for BotError {
     fn from(err: std::io::Error) -> Self {
         BotError::Internal(err.to_string())
@@ -43,4 +71,10 @@ impl From<toml::de::Error> for BotError {
     fn from(err: toml::de::Error) -> Self {
         BotError::Config(err.to_string())
     }
+}
+
+impl From<serde_json::Error> for BotError {
+    fn from(err: serde_json::Error) -> Self {
+        BotError::Internal(err.to_string())
+    }
 }
\ No newline at end of file
@@ -6,20 +6,24 @@ use serenity::model::id::GuildId;
 use serenity::model::user::User;
 use serenity::prelude::*;
 use std::sync::Arc;
+use arc_swap::ArcSwap;
 
 use crate::bot::Bot;
-use crate::config::Config;
-use crate::database::Database;
+use crate::config::ConfigWatcher;
+use crate::database::repositories::Repositories;
 use crate::utils::metrics::Metrics;
 use crate::utils::cache::Cache;
 use crate::utils::task_manager::TaskManager;
 use crate::utils::rate_limiter::RateLimiter;
 use crate::utils::guild_data::GuildData;
+use crate::utils::event_bus::{BotEvent, EventBus};
 use crate::lang::Lang;
 use crate::plugins::PluginManager;
 use crate::security::SecurityManager;
 use crate::telemetry::TelemetryManager;
 use crate::backup::BackupManager;
+use crate::utils::scheduler::Scheduler;
+use crate::utils::shutdown::ShutdownCoordinator;
 
 pub struct Handler {
     bot: Bot,
@@ -27,21 +31,24 @@ pub struct Handler {
 
 impl Handler {
     pub fn new(
-        config: Arc<Config>,
-        database: Arc<Database>,
+        config: Arc<ConfigWatcher>,
+        repositories: Repositories,
         metrics: Arc<Metrics>,
         cache: Arc<Cache<String, String>>,
         task_manager: Arc<TaskManager>,
         rate_limiter: Arc<RateLimiter>,
         guild_data: Arc<GuildData>,
-        lang: Arc<Lang>,
+        lang: Arc<ArcSwap<Lang>>,
+        event_bus: Arc<EventBus>,
         plugin_manager: Arc<PluginManager>,
         security_manager: Arc<SecurityManager>,
         telemetry_manager: Arc<TelemetryManager>,
         backup_manager: Arc<BackupManager>,
+        shutdown: Arc<ShutdownCoordinator>,
+        scheduler: Arc<Scheduler>,
     ) -> Self {
         Self {
-            bot: Bot::new(config, database, metrics, cache, task_manager, rate_limiter, guild_data, lang, plugin_manager, security_manager, telemetry_manager, backup_manager),
+            bot: Bot::new(config, repositories, metrics, cache, task_manager, rate_limiter, guild_data, lang, event_bus, plugin_manager, security_manager, telemetry_manager, backup_manager, shutdown, scheduler),
         }
     }
 }
@@ -51,6 +58,7 @@ impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         if let Err(why) = self.bot.handle_interaction(ctx, interaction).await {
             log::error!("Error handling interaction: {:?}", why);
+            self.bot.metrics.log_error("interaction_error").await;
             self.bot.telemetry_manager.log_error("interaction_error", &why.to_string()).await.unwrap_or_else(|e| log::error!("Failed to log error: {:?}", e));
         }
     }
@@ -58,16 +66,20 @@ impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, ready: Ready) {
         if let Err(why) = self.bot.handle_ready(ctx, ready).await {
             log::error!("Error handling ready event: {:?}", why);
+            self.bot.metrics.log_error("ready_error").await;
             self.bot.telemetry_manager.log_error("ready_error", &why.to_string()).await.unwrap_or_else(|e| log::error!("Failed to log error: {:?}", e));
         }
     }
 
     async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
         let guild_id = new_member.guild_id;
-        let welcome_message = self.bot.guild_data.get(guild_id, "welcome_message")
-            .await
-            .unwrap_or(Ok(self.bot.lang.get("events.member_join")))
-            .unwrap_or_else(|_| self.bot.lang.get("events.member_join"));
+        let welcome_message = match self.bot.guild_data.get(guild_id, "welcome_message").await {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                log::error!("Failed to load welcome message for guild {}: {:?}", guild_id, e);
+                self.bot.lang().get("events.member_join").to_string()
+            }
+        };
 
         let welcome_message = welcome_message.replace("{user}", &new_member.user.name);
 
@@ -76,15 +88,19 @@ impl EventHandler for Handler {
             self.bot.telemetry_manager.log_error("welcome_message_error", &why.to_string()).await.unwrap_or_else(|e| log::error!("Failed to log error: {:?}", e));
         }
 
+        self.bot.event_bus.publish(BotEvent::MemberJoined { guild_id, user_id: new_member.user.id });
         self.bot.metrics.log_event("member_join").await;
         self.bot.telemetry_manager.log_event("member_join").await.unwrap_or_else(|e| log::error!("Failed to log event: {:?}", e));
     }
 
     async fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, _member_data: Option<Member>) {
-        let goodbye_message = self.bot.guild_data.get(guild_id, "goodbye_message")
-            .await
-            .unwrap_or(Ok(self.bot.lang.get("events.member_leave")))
-            .unwrap_or_else(|_| self.bot.lang.get("events.member_leave"));
+        let goodbye_message = match self.bot.guild_data.get(guild_id, "goodbye_message").await {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                log::error!("Failed to load goodbye message for guild {}: {:?}", guild_id, e);
+                self.bot.lang().get("events.member_leave").to_string()
+            }
+        };
 
         let goodbye_message = goodbye_message.replace("{user}", &user.name);
 
@@ -93,6 +109,7 @@ impl EventHandler for Handler {
             self.bot.telemetry_manager.log_error("goodbye_message_error", &why.to_string()).await.unwrap_or_else(|e| log::error!("Failed to log error: {:?}", e));
         }
 
+        self.bot.event_bus.publish(BotEvent::MemberLeft { guild_id, user_id: user.id });
         self.bot.metrics.log_event("member_leave").await;
         self.bot.telemetry_manager.log_event("member_leave").await.unwrap_or_else(|e| log::error!("Failed to log event: {:?}", e));
     }
@@ -100,6 +117,7 @@ impl EventHandler for Handler {
     async fn guild_create(&self, _ctx: Context, guild: Guild, is_new: bool) {
         if is_new {
             log::info!("Joined new guild: {}", guild.name);
+            self.bot.event_bus.publish(BotEvent::GuildJoined { guild_id: guild.id, name: guild.name.clone() });
             self.bot.metrics.log_event("guild_join").await;
             self.bot.telemetry_manager.log_event("guild_join").await.unwrap_or_else(|e| log::error!("Failed to log event: {:?}", e));
         }
@@ -107,6 +125,8 @@ impl EventHandler for Handler {
 
     async fn guild_delete(&self, _ctx: Context, incomplete: GuildId, _full: Option<Guild>) {
         log::info!("Left guild: {}", incomplete);
+        self.bot.event_bus.publish(BotEvent::GuildLeft { guild_id: incomplete });
+        self.bot.guild_data.unload(incomplete).await;
         self.bot.metrics.log_event("guild_leave").await;
         self.bot.telemetry_manager.log_event("guild_leave").await.unwrap_or_else(|e| log::error!("Failed to log event: {:?}", e));
     }
@@ -1,144 +1,284 @@
-use serenity::model::application::interaction::Interaction;
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::{Interaction, InteractionResponseType};
 use serenity::model::gateway::Ready;
+use serenity::model::id::GuildId;
 use serenity::prelude::*;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use arc_swap::ArcSwap;
 
-use crate::config::Config;
-use crate::database::Database;
-use crate::commands;
-use crate::utils::metrics::Metrics;
+pub mod error;
+pub mod handler;
+
+use crate::config::{Config, ConfigWatcher};
+use crate::database::repositories::Repositories;
+use crate::commands::{CommandHandler, CommandResponse};
+use crate::commands::owner::record_audit;
+use crate::utils::metrics::{CommandOutcome, Metrics};
 use crate::utils::cache::Cache;
 use crate::utils::task_manager::TaskManager;
-use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::rate_limiter::{RateLimitSubject, RateLimiter};
 use crate::utils::guild_data::GuildData;
+use crate::utils::event_bus::{BotEvent, EventBus};
+use crate::utils::format_duration;
+use crate::utils::logger::LogContext;
+use crate::utils::scheduler::{JobDefinition, Schedule, Scheduler};
+use crate::utils::shutdown::ShutdownCoordinator;
 use crate::lang::Lang;
-use crate::error::{BotError, BotResult};
-use crate::plugins::PluginManager;
+use crate::bot::error::{BotError, BotResult};
+use crate::plugins::{self, PluginManager};
 use crate::security::SecurityManager;
 use crate::telemetry::TelemetryManager;
+use crate::telemetry::otlp::CommandSpan;
 use crate::backup::BackupManager;
 
 pub struct Bot {
-    pub config: Arc<Config>,
-    pub database: Arc<Database>,
+    pub config: Arc<ConfigWatcher>,
+    pub repositories: Repositories,
     pub metrics: Arc<Metrics>,
     pub cache: Arc<Cache<String, String>>,
     pub task_manager: Arc<TaskManager>,
     pub rate_limiter: Arc<RateLimiter>,
     pub guild_data: Arc<GuildData>,
-    pub lang: Arc<Lang>,
+    pub lang: Arc<ArcSwap<Lang>>,
+    pub event_bus: Arc<EventBus>,
     pub plugin_manager: Arc<PluginManager>,
     pub security_manager: Arc<SecurityManager>,
     pub telemetry_manager: Arc<TelemetryManager>,
     pub backup_manager: Arc<BackupManager>,
+    pub shutdown: Arc<ShutdownCoordinator>,
+    pub scheduler: Arc<Scheduler>,
+    pub commands: CommandHandler,
+    builtin_plugins_loaded: AtomicBool,
 }
 
 impl Bot {
     pub fn new(
-        config: Arc<Config>,
-        database: Arc<Database>,
+        config: Arc<ConfigWatcher>,
+        repositories: Repositories,
         metrics: Arc<Metrics>,
         cache: Arc<Cache<String, String>>,
         task_manager: Arc<TaskManager>,
         rate_limiter: Arc<RateLimiter>,
         guild_data: Arc<GuildData>,
-        lang: Arc<Lang>,
+        lang: Arc<ArcSwap<Lang>>,
+        event_bus: Arc<EventBus>,
         plugin_manager: Arc<PluginManager>,
         security_manager: Arc<SecurityManager>,
         telemetry_manager: Arc<TelemetryManager>,
         backup_manager: Arc<BackupManager>,
+        shutdown: Arc<ShutdownCoordinator>,
+        scheduler: Arc<Scheduler>,
     ) -> Self {
         Self {
             config,
-            database,
+            repositories,
             metrics,
             cache,
             task_manager,
             rate_limiter,
             guild_data,
             lang,
+            event_bus,
             plugin_manager,
             security_manager,
             telemetry_manager,
             backup_manager,
+            shutdown,
+            scheduler,
+            commands: CommandHandler::new(),
+            builtin_plugins_loaded: AtomicBool::new(false),
         }
     }
 
+    /// The current configuration; hold on to it rather than calling this repeatedly, as it may
+    /// change between calls when the config file is reloaded.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.current()
+    }
+
+    pub fn lang(&self) -> Arc<Lang> {
+        self.lang.load_full()
+    }
+
     pub async fn handle_interaction(&self, ctx: Context, interaction: Interaction) -> BotResult<()> {
-        match interaction {
-            Interaction::ApplicationCommand(command) => {
-                let user_id = command.user.id;
-    
-                if self.security_manager.is_user_blocked(user_id).await {
+        let _in_flight = match self.shutdown.try_begin() {
+            Some(guard) => guard,
+            None => {
+                if let Interaction::ApplicationCommand(command) = &interaction {
                     command
                         .create_interaction_response(&ctx.http, |response| {
                             response
                                 .kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|message| 
-                                    message.content(self.lang.get("errors.user_blocked"))
-                                )
+                                .interaction_response_data(|data| data.content(self.lang().get("errors.shutting_down")).ephemeral(true))
                         })
                         .await?;
-                    return Ok(());
                 }
-    
-                if !self.security_manager.check_permissions(&command, &ctx).await? {
-                    command
-                        .create_interaction_response(&ctx.http, |response| {
-                            response
-                                .kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|message| 
-                                    message.content(self.lang.get("errors.missing_permissions"))
-                                )
-                        })
-                        .await?;
-                    return Ok(());
-                }
-    
-                if !self.security_manager.check_rate_limit(&command.data.name, user_id).await {
-                    command
-                        .create_interaction_response(&ctx.http, |response| {
-                            response
-                                .kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|message| 
-                                    message.content(self.lang.get("errors.rate_limit"))
-                                )
-                        })
-                        .await?;
-                    return Ok(());
+                return Ok(());
+            }
+        };
+
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                self.handle_command(&ctx, &command).await?;
+            }
+            Interaction::Autocomplete(autocomplete) => {
+                let builtin = match self.commands.find(&autocomplete.data.name) {
+                    Some(builtin) if !builtin.owner_only() || self.config().is_owner(autocomplete.user.id.0) => builtin,
+                    _ => return Ok(()),
+                };
+                // Options can sit under a subcommand group and a subcommand.
+                let mut subcommand = Vec::new();
+                let mut options = &autocomplete.data.options;
+                while let Some(nested) = options.iter().find(|option| matches!(option.kind, CommandOptionType::SubCommand | CommandOptionType::SubCommandGroup)) {
+                    subcommand.push(nested.name.as_str());
+                    options = &nested.options;
                 }
-    
-                if !self.rate_limiter.check("command", command.user.id.0).await {
-                    command
-                        .create_interaction_response(&ctx.http, |response| {
+                let focused = options.iter().find(|option| option.focused);
+
+                if let Some(focused) = focused {
+                    let partial = focused.value.as_ref().and_then(|value| value.as_str()).unwrap_or_default();
+                    let choices = builtin.autocomplete(self, &subcommand.join(" "), &focused.name, partial).await?;
+                    autocomplete
+                        .create_autocomplete_response(&ctx.http, |response| {
+                            for choice in &choices {
+                                response.add_string_choice(choice, choice);
+                            }
                             response
-                                .kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|message| 
-                                    message.content(self.lang.get("errors.rate_limit"))
-                                )
                         })
                         .await?;
-                    return Ok(());
                 }
-    
-                self.metrics.increment_command(&command.data.name).await;
-                self.telemetry_manager.log_command(&command.data.name).await?;
-    
-                let content = match command.data.name.as_str() {
-                    "ping" => commands::ping::run(&self.lang),
-                    "help" => commands::help::run(&self.config, &self.lang),
-                    _ => {
-                        if let Some(plugin_command) = self.plugin_manager.get_command(&command.data.name).await {
-                            plugin_command.run(self, &ctx, &command).await?
-                        } else {
-                            return Err(BotError::UnknownCommand(command.data.name.clone()));
-                        }
-                    }
-                }?;
-    
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Every invocation, built-in or plugin, goes through here so its duration and outcome are
+    // recorded without the command having to instrument itself.
+    async fn handle_command(&self, ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<()> {
+        let started = Instant::now();
+        let mut span = CommandSpan::start(&command.data.name, command.guild_id.map(|id| id.0));
+        let log_context = LogContext {
+            command: command.data.name.clone(),
+            guild_id: command.guild_id.map(|id| id.0),
+            user_id: command.user.id.0,
+        };
+        let result = log_context.scope(self.run_command(ctx, command, &mut span)).await;
+        let elapsed = started.elapsed();
+
+        let outcome = match &result {
+            Ok(outcome) => *outcome,
+            Err(why) if why.is_user_error() => CommandOutcome::UserError,
+            Err(_) => CommandOutcome::InternalError,
+        };
+        let guild_id = command.guild_id.map(|id| id.0);
+        self.metrics.record_command(&command.data.name, guild_id, outcome, elapsed).await;
+        if let Err(why) = self.telemetry_manager.log_command(&command.data.name, guild_id, outcome, elapsed).await {
+            log::error!("Failed to log command telemetry: {:?}", why);
+        }
+        self.telemetry_manager.record_command_span(span, outcome).await;
+
+        result.map(|_| ())
+    }
+
+    async fn run_command(&self, ctx: &Context, command: &ApplicationCommandInteraction, span: &mut CommandSpan) -> BotResult<CommandOutcome> {
+        let user_id = command.user.id;
+
+        span.phase("checks");
+
+        if self.security_manager.is_user_blocked(user_id).await {
+            command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| 
+                            message.content(self.lang().get("errors.user_blocked"))
+                        )
+                })
+                .await?;
+            return Ok(CommandOutcome::PermissionDenied);
+        }
+
+        let owner_only = self.commands.find(&command.data.name).map_or(false, |builtin| builtin.owner_only());
+        if owner_only && !self.config().is_owner(user_id.0) {
+            record_audit(self, user_id.0, "access_denied", &format!("command={}", command.data.name), false).await;
+            command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|data| data.content(self.lang().get("errors.owner_only")).ephemeral(true))
+                })
+                .await?;
+            return Ok(CommandOutcome::PermissionDenied);
+        }
+
+        if !self.security_manager.check_permissions(command, ctx).await? {
+            command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| 
+                            message.content(self.lang().get("errors.missing_permissions"))
+                        )
+                })
+                .await?;
+            return Ok(CommandOutcome::PermissionDenied);
+        }
+
+        let subject = RateLimitSubject {
+            user_id: user_id.0,
+            channel_id: command.channel_id.0,
+            guild_id: command.guild_id.map(|id| id.0),
+        };
+        let rate_limit = self.security_manager.check_rate_limit(&command.data.name, &subject);
+
+        if let Some(retry_after) = rate_limit.retry_after() {
+            // Round up so users are never told to retry in "0s".
+            let wait = format_duration(Duration::from_secs(retry_after.as_secs_f64().ceil().max(1.0) as u64));
+            let message = self.lang().get_with_params("errors.rate_limit_retry", &[("retry_after", &wait)]);
+            command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|data| data.content(message).ephemeral(true))
+                })
+                .await?;
+            return Ok(CommandOutcome::RateLimited);
+        }
+
+        self.metrics.increment_command(&command.data.name).await;
+
+        span.phase("dispatch");
+        let result = match self.commands.find(&command.data.name) {
+            Some(builtin) => builtin.run(self, ctx, command).await,
+            None => match self.plugin_manager.get_command(&command.data.name).await {
+                Some(plugin_command) => plugin_command.run(self, ctx, command).await.map(CommandResponse::Text),
+                None => Err(BotError::UnknownCommand(command.data.name.clone())),
+            },
+        };
+
+        let response = match result {
+            Ok(response) => response,
+            Err(why) => {
+                self.event_bus.publish(BotEvent::CommandFailed {
+                    command: command.data.name.clone(),
+                    guild_id: command.guild_id,
+                    user_id,
+                    error: why.to_string(),
+                });
+                return Err(why);
+            }
+        };
+
+        span.phase("response");
+        match response {
+            CommandResponse::Text(content) => {
                 let sanitized_content = self.security_manager.sanitize_input(&content);
                 let escaped_content = self.security_manager.escape_markdown(&sanitized_content);
-    
+
                 command
                     .create_interaction_response(&ctx.http, |response| {
                         response
@@ -147,21 +287,68 @@ impl Bot {
                     })
                     .await?;
             }
-            _ => {}
+            CommandResponse::Embed(embed) => {
+                command
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| message.add_embed(embed))
+                    })
+                    .await?;
+            }
+        }
+
+        self.event_bus.publish(BotEvent::CommandExecuted {
+            command: command.data.name.clone(),
+            guild_id: command.guild_id,
+            user_id,
+        });
+
+        Ok(CommandOutcome::Ok)
+    }
+
+    // Plugins need a `Bot`, so they are loaded here rather than at startup. Ready fires again after
+    // a reconnect, and plugins that are already loaded (or were unloaded by an owner) are left alone.
+    async fn load_builtin_plugins(&self) {
+        if self.builtin_plugins_loaded.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for (name, _) in plugins::builtin_plugins() {
+            match self.plugin_manager.load_by_name(self, name).await {
+                Ok(()) => log::info!("Loaded plugin '{}'", name),
+                Err(why) => log::error!("Failed to load plugin '{}': {:?}", name, why),
+            }
         }
-        Ok(())
     }
 
     pub async fn handle_ready(&self, ctx: Context, ready: Ready) -> BotResult<()> {
         log::info!("{} is connected!", ready.user.name);
 
-        let guild_id = GuildId(self.config.guild_id);
+        let [shard_id, shard_count] = ready.shard.unwrap_or([ctx.shard_id, 1]);
+        self.telemetry_manager.set_shard(shard_id, shard_count).await;
+
+        self.load_builtin_plugins().await;
+
+        let config = self.config();
+        let guild_id = GuildId(config.guild_id);
+        let admin_guild_id = config.bot.admin_guild_id.map(GuildId);
+        self.security_manager.configure_rate_limits(&config.rate_limits);
+
+        for builtin in self.commands.get_commands() {
+            if let Some(permissions) = builtin.required_permissions() {
+                self.security_manager.set_command_permissions(&builtin.name(), permissions).await;
+            }
+            self.security_manager.declare_rate_limit(&builtin.name(), builtin.rate_limit());
+        }
+        for plugin_command in self.plugin_manager.get_commands().await {
+            self.security_manager.declare_rate_limit(plugin_command.name(), plugin_command.rate_limit());
+        }
 
         let commands = GuildId::set_application_commands(&guild_id, &ctx.http, |commands| {
-            commands
-                .create_application_command(|command| commands::ping::register(command))
-                .create_application_command(|command| commands::help::register(command));
-            
+            self.commands.register_application_commands(commands);
+            if admin_guild_id == Some(guild_id) {
+                self.commands.register_owner_commands(commands);
+            }
             self.plugin_manager.register_commands(commands);
             commands
         })
@@ -169,27 +356,237 @@ impl Bot {
 
         log::info!("Registered slash commands: {:#?}", commands);
 
-        self.rate_limiter.add_limit("command", 5, std::time::Duration::from_secs(10));
+        if let Some(admin_guild_id) = admin_guild_id.filter(|id| *id != guild_id) {
+            GuildId::set_application_commands(&admin_guild_id, &ctx.http, |commands| {
+                self.commands.register_owner_commands(commands)
+            })
+            .await?;
+            log::info!("Registered owner commands in admin guild {}", admin_guild_id);
+        }
 
-        self.start_periodic_tasks(ctx.clone());
+        self.start_periodic_tasks(ctx.clone()).await?;
 
         self.telemetry_manager.log_event("bot_ready").await?;
 
+        self.event_bus.publish(BotEvent::Ready {
+            user_name: ready.user.name.clone(),
+            guild_count: ready.guilds.len(),
+        });
+
         Ok(())
     }
 
-    fn start_periodic_tasks(&self, ctx: Context) {
+    // Re-registering on reconnect replaces the job, so it always uses the latest context.
+    async fn start_periodic_tasks(&self, ctx: Context) -> BotResult<()> {
         let metrics = self.metrics.clone();
-        let task_manager = self.task_manager.clone();
         let telemetry_manager = self.telemetry_manager.clone();
-        
-        task_manager.spawn("metrics_reporter", async move {
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(300)).await;
+
+        self.scheduler.register(JobDefinition::new("metrics_reporter", Schedule::every(Duration::from_secs(300)), move || {
+            let ctx = ctx.clone();
+            let metrics = metrics.clone();
+            let telemetry_manager = telemetry_manager.clone();
+            async move {
                 let guild_count = ctx.cache.guild_count();
                 metrics.set_gauge("connected_guilds", guild_count as f64).await;
-                telemetry_manager.log_metric("connected_guilds", guild_count as f64).await.unwrap_or_else(|e| log::error!("Failed to log metric: {:?}", e));
+                telemetry_manager.log_metric("connected_guilds", guild_count as f64).await
             }
-        }).await.expect("Failed to spawn metrics reporter task");
+        })).await
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use serenity::builder::CreateApplicationCommands;
+    use tokio::sync::mpsc;
+    use super::*;
+    use crate::plugins::{Plugin, PluginCommand};
+    use crate::testing::test_bot;
+    use crate::utils::settings::{SettingDefinition, SettingKind, SettingValue};
+
+    struct TestPlugin;
+
+    #[async_trait]
+    impl Plugin for TestPlugin {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn description(&self) -> &str {
+            "Plugin used by the tests"
+        }
+
+        fn commands(&self) -> Vec<Box<dyn PluginCommand>> {
+            Vec::new()
+        }
+
+        fn settings(&self) -> Vec<SettingDefinition> {
+            vec![SettingDefinition::new("test_enabled", SettingKind::Bool, SettingValue::Bool(false), "Test setting")]
+        }
+
+        fn jobs(&self) -> Vec<JobDefinition> {
+            vec![JobDefinition::new("tick", Schedule::every(Duration::from_secs(3600)), || async { Ok(()) })]
+        }
+
+        async fn on_load(&self, _bot: &Bot) -> BotResult<()> {
+            Ok(())
+        }
+
+        async fn on_unload(&self, _bot: &Bot) -> BotResult<()> {
+            Ok(())
+        }
+    }
+
+    // Fails in `on_load`, or else when registering its second job.
+    struct BrokenPlugin {
+        fail_on_load: bool,
+    }
+
+    #[async_trait]
+    impl Plugin for BrokenPlugin {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        fn description(&self) -> &str {
+            "Plugin that fails to load"
+        }
+
+        fn commands(&self) -> Vec<Box<dyn PluginCommand>> {
+            Vec::new()
+        }
+
+        fn settings(&self) -> Vec<SettingDefinition> {
+            vec![SettingDefinition::new("broken_enabled", SettingKind::Bool, SettingValue::Bool(false), "Broken setting")]
+        }
+
+        fn jobs(&self) -> Vec<JobDefinition> {
+            vec![
+                JobDefinition::new("tick", Schedule::every(Duration::from_secs(3600)), || async { Ok(()) }),
+                JobDefinition::new("too_often", Schedule::every(Duration::from_millis(10)), || async { Ok(()) }),
+            ]
+        }
+
+        async fn on_load(&self, _bot: &Bot) -> BotResult<()> {
+            if self.fail_on_load {
+                return Err(BotError::Internal("on_load failed".to_string()));
+            }
+            Ok(())
+        }
+
+        async fn on_unload(&self, _bot: &Bot) -> BotResult<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn users_are_stored_in_the_injected_repository() {
+        let bot = test_bot("");
+        let user = bot.repositories.users.create(42, "alice").await.unwrap();
+        let found = bot.repositories.users.get_by_discord_id(42).await.unwrap().unwrap();
+        assert_eq!(found.id, user.id);
+        assert!(bot.repositories.users.create(42, "alice").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn guild_settings_are_validated_and_persisted() {
+        let bot = test_bot("");
+        let guild_id = GuildId(1);
+        bot.guild_data.set(guild_id, "welcome_message", "Hi {user}").await.unwrap();
+        assert!(bot.guild_data.set(guild_id, "no_such_setting", "x").await.is_err());
+
+        let stored = bot.repositories.guild_settings.get_all(guild_id).await.unwrap();
+        assert_eq!(stored["welcome_message"], "Hi {user}");
+        // A fresh read after dropping the cache comes from the repository.
+        bot.guild_data.unload_all().await;
+        assert_eq!(bot.guild_data.get(guild_id, "welcome_message").await.unwrap().to_string(), "Hi {user}");
+
+        bot.guild_data.clear(guild_id).await.unwrap();
+        assert!(bot.repositories.guild_settings.get_all(guild_id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn plugins_register_and_remove_their_settings() {
+        let bot = test_bot("");
+        bot.plugin_manager.load_plugin(&bot, Box::new(TestPlugin)).await.unwrap();
+        assert!(bot.guild_data.registry().get("test_enabled").await.is_some());
+
+        bot.plugin_manager.unload_plugin(&bot, "test").await.unwrap();
+        assert!(bot.guild_data.registry().get("test_enabled").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn plugins_register_and_remove_their_jobs() {
+        let bot = test_bot("");
+        bot.plugin_manager.load_plugin(&bot, Box::new(TestPlugin)).await.unwrap();
+        let jobs = bot.scheduler.list().await.unwrap();
+        assert!(jobs.iter().any(|status| status.job.name == "test:tick" && status.registered));
+
+        bot.plugin_manager.unload_plugin(&bot, "test").await.unwrap();
+        let jobs = bot.scheduler.list().await.unwrap();
+        assert!(jobs.iter().all(|status| !status.registered));
+    }
+
+    #[tokio::test]
+    async fn failed_plugin_loads_are_rolled_back() {
+        let bot = test_bot("");
+        for fail_on_load in [true, false] {
+            assert!(bot.plugin_manager.load_plugin(&bot, Box::new(BrokenPlugin { fail_on_load })).await.is_err());
+            assert!(bot.guild_data.registry().get("broken_enabled").await.is_none());
+            assert!(!bot.plugin_manager.is_loaded("broken").await);
+            let jobs = bot.scheduler.list().await.unwrap();
+            assert!(jobs.iter().all(|status| !status.registered));
+        }
+    }
+
+    #[tokio::test]
+    async fn builtin_plugins_are_loaded_once() {
+        let bot = test_bot("");
+        bot.load_builtin_plugins().await;
+        assert!(bot.plugin_manager.is_loaded("example").await);
+
+        // An owner's unload survives the next ready event.
+        bot.plugin_manager.unload_plugin(&bot, "example").await.unwrap();
+        bot.load_builtin_plugins().await;
+        assert!(!bot.plugin_manager.is_loaded("example").await);
+    }
+
+    #[tokio::test]
+    async fn due_jobs_run_and_record_their_result() {
+        let bot = test_bot("");
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        bot.scheduler.register(JobDefinition::new("once", Schedule::at(chrono::Utc::now()), move || {
+            let sender = sender.clone();
+            async move {
+                sender.send(()).ok();
+                Ok(())
+            }
+        })).await.unwrap();
+        tokio::spawn(Arc::clone(&bot.scheduler).run(bot.task_manager.shutdown_token()));
+
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap().unwrap();
+        let mut job = None;
+        for _ in 0..50 {
+            job = bot.repositories.scheduled_jobs.get("once").await.unwrap();
+            if job.as_ref().map_or(false, |job| job.last_status.is_some()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let job = job.unwrap();
+        assert_eq!(job.last_status.as_deref(), Some("ok"));
+        // A one-shot job is not scheduled again.
+        assert!(job.next_run_at.is_none());
+        assert!(job.locked_until.is_none());
+    }
+
+    #[test]
+    fn owner_commands_are_only_registered_on_request() {
+        let bot = test_bot("");
+        let mut commands = CreateApplicationCommands::default();
+        bot.commands.register_application_commands(&mut commands);
+        let names: Vec<&str> = commands.0.iter().filter_map(|command| command["name"].as_str()).collect();
+        assert!(names.contains(&"ping"));
+        assert!(!names.contains(&"owner"));
+    }
+}
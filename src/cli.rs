@@ -0,0 +1,524 @@
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use serenity::builder::CreateApplicationCommands;
+use serenity::http::Http;
+use serenity::model::application::command::Command;
+use serenity::model::id::GuildId;
+use crate::backup::{BackupArchive, BackupManager};
+use crate::bot::error::{BotResult, BotError};
+use crate::commands::CommandHandler;
+use crate::config::{Config, ConfigLoader};
+use crate::database::Database;
+use crate::lang::{self, Lang};
+use crate::plugins::{self, PluginCommand};
+
+pub const USAGE: &str = "\
+Usage: bot [--config <path>] [--profile <name>] [command]
+
+Commands:
+  run                               Connect to Discord and run the bot (the default)
+  check-config [--print]            Validate the configuration and language files
+  migrate up                        Apply pending database migrations
+  migrate down [steps]              Revert the most recent migrations (1 by default)
+  migrate status                    List migrations and whether they are applied
+  register-commands [--guild <id>] [--dry-run]
+                                    Push slash command definitions globally or to a guild
+  export-data [file]                Write all persisted data as JSON (to stdout by default)
+  import-data <file>                Replace all persisted data with an export or a backup
+  restore-backup <file|latest>      Restore a backup after verifying its checksum
+  help                              Show this message";
+
+// Fields that Discord echoes back unchanged; everything else (IDs, versions) is ignored when diffing.
+const COMPARED_FIELDS: &[&str] = &[
+    "name", "description", "type", "required", "options", "choices", "value",
+    "autocomplete", "min_value", "max_value", "channel_types",
+    "default_member_permissions", "dm_permission",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrateAction {
+    Up,
+    Down { steps: usize },
+    Status,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliCommand {
+    Run,
+    CheckConfig { print: bool },
+    Migrate(MigrateAction),
+    RegisterCommands { guild_id: Option<u64>, dry_run: bool },
+    ExportData { output: Option<PathBuf> },
+    ImportData { input: PathBuf },
+    RestoreBackup { target: String },
+    Help,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub config_path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub command: CliCommand,
+}
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> BotResult<Self> {
+        let mut args: Vec<String> = args.into_iter().collect();
+        let config_path = take_option(&mut args, "--config")?.map(PathBuf::from);
+        let profile = take_option(&mut args, "--profile")?;
+        // Kept from before subcommands existed.
+        let legacy_restore = take_option(&mut args, "--restore-backup")?;
+        let help = take_flag(&mut args, "--help") || take_flag(&mut args, "-h");
+
+        let command = if help {
+            CliCommand::Help
+        } else if let Some(target) = legacy_restore {
+            CliCommand::RestoreBackup { target }
+        } else {
+            let name = if args.is_empty() { "run".to_string() } else { args.remove(0) };
+            match name.as_str() {
+                "run" => CliCommand::Run,
+                "check-config" => CliCommand::CheckConfig { print: take_flag(&mut args, "--print") },
+                "migrate" => CliCommand::Migrate(parse_migrate(&mut args)?),
+                "register-commands" => {
+                    let guild_id = take_option(&mut args, "--guild")?
+                        .map(|id| id.parse::<u64>().map_err(|_| BotError::Cli(format!("Invalid guild ID '{}'", id))))
+                        .transpose()?;
+                    CliCommand::RegisterCommands { guild_id, dry_run: take_flag(&mut args, "--dry-run") }
+                }
+                "export-data" => CliCommand::ExportData {
+                    output: take_positional(&mut args).filter(|output| output != "-").map(PathBuf::from),
+                },
+                "import-data" => CliCommand::ImportData {
+                    input: take_positional(&mut args)
+                        .map(PathBuf::from)
+                        .ok_or_else(|| BotError::Cli("import-data needs a file to import".to_string()))?,
+                },
+                "restore-backup" => CliCommand::RestoreBackup {
+                    target: take_positional(&mut args)
+                        .ok_or_else(|| BotError::Cli("restore-backup needs a backup file or 'latest'".to_string()))?,
+                },
+                "help" => CliCommand::Help,
+                other => return Err(BotError::Cli(format!("Unknown command '{}'", other))),
+            }
+        };
+
+        if let Some(extra) = args.first() {
+            return Err(BotError::Cli(format!("Unexpected argument '{}'", extra)));
+        }
+        Ok(Self { config_path, profile, command })
+    }
+
+    pub fn config_loader(&self) -> ConfigLoader {
+        let mut loader = ConfigLoader::from_env();
+        if let Some(path) = &self.config_path {
+            loader = loader.with_path(path);
+        }
+        if let Some(profile) = &self.profile {
+            loader = loader.with_profile(profile);
+        }
+        loader
+    }
+}
+
+fn parse_migrate(args: &mut Vec<String>) -> BotResult<MigrateAction> {
+    let action = take_positional(args)
+        .ok_or_else(|| BotError::Cli("migrate needs one of up, down or status".to_string()))?;
+    match action.as_str() {
+        "up" => Ok(MigrateAction::Up),
+        "down" => {
+            let steps = match take_positional(args) {
+                Some(steps) => steps.parse::<usize>()
+                    .ok()
+                    .filter(|steps| *steps > 0)
+                    .ok_or_else(|| BotError::Cli(format!("Invalid number of steps '{}'", steps)))?,
+                None => 1,
+            };
+            Ok(MigrateAction::Down { steps })
+        }
+        "status" => Ok(MigrateAction::Status),
+        other => Err(BotError::Cli(format!("Unknown migrate action '{}'", other))),
+    }
+}
+
+// Accepts both `--name value` and `--name=value`.
+fn take_option(args: &mut Vec<String>, name: &str) -> BotResult<Option<String>> {
+    let prefix = format!("{}=", name);
+    if let Some(index) = args.iter().position(|arg| arg.starts_with(&prefix)) {
+        return Ok(Some(args.remove(index)[prefix.len()..].to_string()));
+    }
+    match args.iter().position(|arg| arg == name) {
+        Some(index) if index + 1 < args.len() => {
+            args.remove(index);
+            Ok(Some(args.remove(index)))
+        }
+        Some(_) => Err(BotError::Cli(format!("{} needs a value", name))),
+        None => Ok(None),
+    }
+}
+
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+fn take_positional(args: &mut Vec<String>) -> Option<String> {
+    let index = args.iter().position(|arg| !arg.starts_with("--"))?;
+    Some(args.remove(index))
+}
+
+/// Loads the configuration and every language file the way `run` would, without connecting anywhere.
+pub fn check_config(loader: &ConfigLoader, print: bool) -> BotResult<()> {
+    let config = loader.load()?;
+    println!("Configuration is valid (loaded from {})", config.sources.join(", "));
+
+    let default_language = Lang::load(&config.bot.default_language)?;
+    println!(
+        "Default language '{}' has {} messages",
+        config.bot.default_language,
+        default_language.list_keys().len()
+    );
+    let languages = lang::load_all_languages(&config)?;
+    let mut codes: Vec<&String> = languages.keys().collect();
+    codes.sort();
+    println!("Language files are valid ({})", codes.iter().map(|code| code.as_str()).collect::<Vec<_>>().join(", "));
+
+    if print {
+        println!("\n{}", config.redacted_toml()?);
+    }
+    Ok(())
+}
+
+pub async fn migrate(database: &Database, action: &MigrateAction) -> BotResult<()> {
+    match action {
+        MigrateAction::Up => {
+            let pending = database.migration_status().await?.into_iter().filter(|migration| !migration.applied).count();
+            database.run_migrations().await?;
+            println!("Applied {} migration(s)", pending);
+        }
+        MigrateAction::Down { steps } => {
+            let reverted = database.undo_migrations(*steps).await?;
+            if reverted.is_empty() {
+                println!("No migrations are applied");
+            }
+            for version in reverted {
+                println!("Reverted migration {}", version);
+            }
+        }
+        MigrateAction::Status => {
+            for migration in database.migration_status().await? {
+                println!(
+                    "{:04}  {:<8} {}",
+                    migration.version,
+                    if migration.applied { "applied" } else { "pending" },
+                    migration.description
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Registers the built-in and plugin slash commands globally, or in one guild when `guild_id` is
+/// set. Owner commands are only included for the admin guild.
+pub async fn register_commands(config: &Config, guild_id: Option<u64>, dry_run: bool) -> BotResult<()> {
+    let http = Http::new_with_application_id(&config.discord.token, config.discord.application_id);
+    let handler = CommandHandler::new();
+    let plugin_commands = plugin_commands();
+    let owner_commands = guild_id.is_some() && guild_id == config.bot.admin_guild_id;
+
+    let mut desired = CreateApplicationCommands::default();
+    build_commands(&mut desired, &handler, &plugin_commands, owner_commands);
+    let existing = match guild_id {
+        Some(guild_id) => GuildId(guild_id).get_application_commands(&http).await?,
+        None => Command::get_global_application_commands(&http).await?,
+    };
+    let existing = existing.iter().map(serde_json::to_value).collect::<Result<Vec<_>, _>>()?;
+    let target = guild_id.map_or_else(|| "globally".to_string(), |guild_id| format!("in guild {}", guild_id));
+
+    let changes = diff_commands(&desired.0, &existing);
+    if changes.is_empty() {
+        println!("Slash commands {} are up to date", target);
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    if dry_run {
+        println!("Dry run: {} change(s) {} were not registered", changes.len(), target);
+        return Ok(());
+    }
+
+    let registered = match guild_id {
+        Some(guild_id) => GuildId(guild_id)
+            .set_application_commands(&http, |commands| build_commands(commands, &handler, &plugin_commands, owner_commands))
+            .await?,
+        None => Command::set_global_application_commands(&http, |commands| build_commands(commands, &handler, &plugin_commands, owner_commands))
+            .await?,
+    };
+    println!("Registered {} slash command(s) {}", registered.len(), target);
+    Ok(())
+}
+
+// Setting the commands replaces every command of the application, so anything the running bot
+// registers has to be included or it would be deleted.
+fn plugin_commands() -> Vec<Box<dyn PluginCommand>> {
+    plugins::builtin_plugins()
+        .into_iter()
+        .flat_map(|(_, factory)| factory().commands())
+        .collect()
+}
+
+fn build_commands<'a>(
+    commands: &'a mut CreateApplicationCommands,
+    handler: &CommandHandler,
+    plugin_commands: &[Box<dyn PluginCommand>],
+    owner_commands: bool,
+) -> &'a mut CreateApplicationCommands {
+    handler.register_application_commands(commands);
+    if owner_commands {
+        handler.register_owner_commands(commands);
+    }
+    for command in plugin_commands {
+        command.register(commands);
+    }
+    commands
+}
+
+// One line per command: `+` is added, `-` removed and `~` changed.
+fn diff_commands(desired: &[Value], existing: &[Value]) -> Vec<String> {
+    let existing: Vec<Value> = existing.iter().map(normalize_command).collect();
+    let desired: Vec<Value> = desired.iter().map(normalize_command).collect();
+    let name = |command: &Value| command["name"].as_str().unwrap_or_default().to_string();
+
+    let mut changes = Vec::new();
+    for command in &desired {
+        match existing.iter().find(|existing| name(existing) == name(command)) {
+            None => changes.push(format!("+ /{}", name(command))),
+            Some(existing) if existing != command => changes.push(format!("~ /{}", name(command))),
+            Some(_) => {}
+        }
+    }
+    for command in &existing {
+        if !desired.iter().any(|desired| name(desired) == name(command)) {
+            changes.push(format!("- /{}", name(command)));
+        }
+    }
+    changes
+}
+
+fn normalize_command(command: &Value) -> Value {
+    // `false` counts as unset elsewhere, but a command that is hidden in DMs differs from one
+    // that isn't, and Discord allows DMs when the definition leaves it out.
+    let dm_permission = command.get("dm_permission").and_then(Value::as_bool).unwrap_or(true);
+    // The builder sends the permission bits as a string, while `Command` serializes a number.
+    let default_member_permissions = match command.get("default_member_permissions") {
+        Some(Value::Number(bits)) => Value::from(bits.to_string()),
+        Some(other) => other.clone(),
+        None => Value::Null,
+    };
+
+    let mut command = normalize(command);
+    if let Value::Object(fields) = &mut command {
+        // Discord reports chat input commands as type 1 even when the definition leaves it out.
+        fields.entry("type").or_insert(Value::from(1));
+        fields.insert("dm_permission".to_string(), Value::from(dm_permission));
+        if default_member_permissions.is_null() {
+            fields.remove("default_member_permissions");
+        } else {
+            fields.insert("default_member_permissions".to_string(), default_member_permissions);
+        }
+    }
+    command
+}
+
+// Keeps only the compared fields and drops empty values, which Discord treats as unset.
+fn normalize(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields.iter()
+                .filter(|(key, value)| COMPARED_FIELDS.contains(&key.as_str()) && !is_unset(value))
+                .map(|(key, value)| (key.clone(), normalize(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(normalize).collect()),
+        other => other.clone(),
+    }
+}
+
+fn is_unset(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => true,
+        Value::Array(values) => values.is_empty(),
+        _ => false,
+    }
+}
+
+pub async fn export_data(backup_manager: &BackupManager, output: Option<&Path>) -> BotResult<()> {
+    let archive = backup_manager.export_archive().await?;
+    let json = serde_json::to_string_pretty(&archive)?;
+    match output {
+        Some(path) => {
+            tokio::fs::write(path, json).await
+                .map_err(|e| BotError::Backup(format!("Failed to write {}: {}", path.display(), e)))?;
+            // Reported on stderr so stdout stays clean when it is piped.
+            eprintln!(
+                "Exported {} users and {} guild settings to {}",
+                archive.users.len(),
+                archive.guild_settings.len(),
+                path.display()
+            );
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// Imports a plain JSON export, or a `.json.gz` backup after checking its checksum.
+pub async fn import_data(backup_manager: &BackupManager, input: &Path) -> BotResult<()> {
+    let archive = if input.extension().map_or(false, |extension| extension == "gz") {
+        backup_manager.verify(input).await?
+    } else {
+        let json = tokio::fs::read(input).await
+            .map_err(|e| BotError::Backup(format!("Failed to read {}: {}", input.display(), e)))?;
+        serde_json::from_slice::<BackupArchive>(&json)
+            .map_err(|e| BotError::Backup(format!("Failed to parse {}: {}", input.display(), e)))?
+    };
+    backup_manager.import_archive(&archive).await?;
+    println!(
+        "Imported {} users and {} guild settings from {}",
+        archive.users.len(),
+        archive.guild_settings.len(),
+        input.display()
+    );
+    Ok(())
+}
+
+pub async fn restore_backup(backup_manager: &BackupManager, target: &str) -> BotResult<()> {
+    let path = if target == "latest" {
+        backup_manager.restore_latest().await?
+    } else {
+        let path = PathBuf::from(target);
+        backup_manager.restore(&path).await?;
+        path
+    };
+    println!("Restored backup {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn parse(args: &[&str]) -> BotResult<Cli> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn command(args: &[&str]) -> CliCommand {
+        parse(args).unwrap().command
+    }
+
+    #[test]
+    fn run_is_the_default_command() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, CliCommand::Run);
+        assert_eq!(cli.config_path, None);
+        assert_eq!(cli.profile, None);
+        assert_eq!(command(&["run"]), CliCommand::Run);
+    }
+
+    #[test]
+    fn global_options_are_accepted_anywhere() {
+        let cli = parse(&["--config", "bot.toml", "check-config", "--profile=staging", "--print"]).unwrap();
+        assert_eq!(cli.config_path, Some(PathBuf::from("bot.toml")));
+        assert_eq!(cli.profile.as_deref(), Some("staging"));
+        assert_eq!(cli.command, CliCommand::CheckConfig { print: true });
+        assert_eq!(command(&["check-config"]), CliCommand::CheckConfig { print: false });
+        assert!(parse(&["run", "--config"]).is_err());
+    }
+
+    #[test]
+    fn migrate_actions_are_parsed() {
+        assert_eq!(command(&["migrate", "up"]), CliCommand::Migrate(MigrateAction::Up));
+        assert_eq!(command(&["migrate", "status"]), CliCommand::Migrate(MigrateAction::Status));
+        assert_eq!(command(&["migrate", "down"]), CliCommand::Migrate(MigrateAction::Down { steps: 1 }));
+        assert_eq!(command(&["migrate", "down", "3"]), CliCommand::Migrate(MigrateAction::Down { steps: 3 }));
+        assert!(parse(&["migrate"]).is_err());
+        assert!(parse(&["migrate", "sideways"]).is_err());
+        assert!(parse(&["migrate", "down", "0"]).is_err());
+        assert!(parse(&["migrate", "down", "many"]).is_err());
+    }
+
+    #[test]
+    fn register_commands_options_are_parsed() {
+        assert_eq!(command(&["register-commands"]), CliCommand::RegisterCommands { guild_id: None, dry_run: false });
+        assert_eq!(
+            command(&["register-commands", "--guild", "42", "--dry-run"]),
+            CliCommand::RegisterCommands { guild_id: Some(42), dry_run: true },
+        );
+        assert!(parse(&["register-commands", "--guild", "general"]).is_err());
+    }
+
+    #[test]
+    fn data_commands_take_files() {
+        assert_eq!(command(&["export-data"]), CliCommand::ExportData { output: None });
+        assert_eq!(command(&["export-data", "-"]), CliCommand::ExportData { output: None });
+        assert_eq!(command(&["export-data", "data.json"]), CliCommand::ExportData { output: Some(PathBuf::from("data.json")) });
+        assert_eq!(command(&["import-data", "data.json"]), CliCommand::ImportData { input: PathBuf::from("data.json") });
+        assert!(parse(&["import-data"]).is_err());
+        assert_eq!(command(&["restore-backup", "latest"]), CliCommand::RestoreBackup { target: "latest".to_string() });
+        assert!(parse(&["restore-backup"]).is_err());
+    }
+
+    #[test]
+    fn the_legacy_restore_flag_still_works() {
+        let cli = parse(&["--restore-backup", "backups/old.json.gz", "--config", "bot.toml"]).unwrap();
+        assert_eq!(cli.command, CliCommand::RestoreBackup { target: "backups/old.json.gz".to_string() });
+        assert_eq!(cli.config_path, Some(PathBuf::from("bot.toml")));
+        assert_eq!(command(&["--restore-backup=latest"]), CliCommand::RestoreBackup { target: "latest".to_string() });
+        assert!(parse(&["--restore-backup"]).is_err());
+    }
+
+    #[test]
+    fn help_and_mistakes() {
+        assert_eq!(command(&["help"]), CliCommand::Help);
+        assert_eq!(command(&["--help"]), CliCommand::Help);
+        assert_eq!(command(&["-h"]), CliCommand::Help);
+        assert!(matches!(parse(&["deploy"]), Err(BotError::Cli(_))));
+        assert!(matches!(parse(&["run", "now"]), Err(BotError::Cli(_))));
+    }
+
+    #[test]
+    fn plugin_commands_are_registered() {
+        let mut commands = CreateApplicationCommands::default();
+        build_commands(&mut commands, &CommandHandler::new(), &plugin_commands(), false);
+        assert!(commands.0.iter().any(|command| command["name"] == "example"));
+    }
+
+    #[test]
+    fn permission_changes_are_detected() {
+        let desired = [json!({ "name": "ping", "description": "Pong", "dm_permission": false })];
+        let existing = [json!({ "name": "ping", "description": "Pong", "type": 1, "dm_permission": true })];
+        assert_eq!(diff_commands(&desired, &existing), ["~ /ping"]);
+
+        let desired = [json!({ "name": "ping", "description": "Pong", "default_member_permissions": "8" })];
+        let existing = [json!({ "name": "ping", "description": "Pong", "type": 1, "dm_permission": true })];
+        assert_eq!(diff_commands(&desired, &existing), ["~ /ping"]);
+    }
+
+    #[test]
+    fn equivalent_permissions_are_unchanged() {
+        let desired = [json!({ "name": "ping", "description": "Pong", "default_member_permissions": "8" })];
+        let existing = [json!({
+            "id": "1", "name": "ping", "description": "Pong", "type": 1,
+            "dm_permission": true, "default_member_permissions": 8,
+        })];
+        assert!(diff_commands(&desired, &existing).is_empty());
+    }
+}
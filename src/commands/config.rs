@@ -0,0 +1,302 @@
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption, CreateEmbed};
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOption};
+use serenity::model::id::GuildId;
+use serenity::model::permissions::Permissions;
+use serenity::prelude::*;
+use serenity::utils::Colour;
+use crate::commands::{get_string_option, Command, CommandResponse};
+use crate::bot::Bot;
+use crate::bot::error::{BotResult, BotError};
+use crate::utils::embed_builder::EmbedBuilder;
+use crate::utils::settings::SettingDefinition;
+
+const MAX_FIELD_LENGTH: usize = 1024;
+const MAX_EMBED_FIELDS: usize = 25;
+const MAX_AUTOCOMPLETE_CHOICES: usize = 25;
+
+pub struct ConfigCommand;
+
+enum ConfigAction {
+    List,
+    Get(String),
+    Set(String, String),
+    Reset(String),
+}
+
+impl ConfigAction {
+    fn parse(options: &[CommandDataOption]) -> BotResult<Self> {
+        let subcommand = options.first()
+            .ok_or_else(|| BotError::Command("Missing /config subcommand".to_string()))?;
+        if subcommand.name == "list" {
+            return Ok(ConfigAction::List);
+        }
+
+        let key = get_string_option(&subcommand.options, "key")
+            .ok_or_else(|| BotError::Command("Missing setting key".to_string()))?
+            .to_string();
+        match subcommand.name.as_str() {
+            "get" => Ok(ConfigAction::Get(key)),
+            "set" => {
+                let value = get_string_option(&subcommand.options, "value")
+                    .ok_or_else(|| BotError::Command("Missing setting value".to_string()))?;
+                Ok(ConfigAction::Set(key, value.to_string()))
+            }
+            "reset" => Ok(ConfigAction::Reset(key)),
+            other => Err(BotError::Command(format!("Unknown /config subcommand '{}'", other))),
+        }
+    }
+
+    /// `member_permissions` are the invoking member's permissions in `guild_id`, as sent with the
+    /// interaction.
+    async fn perform(&self, bot: &Bot, guild_id: GuildId, member_permissions: Option<Permissions>) -> BotResult<CommandResponse> {
+        let lang = bot.lang();
+        let embeds = EmbedBuilder::new(&lang);
+        let key = match self {
+            ConfigAction::List => return list(bot, &embeds, guild_id).await,
+            ConfigAction::Get(key) | ConfigAction::Set(key, _) | ConfigAction::Reset(key) => key,
+        };
+
+        let definition = match bot.guild_data.registry().get(key).await {
+            Some(definition) => definition,
+            None => {
+                let message = lang.get_with_params("config.unknown_key", &[("key", key)]);
+                return Ok(CommandResponse::Embed(embeds.build_text("config.error_title", &message, Colour::RED)));
+            }
+        };
+
+        if !matches!(self, ConfigAction::Get(_)) && !bot.security_manager.member_has_permissions(member_permissions, definition.required_permissions) {
+            return Ok(CommandResponse::Embed(embeds.build_error("config.error_title", "errors.missing_permissions")));
+        }
+
+        match self {
+            ConfigAction::Set(_, value) => match bot.guild_data.set(guild_id, key, value).await {
+                Ok(_) => Ok(CommandResponse::Embed(describe(bot, &embeds, guild_id, &definition, Some("config.updated")).await?)),
+                Err(BotError::Settings(why)) => Ok(CommandResponse::Embed(embeds.build_text("config.error_title", &why, Colour::RED))),
+                Err(why) => Err(why),
+            },
+            ConfigAction::Reset(_) => {
+                bot.guild_data.remove(guild_id, key).await?;
+                Ok(CommandResponse::Embed(describe(bot, &embeds, guild_id, &definition, Some("config.reset")).await?))
+            }
+            _ => Ok(CommandResponse::Embed(describe(bot, &embeds, guild_id, &definition, None).await?)),
+        }
+    }
+}
+
+#[async_trait]
+impl Command for ConfigCommand {
+    fn name(&self) -> String {
+        "config".to_string()
+    }
+
+    fn description(&self) -> String {
+        "View and edit this server's settings".to_string()
+    }
+
+    fn required_permissions(&self) -> Option<Permissions> {
+        Some(Permissions::MANAGE_GUILD)
+    }
+
+    fn register(&self, command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description(self.description())
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .create_option(|option| {
+                option.name("list").description("List all settings").kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option.name("get").description("Show a setting").kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|key| key_option(key))
+            })
+            .create_option(|option| {
+                option.name("set").description("Change a setting").kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|key| key_option(key))
+                    .create_sub_option(|value| {
+                        value.name("value").description("New value").kind(CommandOptionType::String).required(true)
+                    })
+            })
+            .create_option(|option| {
+                option.name("reset").description("Reset a setting to its default").kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|key| key_option(key))
+            })
+    }
+
+    async fn run(&self, bot: &Bot, _ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<CommandResponse> {
+        let guild_id = match command.guild_id {
+            Some(guild_id) => guild_id,
+            None => {
+                let lang = bot.lang();
+                return Ok(CommandResponse::Embed(EmbedBuilder::new(&lang).build_error("config.error_title", "config.guild_only")));
+            }
+        };
+        let action = ConfigAction::parse(&command.data.options)?;
+        let member_permissions = command.member.as_ref().and_then(|member| member.permissions);
+        action.perform(bot, guild_id, member_permissions).await
+    }
+
+    async fn autocomplete(&self, bot: &Bot, _subcommand: &str, option: &str, partial: &str) -> BotResult<Vec<String>> {
+        if option != "key" {
+            return Ok(Vec::new());
+        }
+        let partial = partial.to_lowercase();
+        Ok(bot.guild_data.registry().list().await
+            .into_iter()
+            .filter(|definition| definition.key.contains(&partial))
+            .take(MAX_AUTOCOMPLETE_CHOICES)
+            .map(|definition| definition.key)
+            .collect())
+    }
+}
+
+fn key_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    option
+        .name("key")
+        .description("Setting name")
+        .kind(CommandOptionType::String)
+        .required(true)
+        .set_autocomplete(true)
+}
+
+async fn list(bot: &Bot, embeds: &EmbedBuilder<'_>, guild_id: GuildId) -> BotResult<CommandResponse> {
+    let mut embed = embeds.build_custom(|e| e.title(bot.lang().get("config.list_title")).color(Colour::BLUE));
+    for definition in bot.guild_data.registry().list().await.into_iter().take(MAX_EMBED_FIELDS) {
+        let current = match bot.guild_data.get_raw(guild_id, &definition.key).await? {
+            Some(_) => bot.guild_data.get(guild_id, &definition.key).await?.to_string(),
+            None => format!("{} ({})", definition.default, bot.lang().get("config.default")),
+        };
+        embed.field(&definition.key, truncate(&format!("{}\n*{}*", current, definition.description)), false);
+    }
+    Ok(CommandResponse::Embed(embed))
+}
+
+async fn describe(
+    bot: &Bot,
+    embeds: &EmbedBuilder<'_>,
+    guild_id: GuildId,
+    definition: &SettingDefinition,
+    status_key: Option<&str>,
+) -> BotResult<CreateEmbed> {
+    let current = match bot.guild_data.get_raw(guild_id, &definition.key).await? {
+        Some(_) => bot.guild_data.get(guild_id, &definition.key).await?.to_string(),
+        None => bot.lang().get("config.not_set").to_string(),
+    };
+
+    let (title, color) = match status_key {
+        Some(status_key) => (format!("{}: {}", bot.lang().get(status_key), definition.key), Colour::DARK_GREEN),
+        None => (definition.key.clone(), Colour::BLUE),
+    };
+
+    let mut embed = embeds.build_custom(|e| e.title(title).description(&definition.description).color(color));
+    embeds.add_value_field(&mut embed, "config.current", &truncate(&current), false);
+    embeds.add_value_field(&mut embed, "config.default", &truncate(&definition.default.to_string()), false);
+    embeds.add_value_field(&mut embed, "config.type", &definition.kind.describe(), true);
+    Ok(embed)
+}
+
+fn truncate(value: &str) -> String {
+    if value.chars().count() <= MAX_FIELD_LENGTH {
+        value.to_string()
+    } else {
+        value.chars().take(MAX_FIELD_LENGTH - 1).collect::<String>() + "…"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use serde_json::Value;
+    use super::*;
+    use crate::testing::test_bot;
+    use crate::utils::settings::{SettingKind, SettingValue};
+
+    const GUILD: GuildId = GuildId(10);
+    const MANAGER: Option<Permissions> = Some(Permissions::MANAGE_GUILD);
+
+    async fn bot_with_settings() -> Bot {
+        let bot = test_bot("");
+        let registry = bot.guild_data.registry();
+        registry.register(SettingDefinition::new("levels_enabled", SettingKind::Bool, SettingValue::Bool(false), "Award levels")).await.unwrap();
+        registry.register(
+            SettingDefinition::new("audit_enabled", SettingKind::Bool, SettingValue::Bool(false), "Record audits")
+                .permissions(Permissions::ADMINISTRATOR),
+        ).await.unwrap();
+        bot
+    }
+
+    async fn perform(bot: &Bot, action: ConfigAction, member_permissions: Option<Permissions>) -> HashMap<&'static str, Value> {
+        match action.perform(bot, GUILD, member_permissions).await.unwrap() {
+            CommandResponse::Embed(embed) => embed.0,
+            CommandResponse::Text(text) => panic!("expected an embed, got {}", text),
+        }
+    }
+
+    fn field<'a>(embed: &'a HashMap<&'static str, Value>, name: &str) -> &'a str {
+        embed["fields"].as_array().unwrap().iter()
+            .find(|field| field["name"] == name)
+            .and_then(|field| field["value"].as_str())
+            .unwrap_or_else(|| panic!("no field named {}", name))
+    }
+
+    #[tokio::test]
+    async fn valid_values_are_stored() {
+        let bot = bot_with_settings().await;
+        let embed = perform(&bot, ConfigAction::Set("levels_enabled".to_string(), "yes".to_string()), MANAGER).await;
+        assert_eq!(embed["title"], "Setting updated: levels_enabled");
+        assert_eq!(field(&embed, "Current value"), "true");
+        assert_eq!(bot.guild_data.get(GUILD, "levels_enabled").await.unwrap(), SettingValue::Bool(true));
+    }
+
+    #[tokio::test]
+    async fn invalid_values_are_rejected() {
+        let bot = bot_with_settings().await;
+        let embed = perform(&bot, ConfigAction::Set("levels_enabled".to_string(), "maybe".to_string()), MANAGER).await;
+        assert_eq!(embed["title"], "Could not change setting");
+        assert!(embed["description"].as_str().unwrap().contains("levels_enabled"));
+        assert_eq!(bot.guild_data.get_raw(GUILD, "levels_enabled").await.unwrap(), None);
+
+        let embed = perform(&bot, ConfigAction::Set("no_such_setting".to_string(), "1".to_string()), MANAGER).await;
+        assert_eq!(embed["description"], "Unknown setting: no_such_setting");
+    }
+
+    #[tokio::test]
+    async fn reset_returns_to_the_default() {
+        let bot = bot_with_settings().await;
+        perform(&bot, ConfigAction::Set("levels_enabled".to_string(), "on".to_string()), MANAGER).await;
+        let embed = perform(&bot, ConfigAction::Reset("levels_enabled".to_string()), MANAGER).await;
+        assert_eq!(embed["title"], "Setting reset: levels_enabled");
+        assert_eq!(field(&embed, "Current value"), "Not set, using the default");
+        assert_eq!(bot.guild_data.get_raw(GUILD, "levels_enabled").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn list_marks_defaults() {
+        let bot = bot_with_settings().await;
+        perform(&bot, ConfigAction::Set("levels_enabled".to_string(), "true".to_string()), MANAGER).await;
+        let embed = perform(&bot, ConfigAction::List, MANAGER).await;
+        assert_eq!(embed["title"], "Server Settings");
+        assert_eq!(field(&embed, "levels_enabled"), "true\n*Award levels*");
+        assert!(field(&embed, "welcome_message").contains("(Default value)"));
+    }
+
+    #[tokio::test]
+    async fn changes_need_the_setting_permissions() {
+        let bot = bot_with_settings().await;
+        let denied = "You don't have permission to use this command.";
+        let embed = perform(&bot, ConfigAction::Set("audit_enabled".to_string(), "true".to_string()), MANAGER).await;
+        assert_eq!(embed["description"], denied);
+        let embed = perform(&bot, ConfigAction::Reset("levels_enabled".to_string()), None).await;
+        assert_eq!(embed["description"], denied);
+        assert_eq!(bot.guild_data.get_raw(GUILD, "audit_enabled").await.unwrap(), None);
+
+        // Anyone who can run the command may read a setting.
+        let embed = perform(&bot, ConfigAction::Get("audit_enabled".to_string()), None).await;
+        assert_eq!(embed["title"], "audit_enabled");
+
+        let embed = perform(&bot, ConfigAction::Set("audit_enabled".to_string(), "true".to_string()), Some(Permissions::ADMINISTRATOR)).await;
+        assert_eq!(embed["title"], "Setting updated: audit_enabled");
+        assert_eq!(bot.guild_data.get_raw(GUILD, "audit_enabled").await.unwrap().as_deref(), Some("true"));
+    }
+}
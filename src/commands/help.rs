@@ -1,7 +1,7 @@
 use serenity::builder::CreateApplicationCommand;
 use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
 use serenity::prelude::*;
-use crate::commands::Command;
+use crate::commands::{Command, CommandResponse};
 use crate::bot::Bot;
 use crate::bot::error::BotResult;
 
@@ -23,15 +23,15 @@ impl Command for Help {
             .description(self.description())
     }
 
-    async fn run(&self, bot: &Bot, _ctx: &Context, _command: &ApplicationCommandInteraction) -> BotResult<String> {
-        let mut help_text = bot.lang.get("commands.help_title").to_string() + "\n\n";
-        help_text += &bot.lang.get("commands.help_description") + "\n\n";
+    async fn run(&self, bot: &Bot, _ctx: &Context, _command: &ApplicationCommandInteraction) -> BotResult<CommandResponse> {
+        let mut help_text = bot.lang().get("commands.help_title").to_string() + "\n\n";
+        help_text += &bot.lang().get("commands.help_description") + "\n\n";
 
         for cmd in bot.plugin_manager.get_commands() {
             help_text += &format!("/{} - {}\n", cmd.name(), cmd.description());
         }
 
         bot.telemetry_manager.log_event("help_command_used").await?;
-        Ok(help_text)
+        Ok(help_text.into())
     }
 }
\ No newline at end of file
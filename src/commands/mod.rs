@@ -1,19 +1,52 @@
-use serenity::builder::CreateApplicationCommand;
-use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommands, CreateEmbed};
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOption};
+use serenity::model::permissions::Permissions;
 use serenity::prelude::*;
 use crate::bot::Bot;
 use crate::bot::error::BotResult;
+use crate::utils::rate_limiter::RateLimit;
 use crate::lang::Lang;
 
 pub mod ping;
 pub mod help;
+pub mod config;
+pub mod owner;
+
+pub enum CommandResponse {
+    Text(String),
+    Embed(CreateEmbed),
+}
+
+impl From<String> for CommandResponse {
+    fn from(text: String) -> Self {
+        CommandResponse::Text(text)
+    }
+}
 
 #[async_trait]
 pub trait Command: Send + Sync {
     fn name(&self) -> String;
     fn description(&self) -> String;
     fn register(&self, command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand;
-    async fn run(&self, bot: &Bot, ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<String>;
+    async fn run(&self, bot: &Bot, ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<CommandResponse>;
+
+    fn required_permissions(&self) -> Option<Permissions> {
+        None
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit> {
+        None
+    }
+
+    /// Owner-only commands are restricted to `bot.owners` and registered only in the admin guild.
+    fn owner_only(&self) -> bool {
+        false
+    }
+
+    /// `subcommand` is the focused option's subcommand path, such as `plugin load`, or empty.
+    async fn autocomplete(&self, _bot: &Bot, _subcommand: &str, _option: &str, _partial: &str) -> BotResult<Vec<String>> {
+        Ok(Vec::new())
+    }
 }
 
 pub struct CommandHandler {
@@ -30,13 +63,19 @@ impl CommandHandler {
     fn register_commands(&mut self) {
         self.commands.push(Box::new(ping::Ping));
         self.commands.push(Box::new(help::Help));
+        self.commands.push(Box::new(config::ConfigCommand));
+        self.commands.push(Box::new(owner::OwnerCommand));
     }
 
     pub fn get_commands(&self) -> &[Box<dyn Command>] {
         &self.commands
     }
 
-    pub async fn handle_command(&self, bot: &Bot, ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<String> {
+    pub fn find(&self, name: &str) -> Option<&dyn Command> {
+        self.commands.iter().find(|cmd| cmd.name() == name).map(|cmd| cmd.as_ref())
+    }
+
+    pub async fn handle_command(&self, bot: &Bot, ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<CommandResponse> {
         for cmd in &self.commands {
             if cmd.name() == command.data.name {
                 return cmd.run(bot, ctx, command).await;
@@ -45,8 +84,17 @@ impl CommandHandler {
         Err(crate::bot::error::BotError::UnknownCommand(command.data.name.clone()))
     }
 
-    pub fn register_application_commands(&self, commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
-        for command in &self.commands {
+    pub fn register_application_commands<'a>(&self, commands: &'a mut CreateApplicationCommands) -> &'a mut CreateApplicationCommands {
+        for command in self.commands.iter().filter(|command| !command.owner_only()) {
+            commands.create_application_command(|create_command| {
+                command.register(create_command)
+            });
+        }
+        commands
+    }
+
+    pub fn register_owner_commands<'a>(&self, commands: &'a mut CreateApplicationCommands) -> &'a mut CreateApplicationCommands {
+        for command in self.commands.iter().filter(|command| command.owner_only()) {
             commands.create_application_command(|create_command| {
                 command.register(create_command)
             });
@@ -55,6 +103,13 @@ impl CommandHandler {
     }
 }
 
+pub fn get_string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options.iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
 pub async fn check_permissions(ctx: &Context, command: &ApplicationCommandInteraction, required_permissions: Permissions) -> BotResult<bool> {
     if let Some(member) = &command.member {
         let guild = command.guild_id.unwrap().to_partial_guild(&ctx.http).await?;
@@ -63,4 +118,4 @@ pub async fn check_permissions(ctx: &Context, command: &ApplicationCommandIntera
     } else {
         Ok(false)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,543 @@
+use std::str::FromStr;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use log::LevelFilter;
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption};
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOption};
+use serenity::model::id::UserId;
+use serenity::prelude::*;
+use serenity::utils::Colour;
+use crate::commands::{get_string_option, Command, CommandResponse};
+use crate::bot::Bot;
+use crate::bot::error::{BotResult, BotError};
+use crate::utils::embed_builder::EmbedBuilder;
+use crate::utils::format_duration;
+use crate::utils::logger;
+use crate::utils::task_manager::{TaskInfo, TaskState};
+
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_AUTOCOMPLETE_CHOICES: usize = 25;
+const DEFAULT_AUDIT_ENTRIES: i64 = 10;
+const MAX_AUDIT_ENTRIES: i64 = 25;
+const MAX_FINISHED_TASKS_SHOWN: usize = 10;
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+/// Runtime administration for the bot owners. Only registered in `bot.admin_guild_id`.
+pub struct OwnerCommand;
+
+enum OwnerAction {
+    SetLogLevel(LevelFilter),
+    BlockUser(UserId),
+    UnblockUser(UserId),
+    ListPlugins,
+    LoadPlugin(String),
+    UnloadPlugin(String),
+    ListTasks,
+    ShowTask(String),
+    CancelTask(String),
+    ListJobs,
+    ClearCaches,
+    ReloadConfig,
+    Backup,
+    Audit(i64),
+}
+
+impl OwnerAction {
+    fn parse(options: &[CommandDataOption]) -> BotResult<Self> {
+        let top = options.first()
+            .ok_or_else(|| BotError::Command("Missing /owner subcommand".to_string()))?;
+        let (name, options) = match top.options.first() {
+            Some(sub) if top.kind == CommandOptionType::SubCommandGroup => (format!("{} {}", top.name, sub.name), &sub.options),
+            _ => (top.name.clone(), &top.options),
+        };
+
+        let action = match name.as_str() {
+            "loglevel" => {
+                let level = required_string(options, "level")?;
+                OwnerAction::SetLogLevel(LevelFilter::from_str(level)
+                    .map_err(|_| BotError::Command(format!("Invalid log level '{}'", level)))?)
+            }
+            "user block" => OwnerAction::BlockUser(required_user(options)?),
+            "user unblock" => OwnerAction::UnblockUser(required_user(options)?),
+            "plugin list" => OwnerAction::ListPlugins,
+            "plugin load" => OwnerAction::LoadPlugin(required_string(options, "name")?.to_string()),
+            "plugin unload" => OwnerAction::UnloadPlugin(required_string(options, "name")?.to_string()),
+            "task list" => OwnerAction::ListTasks,
+            "task info" => OwnerAction::ShowTask(required_string(options, "name")?.to_string()),
+            "task cancel" => OwnerAction::CancelTask(required_string(options, "name")?.to_string()),
+            "jobs" => OwnerAction::ListJobs,
+            "clearcache" => OwnerAction::ClearCaches,
+            "reloadconfig" => OwnerAction::ReloadConfig,
+            "backup" => OwnerAction::Backup,
+            "audit" => {
+                let limit = options.iter()
+                    .find(|option| option.name == "limit")
+                    .and_then(|option| option.value.as_ref())
+                    .and_then(|value| value.as_i64())
+                    .unwrap_or(DEFAULT_AUDIT_ENTRIES);
+                OwnerAction::Audit(limit.clamp(1, MAX_AUDIT_ENTRIES))
+            }
+            other => return Err(BotError::Command(format!("Unknown /owner subcommand '{}'", other))),
+        };
+        Ok(action)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            OwnerAction::SetLogLevel(_) => "log_level",
+            OwnerAction::BlockUser(_) => "user_block",
+            OwnerAction::UnblockUser(_) => "user_unblock",
+            OwnerAction::ListPlugins => "plugin_list",
+            OwnerAction::LoadPlugin(_) => "plugin_load",
+            OwnerAction::UnloadPlugin(_) => "plugin_unload",
+            OwnerAction::ListTasks => "task_list",
+            OwnerAction::ShowTask(_) => "task_info",
+            OwnerAction::CancelTask(_) => "task_cancel",
+            OwnerAction::ListJobs => "job_list",
+            OwnerAction::ClearCaches => "cache_clear",
+            OwnerAction::ReloadConfig => "config_reload",
+            OwnerAction::Backup => "backup",
+            OwnerAction::Audit(_) => "audit_view",
+        }
+    }
+
+    fn details(&self) -> String {
+        match self {
+            OwnerAction::SetLogLevel(level) => format!("level={}", level),
+            OwnerAction::BlockUser(user_id) | OwnerAction::UnblockUser(user_id) => format!("user_id={}", user_id.0),
+            OwnerAction::LoadPlugin(name) | OwnerAction::UnloadPlugin(name) => format!("plugin={}", name),
+            OwnerAction::ShowTask(name) | OwnerAction::CancelTask(name) => format!("task={}", name),
+            OwnerAction::Audit(limit) => format!("limit={}", limit),
+            OwnerAction::ListPlugins
+            | OwnerAction::ListTasks
+            | OwnerAction::ListJobs
+            | OwnerAction::ClearCaches
+            | OwnerAction::ReloadConfig
+            | OwnerAction::Backup => String::new(),
+        }
+    }
+
+    /// Runs the action and records it in the audit log, whether or not it succeeded.
+    async fn perform(&self, bot: &Bot, user_id: u64) -> BotResult<String> {
+        let result = self.execute(bot).await;
+
+        let mut details = self.details();
+        if let Err(why) = &result {
+            details = format!("{} error={}", details, why).trim_start().to_string();
+        }
+        record_audit(bot, user_id, self.name(), &details, result.is_ok()).await;
+        result
+    }
+
+    async fn execute(&self, bot: &Bot) -> BotResult<String> {
+        match self {
+            OwnerAction::SetLogLevel(level) => {
+                let previous = logger::log_level();
+                logger::set_log_level(*level);
+                Ok(format!("Log level changed from `{}` to `{}`", previous, level))
+            }
+            OwnerAction::BlockUser(user_id) => {
+                if bot.config().is_owner(user_id.0) {
+                    return Err(BotError::Command("Owners cannot be blocked".to_string()));
+                }
+                bot.security_manager.block_user(*user_id).await;
+                Ok(format!("Blocked <@{}>", user_id.0))
+            }
+            OwnerAction::UnblockUser(user_id) => {
+                bot.security_manager.unblock_user(*user_id).await;
+                Ok(format!("Unblocked <@{}>", user_id.0))
+            }
+            OwnerAction::ListPlugins => {
+                let loaded = bot.plugin_manager.get_plugins().await;
+                let mut names = bot.plugin_manager.get_available_plugins().await;
+                names.extend(loaded.iter().cloned());
+                names.sort();
+                names.dedup();
+                let lines = names.into_iter()
+                    .map(|name| {
+                        let state = if loaded.contains(&name) { "loaded" } else { "available" };
+                        format!("`{}` ({})", name, state)
+                    })
+                    .collect();
+                Ok(list_or_empty(lines, "No plugins"))
+            }
+            OwnerAction::LoadPlugin(name) => {
+                bot.plugin_manager.load_by_name(bot, name).await?;
+                Ok(format!("Loaded plugin `{}`. Its commands are registered on the next restart.", name))
+            }
+            OwnerAction::UnloadPlugin(name) => {
+                if !bot.plugin_manager.is_loaded(name).await {
+                    return Err(BotError::Command(format!("Plugin '{}' is not loaded", name)));
+                }
+                bot.plugin_manager.unload_plugin(bot, name).await?;
+                Ok(format!("Unloaded plugin `{}`", name))
+            }
+            OwnerAction::ListTasks => {
+                let infos = bot.task_manager.get_task_infos();
+                let count = |state: TaskState| infos.iter().filter(|info| info.state == state).count();
+                let mut lines = vec![format!(
+                    "**Running** {}/{}, **queued** {}",
+                    count(TaskState::Running),
+                    bot.task_manager.max_concurrent_tasks(),
+                    count(TaskState::Queued)
+                )];
+                let mut finished = 0;
+                for info in &infos {
+                    if info.state.is_finished() {
+                        finished += 1;
+                        if finished > MAX_FINISHED_TASKS_SHOWN {
+                            continue;
+                        }
+                    }
+                    lines.push(task_summary(info));
+                }
+                if finished > MAX_FINISHED_TASKS_SHOWN {
+                    lines.push(format!("…and {} older finished task(s)", finished - MAX_FINISHED_TASKS_SHOWN));
+                }
+                Ok(lines.join("\n"))
+            }
+            OwnerAction::ShowTask(name) => {
+                let info = bot.task_manager.get_task_info(name)
+                    .ok_or_else(|| BotError::Command(format!("Task '{}' not found", name)))?;
+                let mut lines = vec![
+                    format!("`{}` is **{}**", info.name, info.state.as_str()),
+                    format!("Spawned: {}", format_time(Some(info.spawned_at))),
+                    format!("Last started: {}", format_time(info.last_started_at)),
+                    format!("Runs: {}", info.run_count),
+                    format!("Queue wait: {}", format_duration(info.waited())),
+                ];
+                if let Some(finished_at) = info.finished_at {
+                    lines.push(format!("Finished: {}", format_time(Some(finished_at))));
+                }
+                if let Some(error) = &info.last_error {
+                    lines.push(format!("Last error: {}", error));
+                }
+                Ok(lines.join("\n"))
+            }
+            OwnerAction::CancelTask(name) => {
+                bot.task_manager.cancel(name).await
+                    .map_err(|_| BotError::Command(format!("Task '{}' is not running", name)))?;
+                Ok(format!("Cancelled task `{}`", name))
+            }
+            OwnerAction::ListJobs => {
+                let lines = bot.scheduler.list().await?
+                    .into_iter()
+                    .map(|status| {
+                        let job = &status.job;
+                        let state = if status.running {
+                            "running"
+                        } else if status.registered {
+                            "scheduled"
+                        } else {
+                            "not registered"
+                        };
+                        let mut line = format!(
+                            "`{}` {} ({}), next {}, last {}",
+                            job.name,
+                            job.schedule,
+                            state,
+                            format_time(job.next_run_at),
+                            match (&job.last_run_at, &job.last_status) {
+                                (Some(at), Some(status)) => format!("{} at {}", status, at.format("%Y-%m-%d %H:%M:%S")),
+                                _ => "never".to_string(),
+                            }
+                        );
+                        if job.missed_runs > 0 {
+                            line.push_str(&format!(", missed {} run(s) while offline", job.missed_runs));
+                        }
+                        line
+                    })
+                    .collect();
+                Ok(list_or_empty(lines, "No scheduled jobs"))
+            }
+            OwnerAction::ClearCaches => {
+                let cached = bot.cache.len().await;
+                bot.cache.clear().await;
+                let guilds = bot.guild_data.unload_all().await;
+                Ok(format!("Cleared {} cache entries and the settings of {} guilds", cached, guilds))
+            }
+            OwnerAction::ReloadConfig => {
+                let changed = bot.config.reload()
+                    .map_err(|why| BotError::Command(format!("Kept the current configuration: {}", why)))?;
+                Ok(if changed {
+                    "Reloaded the configuration. Check the log for settings that need a restart.".to_string()
+                } else {
+                    "The configuration is unchanged".to_string()
+                })
+            }
+            OwnerAction::Backup => {
+                let path = bot.backup_manager.create_backup().await?;
+                Ok(format!("Created backup `{}`", path.display()))
+            }
+            OwnerAction::Audit(limit) => {
+                let lines = bot.repositories.audit_log.recent(*limit).await?
+                    .into_iter()
+                    .map(|entry| format!(
+                        "`{}` <@{}> {} {}{}",
+                        entry.created_at.format("%Y-%m-%d %H:%M:%S"),
+                        entry.user_id,
+                        entry.action,
+                        entry.details,
+                        if entry.succeeded { "" } else { " (failed)" }
+                    ))
+                    .collect();
+                Ok(list_or_empty(lines, "The audit log is empty"))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Command for OwnerCommand {
+    fn name(&self) -> String {
+        "owner".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Bot owner administration".to_string()
+    }
+
+    fn owner_only(&self) -> bool {
+        true
+    }
+
+    fn register(&self, command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description(self.description())
+            .dm_permission(false)
+            .create_option(|option| {
+                option.name("loglevel").description("Change the default log level").kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|level| {
+                        level.name("level").description("New level").kind(CommandOptionType::String).required(true);
+                        for name in LOG_LEVELS {
+                            level.add_string_choice(name, name);
+                        }
+                        level
+                    })
+            })
+            .create_option(|group| {
+                group.name("user").description("Block or unblock users").kind(CommandOptionType::SubCommandGroup)
+                    .create_sub_option(|option| {
+                        option.name("block").description("Stop a user from running commands").kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|user| user_option(user))
+                    })
+                    .create_sub_option(|option| {
+                        option.name("unblock").description("Let a blocked user run commands again").kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|user| user_option(user))
+                    })
+            })
+            .create_option(|group| {
+                group.name("plugin").description("Manage plugins").kind(CommandOptionType::SubCommandGroup)
+                    .create_sub_option(|option| {
+                        option.name("list").description("List plugins").kind(CommandOptionType::SubCommand)
+                    })
+                    .create_sub_option(|option| {
+                        option.name("load").description("Load a plugin").kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|name| name_option(name, "Plugin name"))
+                    })
+                    .create_sub_option(|option| {
+                        option.name("unload").description("Unload a plugin").kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|name| name_option(name, "Plugin name"))
+                    })
+            })
+            .create_option(|group| {
+                group.name("task").description("Manage background tasks").kind(CommandOptionType::SubCommandGroup)
+                    .create_sub_option(|option| {
+                        option.name("list").description("List running, queued and recently finished tasks").kind(CommandOptionType::SubCommand)
+                    })
+                    .create_sub_option(|option| {
+                        option.name("info").description("Show the state and timings of a task").kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|name| name_option(name, "Task name"))
+                    })
+                    .create_sub_option(|option| {
+                        option.name("cancel").description("Cancel a task").kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|name| name_option(name, "Task name"))
+                    })
+            })
+            .create_option(|option| {
+                option.name("jobs").description("List scheduled jobs").kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option.name("clearcache").description("Clear the shared cache and cached guild settings").kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option.name("reloadconfig").description("Reload the configuration files now").kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option.name("backup").description("Create a backup now").kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option.name("audit").description("Show recent owner actions").kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|limit| {
+                        limit.name("limit").description("Number of entries").kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(MAX_AUDIT_ENTRIES)
+                    })
+            })
+    }
+
+    async fn run(&self, bot: &Bot, _ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<CommandResponse> {
+        let lang = bot.lang();
+        let embeds = EmbedBuilder::new(&lang);
+        let action = match OwnerAction::parse(&command.data.options) {
+            Ok(action) => action,
+            Err(BotError::Command(why)) => {
+                record_audit(bot, command.user.id.0, "invalid_request", &why, false).await;
+                return Ok(CommandResponse::Embed(embeds.build_text("owner.error_title", &why, Colour::RED)));
+            }
+            Err(why) => return Err(why),
+        };
+
+        match action.perform(bot, command.user.id.0).await {
+            Ok(message) => Ok(CommandResponse::Embed(embeds.build_text("owner.title", &truncate(&message), Colour::DARK_GREEN))),
+            Err(BotError::Command(why)) => Ok(CommandResponse::Embed(embeds.build_text("owner.error_title", &why, Colour::RED))),
+            Err(why) => Err(why),
+        }
+    }
+
+    async fn autocomplete(&self, bot: &Bot, subcommand: &str, option: &str, partial: &str) -> BotResult<Vec<String>> {
+        if option != "name" {
+            return Ok(Vec::new());
+        }
+        let mut names = match subcommand {
+            "plugin load" => bot.plugin_manager.get_available_plugins().await,
+            "plugin unload" => bot.plugin_manager.get_plugins().await,
+            "task info" => bot.task_manager.get_task_infos().into_iter().map(|info| info.name).collect(),
+            "task cancel" => {
+                let mut names = bot.task_manager.get_running_tasks().await;
+                names.extend(bot.task_manager.get_queued_tasks().await);
+                names
+            }
+            _ => Vec::new(),
+        };
+        names.sort();
+        names.dedup();
+        Ok(names.into_iter()
+            .filter(|name| name.contains(partial))
+            .take(MAX_AUTOCOMPLETE_CHOICES)
+            .collect())
+    }
+}
+
+pub(crate) async fn record_audit(bot: &Bot, user_id: u64, action: &str, details: &str, succeeded: bool) {
+    if let Err(why) = bot.repositories.audit_log.record(user_id as i64, action, details, succeeded).await {
+        log::error!("Failed to record owner action '{}' in the audit log: {:?}", action, why);
+    }
+    log::info!(target: "audit", "Owner {} ran {} {} (succeeded: {})", user_id, action, details, succeeded);
+}
+
+fn user_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    option.name("user").description("User").kind(CommandOptionType::User).required(true)
+}
+
+fn name_option<'a>(option: &'a mut CreateApplicationCommandOption, description: &str) -> &'a mut CreateApplicationCommandOption {
+    option.name("name").description(description).kind(CommandOptionType::String).required(true).set_autocomplete(true)
+}
+
+fn required_string<'a>(options: &'a [CommandDataOption], name: &str) -> BotResult<&'a str> {
+    get_string_option(options, name).ok_or_else(|| BotError::Command(format!("Missing option '{}'", name)))
+}
+
+// User options arrive as the user's ID in string form.
+fn required_user(options: &[CommandDataOption]) -> BotResult<UserId> {
+    required_string(options, "user")?
+        .parse()
+        .map(UserId)
+        .map_err(|_| BotError::Command("Invalid user".to_string()))
+}
+
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map_or_else(|| "never".to_string(), |time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+fn task_summary(info: &TaskInfo) -> String {
+    let mut line = format!("`{}` {}", info.name, info.state.as_str());
+    match info.state {
+        TaskState::Queued => line.push_str(&format!(", waiting {}", format_duration(info.waited()))),
+        TaskState::Running => {
+            if let Some(started) = info.last_started_at {
+                line.push_str(&format!(", since {}", format_time(Some(started))));
+            }
+            if info.queue_wait.map_or(false, |wait| wait >= Duration::from_secs(1)) {
+                line.push_str(&format!(", queued {}", format_duration(info.waited())));
+            }
+        }
+        _ => line.push_str(&format!(" at {}", format_time(info.finished_at))),
+    }
+    if info.run_count > 1 {
+        line.push_str(&format!(", {} runs", info.run_count));
+    }
+    if let Some(error) = &info.last_error {
+        line.push_str(&format!(", last error: {}", error));
+    }
+    line
+}
+
+fn list_or_empty(lines: Vec<String>, empty: &str) -> String {
+    if lines.is_empty() {
+        empty.to_string()
+    } else {
+        lines.join("\n")
+    }
+}
+
+fn truncate(value: &str) -> String {
+    if value.chars().count() <= MAX_DESCRIPTION_LENGTH {
+        value.to_string()
+    } else {
+        value.chars().take(MAX_DESCRIPTION_LENGTH - 1).collect::<String>() + "…"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_bot;
+
+    const OWNER: u64 = 1;
+
+    #[tokio::test]
+    async fn owner_actions_are_audited() {
+        let bot = test_bot("[bot]\nowners = [1]");
+        OwnerAction::BlockUser(UserId(5)).perform(&bot, OWNER).await.unwrap();
+        assert!(bot.security_manager.is_user_blocked(UserId(5)).await);
+        // Owners cannot be blocked, and the failed attempt is still recorded.
+        assert!(OwnerAction::BlockUser(UserId(OWNER)).perform(&bot, OWNER).await.is_err());
+
+        let entries = bot.repositories.audit_log.recent(10).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, "user_block");
+        assert!(!entries[0].succeeded);
+        assert!(entries[0].details.contains("error="));
+        assert_eq!(entries[1].details, "user_id=5");
+        assert!(entries[1].succeeded);
+
+        let listing = OwnerAction::Audit(10).perform(&bot, OWNER).await.unwrap();
+        assert!(listing.contains("user_block user_id=5"));
+    }
+
+    #[tokio::test]
+    async fn backup_action_writes_a_verifiable_backup() {
+        let bot = test_bot("[bot]\nowners = [1]");
+        bot.repositories.users.create(42, "alice").await.unwrap();
+        OwnerAction::Backup.perform(&bot, OWNER).await.unwrap();
+
+        let backups = bot.backup_manager.list_backups().await.unwrap();
+        assert_eq!(backups.len(), 1);
+        let archive = bot.backup_manager.verify(&backups[0].path).await.unwrap();
+        assert_eq!(archive.users.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn name_choices_depend_on_the_subcommand() {
+        let bot = test_bot("[bot]\nowners = [1]");
+        bot.task_manager.spawn("sleeper", std::future::pending()).await.unwrap();
+
+        let tasks = OwnerCommand.autocomplete(&bot, "task cancel", "name", "sle").await.unwrap();
+        assert_eq!(tasks, vec!["sleeper".to_string()]);
+        assert!(OwnerCommand.autocomplete(&bot, "plugin unload", "name", "").await.unwrap().is_empty());
+        bot.task_manager.cancel("sleeper").await.unwrap();
+    }
+}
@@ -1,9 +1,11 @@
 use serenity::builder::CreateApplicationCommand;
 use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
 use serenity::prelude::*;
-use crate::commands::Command;
+use std::time::Duration;
+use crate::commands::{Command, CommandResponse};
 use crate::bot::Bot;
 use crate::bot::error::BotResult;
+use crate::utils::rate_limiter::RateLimit;
 
 pub struct Ping;
 
@@ -17,16 +19,20 @@ impl Command for Ping {
         "A simple ping command".to_string()
     }
 
+    fn rate_limit(&self) -> Option<RateLimit> {
+        Some(RateLimit::new(3, Duration::from_secs(5)))
+    }
+
     fn register(&self, command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
         command
             .name(self.name())
             .description(self.description())
     }
 
-    async fn run(&self, bot: &Bot, ctx: &Context, _command: &ApplicationCommandInteraction) -> BotResult<String> {
+    async fn run(&self, bot: &Bot, ctx: &Context, _command: &ApplicationCommandInteraction) -> BotResult<CommandResponse> {
         let latency = ctx.cache.current_user().unwrap().id.created_at().timestamp_millis() as u64;
-        let response = bot.lang.get("commands.ping_response").replace("{latency}", &latency.to_string());
+        let response = bot.lang().get("commands.ping_response").replace("{latency}", &latency.to_string());
         bot.telemetry_manager.log_event("ping_command_used").await?;
-        Ok(response)
+        Ok(response.into())
     }
 }
\ No newline at end of file
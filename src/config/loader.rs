@@ -1,25 +1,425 @@
+use std::collections::HashMap;
 use std::fs;
-use toml;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
 use crate::config::Config;
-use crate::bot::error::BotError;
+use crate::bot::error::{BotResult, BotError};
 
-pub struct ConfigLoader;
+const DEFAULTS: &str = include_str!("defaults.toml");
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+const ENV_PREFIX: &str = "BOT__";
+const ENV_SEPARATOR: &str = "__";
+const FILE_SUFFIX: &str = "_FILE";
+
+// Variables read before `BOT__` overrides existed; they only fill values left empty.
+const LEGACY_ENV: &[(&str, &[&str])] = &[
+    ("DISCORD_TOKEN", &["discord", "token"]),
+    ("DATABASE_URL", &["database", "url"]),
+    ("TELEMETRY_SALT", &["telemetry", "redaction", "salt"]),
+];
+
+/// Builds the configuration from layers, each overriding the one before: built-in defaults,
+/// the config file, an optional profile file (`config.<profile>.toml`) and `BOT__SECTION__KEY`
+/// environment variables. Strings in files may reference `${VAR}` or `${VAR:-default}`, and any
+/// variable read from the environment can instead be read from the file named by `VAR_FILE`.
+pub struct ConfigLoader {
+    path: PathBuf,
+    // A missing config file is only an error when its path was chosen explicitly.
+    path_required: bool,
+    profile: Option<String>,
+    // Read once when the loader is created; secret files are still read on every load.
+    env: HashMap<String, String>,
+}
 
 impl ConfigLoader {
-    pub fn load() -> Result<Config, BotError> {
-        let config_str = fs::read_to_string("config.toml")
-            .map_err(|e| BotError::Config(format!("Failed to read config file: {}", e)))?;
+    /// Uses `CONFIG_PATH` and `BOT_PROFILE` when set.
+    pub fn from_env() -> Self {
+        Self::from_vars(
+            std::env::vars_os()
+                .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+                .collect(),
+        )
+    }
+
+    /// Like `from_env`, but reads every variable from `env` instead of the process environment.
+    pub fn from_vars(env: HashMap<String, String>) -> Self {
+        let path = env.get("CONFIG_PATH").filter(|path| !path.is_empty()).cloned();
+        Self {
+            path_required: path.is_some(),
+            path: PathBuf::from(path.unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string())),
+            profile: env.get("BOT_PROFILE").filter(|profile| !profile.is_empty()).cloned(),
+            env,
+        }
+    }
+
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = path.into();
+        self.path_required = true;
+        self
+    }
+
+    pub fn with_profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
 
-        let config: Config = toml::from_str(&config_str)
-            .map_err(|e| BotError::Config(format!("Failed to parse config file: {}", e)))?;
+    pub fn load(&self) -> BotResult<Config> {
+        let config = self.merge_layers()?;
+        config.validate()?;
+        Ok(config)
+    }
 
+    /// Like `load`, but only validates `[database]` and `[backup]`, for the commands that work on
+    /// stored data and never connect to Discord.
+    pub fn load_storage(&self) -> BotResult<Config> {
+        let config = self.merge_layers()?;
+        config.validate_storage()?;
         Ok(config)
     }
 
-    pub fn load_env() -> Result<(), BotError> {
-        dotenv::dotenv()
-            .map_err(|e| BotError::Config(format!("Failed to load .env file: {}", e)))?;
+    fn merge_layers(&self) -> BotResult<Config> {
+        let mut merged = parse(DEFAULTS, "built-in defaults")?;
+        let mut sources = vec!["built-in defaults".to_string()];
+
+        if self.path.exists() || self.path_required {
+            merge(&mut merged, read_layer(&self.path, &self.env)?);
+            sources.push(self.path.display().to_string());
+        }
+        if let Some(profile) = &self.profile {
+            let path = self.profile_path(profile);
+            if !path.exists() {
+                return Err(BotError::Config(format!(
+                    "Profile '{}' was selected but {} does not exist",
+                    profile,
+                    path.display()
+                )));
+            }
+            merge(&mut merged, read_layer(&path, &self.env)?);
+            sources.push(path.display().to_string());
+        }
+
+        let overrides = apply_env_overrides(&mut merged, &self.env)?;
+        if overrides > 0 {
+            sources.push(format!("{} {} environment variable(s)", overrides, ENV_PREFIX));
+        }
+        for &(name, path) in LEGACY_ENV {
+            if get(&merged, path).and_then(Value::as_str).map_or(true, str::is_empty) {
+                if let Some(value) = read_env(name, &self.env)? {
+                    set(&mut merged, path, Value::String(value), name)?;
+                    sources.push(name.to_string());
+                }
+            }
+        }
+
+        let mut config: Config = merged.try_into()
+            .map_err(|e| BotError::Config(format!("Invalid configuration: {}", e)))?;
+        config.sources = sources;
+        Ok(config)
+    }
+
+    /// The files this loader reads, whether or not they exist yet.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.path.clone()];
+        paths.extend(self.profile.as_deref().map(|profile| self.profile_path(profile)));
+        paths
+    }
+
+    // config.toml becomes config.production.toml, next to it.
+    fn profile_path(&self, profile: &str) -> PathBuf {
+        let stem = self.path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("config");
+        let extension = self.path.extension().and_then(|extension| extension.to_str()).unwrap_or("toml");
+        self.path.with_file_name(format!("{}.{}.{}", stem, profile, extension))
+    }
+}
+
+fn parse(contents: &str, source: &str) -> BotResult<Value> {
+    toml::from_str(contents).map_err(|e| BotError::Config(format!("Failed to parse {}: {}", source, e)))
+}
+
+fn read_layer(path: &Path, env: &HashMap<String, String>) -> BotResult<Value> {
+    let source = path.display().to_string();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| BotError::Config(format!("Failed to read config file {}: {}", source, e)))?;
+    let mut value = parse(&contents, &source)?;
+    interpolate(&mut value, &source, env)?;
+    Ok(value)
+}
+
+// Tables are merged key by key; any other value replaces the one below it.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Table(base), Value::Table(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+fn interpolate(value: &mut Value, source: &str, env: &HashMap<String, String>) -> BotResult<()> {
+    match value {
+        Value::String(text) => *text = interpolate_str(text, source, env)?,
+        Value::Array(values) => {
+            for value in values {
+                interpolate(value, source, env)?;
+            }
+        }
+        Value::Table(table) => {
+            for value in table.values_mut() {
+                interpolate(value, source, env)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(text: &str, source: &str, env: &HashMap<String, String>) -> BotResult<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find('}')
+            .ok_or_else(|| BotError::Config(format!("Unterminated '${{' in {}", source)))?;
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        match (read_env(name, env)?, default) {
+            (Some(value), _) => result.push_str(&value),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => {
+                return Err(BotError::Config(format!("{} references ${{{}}}, which is not set", source, name)));
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Reads `name` from the environment, or the contents of the file named by `name_FILE`
+/// (as used for Docker and Kubernetes secrets).
+fn read_env(name: &str, env: &HashMap<String, String>) -> BotResult<Option<String>> {
+    if let Some(value) = env.get(name) {
+        return Ok(Some(value.clone()));
+    }
+    match env.get(&format!("{}{}", name, FILE_SUFFIX)) {
+        Some(path) => read_secret(path, name).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn read_secret(path: &str, name: &str) -> BotResult<String> {
+    fs::read_to_string(path)
+        .map(|contents| contents.trim_end_matches(['\n', '\r']).to_string())
+        .map_err(|e| BotError::Config(format!("Failed to read {}{} from {}: {}", name, FILE_SUFFIX, path, e)))
+}
+
+// BOT__TELEMETRY__OTLP__ENABLED=true sets telemetry.otlp.enabled. A `_FILE` suffix on a key
+// the configuration does not already have reads the value from that file instead.
+fn apply_env_overrides(merged: &mut Value, env: &HashMap<String, String>) -> BotResult<usize> {
+    let mut variables: Vec<(&String, &String)> = env.iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    variables.sort();
+
+    for &(name, raw) in &variables {
+        let mut path: Vec<String> = name[ENV_PREFIX.len()..]
+            .split(ENV_SEPARATOR)
+            .map(str::to_lowercase)
+            .collect();
+        if path.iter().any(String::is_empty) {
+            return Err(BotError::Config(format!("Invalid configuration variable {}", name)));
+        }
+
+        let mut raw = raw.to_string();
+        if get(merged, &path).is_none() {
+            if let Some(key) = path.last().and_then(|last| last.strip_suffix("_file")).map(str::to_string) {
+                raw = read_secret(&raw, &name[..name.len() - FILE_SUFFIX.len()])?;
+                *path.last_mut().expect("path is not empty") = key;
+            }
+        }
+
+        let value = match get(merged, &path) {
+            // Strings stay strings, so a numeric token or password is not turned into a number.
+            Some(Value::String(_)) => Value::String(raw),
+            Some(_) => toml_literal(&raw)
+                .ok_or_else(|| BotError::Config(format!("{} must be a TOML value such as 5, true or [1, 2]", name)))?,
+            None => toml_literal(&raw).unwrap_or(Value::String(raw)),
+        };
+        set(merged, &path, value, name)?;
+    }
+    Ok(variables.len())
+}
+
+fn toml_literal(raw: &str) -> Option<Value> {
+    let mut table: Table = toml::from_str(&format!("value = {}", raw)).ok()?;
+    table.remove("value")
+}
+
+fn get<'a, K: AsRef<str>>(value: &'a Value, path: &[K]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key.as_ref()))
+}
+
+fn set<K: AsRef<str>>(root: &mut Value, path: &[K], value: Value, source: &str) -> BotResult<()> {
+    let (last, parents) = path.split_last()
+        .ok_or_else(|| BotError::Config(format!("Invalid configuration variable {}", source)))?;
+    let mut table = root.as_table_mut()
+        .ok_or_else(|| BotError::Config("The configuration root must be a table".to_string()))?;
+    for key in parents {
+        table = table.entry(key.as_ref().to_string())
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| BotError::Config(format!("{} sets a key inside '{}', which is not a table", source, key.as_ref())))?;
+    }
+    table.insert(last.as_ref().to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{temp_dir, write_config};
+
+    fn loader(vars: &[(&str, &str)]) -> ConfigLoader {
+        ConfigLoader::from_vars(vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+    }
+
+    #[test]
+    fn storage_loads_skip_the_discord_settings() {
+        let path = temp_dir().join("config.toml");
+        fs::write(&path, "[database]\nurl = \"sqlite::memory:\"\n\n[bot]\ndefault_language = \"xx\"").unwrap();
+
+        assert!(loader(&[]).with_path(&path).load().is_err());
+        let config = loader(&[]).with_path(&path).load_storage().unwrap();
+        assert!(config.discord.token.is_empty());
+        assert_eq!(config.database.url, "sqlite::memory:");
+
+        fs::write(&path, "[database]\nurl = \"mysql://localhost/bot\"").unwrap();
+        assert!(loader(&[]).with_path(&path).load_storage().is_err());
+    }
+
+    #[test]
+    fn layers_override_each_other_in_order() {
+        let directory = temp_dir();
+        let path = write_config(&directory, "[bot]\nname = \"file\"\nshutdown_timeout_secs = 10");
+        let profile_path = directory.join("config.staging.toml");
+        fs::write(&profile_path, "[bot]\nname = \"profile\"").unwrap();
+
+        let config = loader(&[]).with_path(&path).load().unwrap();
+        assert_eq!(config.bot.name, "file");
+        assert_eq!(config.bot.shutdown_timeout_secs, 10);
+        // Keys no layer sets keep the built-in default.
+        assert_eq!(config.bot.config_reload_secs, 5);
+
+        let config = loader(&[("BOT_PROFILE", "staging")]).with_path(&path).load().unwrap();
+        assert_eq!(config.bot.name, "profile");
+        assert_eq!(config.bot.shutdown_timeout_secs, 10);
+
+        let config = loader(&[("BOT_PROFILE", "staging"), ("BOT__BOT__NAME", "env"), ("BOT__BOT__SHUTDOWN_TIMEOUT_SECS", "20")])
+            .with_path(&path)
+            .load()
+            .unwrap();
+        assert_eq!(config.bot.name, "env");
+        assert_eq!(config.bot.shutdown_timeout_secs, 20);
+        assert_eq!(config.sources, [
+            "built-in defaults".to_string(),
+            path.display().to_string(),
+            profile_path.display().to_string(),
+            "2 BOT__ environment variable(s)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn paths_and_profiles_come_from_the_environment() {
+        let directory = temp_dir();
+        let path = write_config(&directory, "[bot]\nname = \"file\"");
+        let config = loader(&[("CONFIG_PATH", &path.display().to_string())]).load().unwrap();
+        assert_eq!(config.bot.name, "file");
+
+        // A chosen file or profile has to exist.
+        assert!(loader(&[("CONFIG_PATH", &directory.join("missing.toml").display().to_string())]).load().is_err());
+        assert!(loader(&[("BOT_PROFILE", "missing")]).with_path(&path).load().is_err());
+    }
+
+    #[test]
+    fn overrides_keep_the_type_of_the_value_they_replace() {
+        let directory = temp_dir();
+        let path = write_config(&directory, "");
+        let config = loader(&[("BOT__DISCORD__TOKEN", "12345"), ("BOT__BOT__OWNERS", "[1, 2]")]).with_path(&path).load().unwrap();
+        assert_eq!(config.discord.token, "12345");
+        assert_eq!(config.bot.owners, [1, 2]);
+
+        assert!(loader(&[("BOT__BOT__SHUTDOWN_TIMEOUT_SECS", "soon")]).with_path(&path).load().is_err());
+        assert!(loader(&[("BOT__BOT____NAME", "x")]).with_path(&path).load().is_err());
+    }
+
+    #[test]
+    fn strings_interpolate_variables_with_defaults() {
+        let directory = temp_dir();
+        let path = write_config(&directory, "[bot]\nname = \"${BOT_NAME} bot\"\n\n[metrics]\nbind_address = \"${METRICS_HOST:-0.0.0.0}\"");
+
+        let config = loader(&[("BOT_NAME", "alpha")]).with_path(&path).load().unwrap();
+        assert_eq!(config.bot.name, "alpha bot");
+        assert_eq!(config.metrics.bind_address, "0.0.0.0");
+
+        let config = loader(&[("BOT_NAME", "alpha"), ("METRICS_HOST", "10.0.0.1")]).with_path(&path).load().unwrap();
+        assert_eq!(config.metrics.bind_address, "10.0.0.1");
+
+        let error = loader(&[]).with_path(&path).load().unwrap_err();
+        assert!(error.to_string().contains("BOT_NAME"));
+        assert!(interpolate_str("${UNTERMINATED", "test", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn secrets_are_read_from_files() {
+        let directory = temp_dir();
+        let secret = directory.join("secret");
+        fs::write(&secret, "from-file\n").unwrap();
+        let secret = secret.display().to_string();
+        let path = write_config(&directory, "[bot]\nname = \"${BOT_NAME}\"");
+
+        let config = loader(&[("BOT_NAME_FILE", &secret), ("BOT__DISCORD__TOKEN_FILE", &secret)]).with_path(&path).load().unwrap();
+        assert_eq!(config.bot.name, "from-file");
+        assert_eq!(config.discord.token, "from-file");
+
+        let missing = directory.join("missing").display().to_string();
+        assert!(loader(&[("BOT_NAME_FILE", &missing)]).with_path(&path).load().is_err());
+    }
+
+    #[test]
+    fn legacy_variables_only_fill_empty_values() {
+        let directory = temp_dir();
+        let path = directory.join("config.toml");
+        fs::write(&path, "[discord]\napplication_id = 1").unwrap();
+        let secret = directory.join("salt");
+        fs::write(&secret, "salt-from-file").unwrap();
+
+        let config = loader(&[
+            ("DISCORD_TOKEN", "legacy-token"),
+            ("DATABASE_URL", "sqlite::memory:"),
+            ("TELEMETRY_SALT_FILE", &secret.display().to_string()),
+        ])
+        .with_path(&path)
+        .load()
+        .unwrap();
+        assert_eq!(config.discord.token, "legacy-token");
+        assert_eq!(config.database.url, "sqlite::memory:");
+        assert_eq!(config.telemetry.redaction.salt, "salt-from-file");
+        assert!(config.sources.contains(&"DISCORD_TOKEN".to_string()));
 
-        Ok(())
+        let config = loader(&[("DISCORD_TOKEN", "legacy-token"), ("BOT__DISCORD__TOKEN", "new-token"), ("DATABASE_URL", "sqlite::memory:")])
+            .with_path(&path)
+            .load()
+            .unwrap();
+        assert_eq!(config.discord.token, "new-token");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,256 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use arc_swap::ArcSwap;
+use crate::config::{Config, ConfigLoader};
+use crate::bot::error::BotResult;
+use crate::utils::task_manager::ShutdownToken;
+
+/// Switches a subsystem over to a reloaded configuration; prepared up front, so it cannot fail.
+pub type ApplyConfig = Box<dyn FnOnce() + Send>;
+
+/// Implemented by subsystems that reconfigure themselves when the configuration is reloaded.
+pub trait ConfigListener: Send + Sync {
+    /// Builds everything the subsystem needs to move from `previous` to `config` without
+    /// applying any of it, and returns the step that does, or `None` if nothing it uses changed.
+    /// An error from any listener rejects the reload and keeps the last good configuration.
+    fn prepare(self: Arc<Self>, previous: &Config, config: &Config) -> BotResult<Option<ApplyConfig>>;
+}
+
+/// Holds the current configuration and replaces it when the files it was loaded from change.
+/// A new configuration is only swapped in once it loads and validates, so an invalid edit
+/// leaves the last good one in place.
+pub struct ConfigWatcher {
+    current: ArcSwap<Config>,
+    loader: ConfigLoader,
+    modified: Mutex<Vec<(PathBuf, Option<SystemTime>)>>,
+    listeners: RwLock<Vec<Arc<dyn ConfigListener>>>,
+    // Held for a whole reload, so listeners never prepare against a configuration that another
+    // reload is about to replace.
+    reloading: Mutex<()>,
+}
+
+impl ConfigWatcher {
+    pub fn new(loader: ConfigLoader) -> BotResult<Self> {
+        let config = loader.load()?;
+        Ok(Self {
+            current: ArcSwap::from_pointee(config),
+            modified: Mutex::new(modification_times(&loader)),
+            loader,
+            listeners: RwLock::new(Vec::new()),
+            reloading: Mutex::new(()),
+        })
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.current.load_full()
+    }
+
+    pub fn subscribe(&self, listener: Arc<dyn ConfigListener>) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Loads the configuration again and swaps it in once every listener has accepted it.
+    /// Returns whether anything changed.
+    pub fn reload(&self) -> BotResult<bool> {
+        let _reloading = self.reloading.lock().unwrap();
+        *self.modified.lock().unwrap() = modification_times(&self.loader);
+        let config = Arc::new(self.loader.load()?);
+        let previous = self.current();
+        if config.as_ref() == previous.as_ref() {
+            return Ok(false);
+        }
+
+        let listeners = self.listeners.read().unwrap().clone();
+        let changes = listeners.into_iter()
+            .map(|listener| listener.prepare(&previous, &config))
+            .collect::<BotResult<Vec<_>>>()?;
+
+        let restart_required = restart_required(&previous, &config);
+        if !restart_required.is_empty() {
+            log::warn!(
+                "Changes to {} take effect after a restart",
+                restart_required.join(", ")
+            );
+        }
+        self.current.store(Arc::clone(&config));
+        for apply in changes.into_iter().flatten() {
+            apply();
+        }
+        log::info!("Reloaded configuration from {}", config.sources.join(", "));
+        Ok(true)
+    }
+
+    fn changed_on_disk(&self) -> bool {
+        *self.modified.lock().unwrap() != modification_times(&self.loader)
+    }
+
+    /// Polls the configuration files every `bot.config_reload_secs` and reloads them on change.
+    pub async fn watch(self: Arc<Self>, shutdown: ShutdownToken) -> BotResult<()> {
+        loop {
+            let interval = self.current().bot.config_reload_secs.max(1);
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+            if !self.changed_on_disk() {
+                continue;
+            }
+            if let Err(why) = self.reload() {
+                log::error!("Rejected the changed configuration, keeping the last good one: {}", why);
+            }
+        }
+    }
+}
+
+fn modification_times(loader: &ConfigLoader) -> Vec<(PathBuf, Option<SystemTime>)> {
+    loader.paths()
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+// Sections that are only read while the bot starts up.
+fn restart_required(previous: &Config, config: &Config) -> Vec<&'static str> {
+    let mut sections = Vec::new();
+    if previous.bot.name != config.bot.name {
+        sections.push("bot.name");
+    }
+    if previous.bot.admin_guild_id != config.bot.admin_guild_id {
+        sections.push("bot.admin_guild_id");
+    }
+    if previous.database != config.database {
+        sections.push("[database]");
+    }
+    if previous.discord != config.discord {
+        sections.push("[discord]");
+    }
+    if previous.logging != config.logging {
+        sections.push("[logging]");
+    }
+    if previous.metrics != config.metrics {
+        sections.push("[metrics]");
+    }
+    if previous.backup != config.backup {
+        sections.push("[backup]");
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use super::*;
+    use crate::bot::error::BotError;
+    use crate::testing::{temp_dir, write_config};
+
+    struct Listener {
+        fails: bool,
+        applied: AtomicBool,
+    }
+
+    impl Listener {
+        fn new(fails: bool) -> Arc<Self> {
+            Arc::new(Self { fails, applied: AtomicBool::new(false) })
+        }
+    }
+
+    impl ConfigListener for Listener {
+        fn prepare(self: Arc<Self>, _previous: &Config, _config: &Config) -> BotResult<Option<ApplyConfig>> {
+            if self.fails {
+                return Err(BotError::Config("rejected".to_string()));
+            }
+            Ok(Some(Box::new(move || self.applied.store(true, Ordering::SeqCst))))
+        }
+    }
+
+    fn watcher(directory: &std::path::Path) -> ConfigWatcher {
+        let path = write_config(directory, "");
+        ConfigWatcher::new(ConfigLoader::from_vars(HashMap::new()).with_path(path)).unwrap()
+    }
+
+    #[test]
+    fn reload_applies_a_valid_change() {
+        let directory = temp_dir();
+        let watcher = watcher(&directory);
+        let listener = Listener::new(false);
+        watcher.subscribe(Arc::clone(&listener) as Arc<dyn ConfigListener>);
+
+        assert!(!watcher.reload().unwrap());
+        write_config(&directory, "[bot]\nowners = [1]");
+        assert!(watcher.reload().unwrap());
+        assert_eq!(watcher.current().bot.owners, vec![1]);
+        assert!(listener.applied.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn missing_language_file_keeps_the_last_good_config() {
+        let directory = temp_dir();
+        let watcher = watcher(&directory);
+        let listener = Listener::new(false);
+        watcher.subscribe(Arc::clone(&listener) as Arc<dyn ConfigListener>);
+
+        write_config(&directory, "[bot]\ndefault_language = \"xx\"");
+        assert!(watcher.reload().is_err());
+        assert_eq!(watcher.current().bot.default_language, "en");
+        assert!(!listener.applied.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn a_failing_listener_rejects_the_whole_reload() {
+        let directory = temp_dir();
+        let watcher = watcher(&directory);
+        let accepting = Listener::new(false);
+        watcher.subscribe(Arc::clone(&accepting) as Arc<dyn ConfigListener>);
+        watcher.subscribe(Listener::new(true) as Arc<dyn ConfigListener>);
+
+        write_config(&directory, "[bot]\nowners = [1]");
+        assert!(watcher.reload().is_err());
+        assert!(watcher.current().bot.owners.is_empty());
+        assert!(!accepting.applied.load(Ordering::SeqCst));
+    }
+
+    #[derive(Default)]
+    struct SlowListener {
+        preparing: AtomicBool,
+        overlapped: AtomicBool,
+        applied: AtomicUsize,
+    }
+
+    impl ConfigListener for SlowListener {
+        fn prepare(self: Arc<Self>, _previous: &Config, _config: &Config) -> BotResult<Option<ApplyConfig>> {
+            if self.preparing.swap(true, Ordering::SeqCst) {
+                self.overlapped.store(true, Ordering::SeqCst);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+            Ok(Some(Box::new(move || {
+                self.applied.fetch_add(1, Ordering::SeqCst);
+                self.preparing.store(false, Ordering::SeqCst);
+            })))
+        }
+    }
+
+    #[test]
+    fn concurrent_reloads_run_one_at_a_time() {
+        let directory = temp_dir();
+        let watcher = watcher(&directory);
+        let listener = Arc::new(SlowListener::default());
+        watcher.subscribe(Arc::clone(&listener) as Arc<dyn ConfigListener>);
+
+        write_config(&directory, "[bot]\nowners = [1]");
+        let changed: Vec<bool> = std::thread::scope(|scope| {
+            let reloads: Vec<_> = (0..4).map(|_| scope.spawn(|| watcher.reload().unwrap())).collect();
+            reloads.into_iter().map(|reload| reload.join().unwrap()).collect()
+        });
+
+        // The first reload applies the change; the others find nothing left to do.
+        assert_eq!(changed.iter().filter(|&&changed| changed).count(), 1);
+        assert_eq!(listener.applied.load(Ordering::SeqCst), 1);
+        assert!(!listener.overlapped.load(Ordering::SeqCst));
+    }
+}
@@ -1,20 +1,81 @@
+use sqlx::migrate::{Migrate, Migrator};
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::str::FromStr;
 use std::sync::Arc;
 use crate::bot::error::{BotResult, BotError};
+use crate::config::DatabaseConfig;
 
 pub mod models;
+pub mod repositories;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseBackend {
+    Postgres,
+    Sqlite,
+}
+
+pub enum DatabasePool {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+/// Runs the same query code against whichever pool the database was opened with.
+#[macro_export]
+macro_rules! with_pool {
+    ($database:expr, |$pool:ident| $body:expr) => {
+        match $database.pool() {
+            $crate::database::DatabasePool::Postgres($pool) => $body,
+            $crate::database::DatabasePool::Sqlite($pool) => $body,
+        }
+    };
+}
 
 pub struct Database {
-    pool: Arc<PgPool>,
+    pool: Arc<DatabasePool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> BotResult<Self> {
-        let pool = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(database_url)
-            .await
-            .map_err(|e| BotError::Database(e.to_string()))?;
+    pub async fn new(config: &DatabaseConfig) -> BotResult<Self> {
+        let pool = match DatabaseBackend::from_url(&config.url)? {
+            DatabaseBackend::Postgres => {
+                let pool = PgPoolOptions::new()
+                    .max_connections(config.max_connections)
+                    .connect(&config.url)
+                    .await?;
+                DatabasePool::Postgres(pool)
+            }
+            DatabaseBackend::Sqlite => {
+                let options = SqliteConnectOptions::from_str(&config.url)?
+                    .create_if_missing(true)
+                    .foreign_keys(true);
+
+                // Every connection to an in-memory database gets its own empty database,
+                // so keep exactly one connection alive for the lifetime of the pool.
+                let pool = if config.url.contains(":memory:") {
+                    SqlitePoolOptions::new()
+                        .max_connections(1)
+                        .min_connections(1)
+                        .idle_timeout(None)
+                        .max_lifetime(None)
+                        .connect_with(options)
+                        .await?
+                } else {
+                    SqlitePoolOptions::new()
+                        .max_connections(config.max_connections)
+                        .connect_with(options)
+                        .await?
+                };
+                DatabasePool::Sqlite(pool)
+            }
+        };
 
         Ok(Self {
             pool: Arc::new(pool),
@@ -22,23 +83,81 @@ impl Database {
     }
 
     pub async fn execute_query(&self, query: &str) -> BotResult<()> {
-        sqlx::query(query)
-            .execute(&*self.pool)
-            .await
-            .map_err(|e| BotError::Database(e.to_string()))?;
+        with_pool!(self, |pool| sqlx::query(query).execute(pool).await)?;
         Ok(())
     }
 
+    fn migrator(&self) -> Migrator {
+        match self.backend() {
+            DatabaseBackend::Postgres => sqlx::migrate!("./src/migrations/postgres"),
+            DatabaseBackend::Sqlite => sqlx::migrate!("./src/migrations/sqlite"),
+        }
+    }
+
     pub async fn run_migrations(&self) -> BotResult<()> {
-        sqlx::migrate!("./migrations")
-            .run(&*self.pool)
-            .await
-            .map_err(|e| BotError::Database(format!("Migration error: {}", e)))?;
+        let migrator = self.migrator();
+        with_pool!(self, |pool| migrator.run(pool).await)?;
         Ok(())
     }
 
-    pub fn get_pool(&self) -> Arc<PgPool> {
-        Arc::clone(&self.pool)
+    /// Reverts the `steps` most recently applied migrations and returns their versions.
+    pub async fn undo_migrations(&self, steps: usize) -> BotResult<Vec<i64>> {
+        let mut applied = self.applied_migrations().await?;
+        applied.sort_unstable_by(|a, b| b.cmp(a));
+        // Everything newer than the target version is reverted.
+        let target = applied.get(steps).copied().unwrap_or(0);
+        let migrator = self.migrator();
+        with_pool!(self, |pool| migrator.undo(pool, target).await)?;
+        Ok(applied.into_iter().take(steps).collect())
+    }
+
+    pub async fn migration_status(&self) -> BotResult<Vec<MigrationStatus>> {
+        let applied = self.applied_migrations().await?;
+        Ok(self.migrator()
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                applied: applied.contains(&migration.version),
+            })
+            .collect())
+    }
+
+    async fn applied_migrations(&self) -> BotResult<Vec<i64>> {
+        let applied = with_pool!(self, |pool| {
+            let mut connection = pool.acquire().await?;
+            connection.ensure_migrations_table().await?;
+            connection.list_applied_migrations().await?
+        });
+        Ok(applied.into_iter().map(|migration| migration.version).collect())
+    }
+
+    pub fn backend(&self) -> DatabaseBackend {
+        match self.pool() {
+            DatabasePool::Postgres(_) => DatabaseBackend::Postgres,
+            DatabasePool::Sqlite(_) => DatabaseBackend::Sqlite,
+        }
+    }
+
+    pub fn pool(&self) -> &DatabasePool {
+        &self.pool
+    }
+
+    pub async fn close(&self) {
+        with_pool!(self, |pool| pool.close().await);
+    }
+}
+
+impl DatabaseBackend {
+    pub fn from_url(url: &str) -> BotResult<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            Ok(DatabaseBackend::Postgres)
+        } else if url.starts_with("sqlite:") {
+            Ok(DatabaseBackend::Sqlite)
+        } else {
+            Err(BotError::Config("Unsupported database URL, expected postgres:// or sqlite:".to_string()))
+        }
     }
 }
 
@@ -48,4 +167,41 @@ impl Clone for Database {
             pool: Arc::clone(&self.pool),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn memory_database() -> Database {
+        Database::new(&DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            max_connections: 1,
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrations_apply_and_revert_on_sqlite() {
+        let database = memory_database().await;
+        assert!(database.migration_status().await.unwrap().iter().all(|migration| !migration.applied));
+
+        database.run_migrations().await.unwrap();
+        let status = database.migration_status().await.unwrap();
+        assert_eq!(status.len(), 5);
+        assert!(status.iter().all(|migration| migration.applied));
+
+        assert_eq!(database.undo_migrations(2).await.unwrap(), vec![5, 4]);
+        let applied: Vec<i64> = database.migration_status().await.unwrap()
+            .into_iter()
+            .filter(|migration| migration.applied)
+            .map(|migration| migration.version)
+            .collect();
+        assert_eq!(applied, vec![1, 2, 3]);
+
+        // Reverted migrations can be applied again.
+        database.run_migrations().await.unwrap();
+        assert!(database.migration_status().await.unwrap().iter().all(|migration| migration.applied));
+    }
+}
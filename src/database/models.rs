@@ -1,40 +1,45 @@
 use sqlx::FromRow;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-#[derive(FromRow, Debug)]
+#[derive(FromRow, Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: i64,
     pub discord_id: i64,
     pub username: String,
     pub joined_at: DateTime<Utc>,
+    pub experience: i32,
+    pub level: i32,
 }
 
-impl User {
-    pub async fn create(pool: &sqlx::PgPool, discord_id: i64, username: &str) -> Result<Self, sqlx::Error> {
-        let user = sqlx::query_as!(
-            User,
-            "INSERT INTO users (discord_id, username, joined_at) 
-             VALUES ($1, $2, $3) 
-             RETURNING id, discord_id, username, joined_at",
-            discord_id,
-            username,
-            Utc::now()
-        )
-        .fetch_one(pool)
-        .await?;
-
-        Ok(user)
-    }
+#[derive(FromRow, Debug, Clone, Serialize, Deserialize)]
+pub struct GuildSetting {
+    pub guild_id: i64,
+    pub name: String,
+    pub value: String,
+    pub updated_at: DateTime<Utc>,
+}
 
-    pub async fn get_by_discord_id(pool: &sqlx::PgPool, discord_id: i64) -> Result<Option<Self>, sqlx::Error> {
-        let user = sqlx::query_as!(
-            User,
-            "SELECT * FROM users WHERE discord_id = $1",
-            discord_id
-        )
-        .fetch_optional(pool)
-        .await?;
+#[derive(FromRow, Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub user_id: i64,
+    pub action: String,
+    pub details: String,
+    pub succeeded: bool,
+    pub created_at: DateTime<Utc>,
+}
 
-        Ok(user)
-    }
-}
\ No newline at end of file
+#[derive(FromRow, Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub name: String,
+    pub schedule: String,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_status: Option<String>,
+    pub last_error: Option<String>,
+    pub missed_runs: i64,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub lock_token: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serenity::model::id::GuildId;
+use tokio::sync::RwLock;
+use crate::bot::error::{BotResult, BotError};
+use crate::database::models::{AuditEntry, GuildSetting, ScheduledJob, User};
+use crate::database::repositories::{ArchiveRepository, AuditLogRepository, GuildSettingsRepository, ScheduledJobRepository, UserRepository};
+
+pub struct InMemoryUserRepository {
+    users: RwLock<Vec<User>>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self {
+            users: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn create(&self, discord_id: i64, username: &str) -> BotResult<User> {
+        let mut users = self.users.write().await;
+        if users.iter().any(|u| u.discord_id == discord_id) {
+            return Err(BotError::Internal(format!("User with discord id {} already exists", discord_id)));
+        }
+
+        let user = User {
+            id: users.iter().map(|u| u.id).max().unwrap_or(0) + 1,
+            discord_id,
+            username: username.to_string(),
+            joined_at: Utc::now(),
+            experience: 0,
+            level: 1,
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn get_by_discord_id(&self, discord_id: i64) -> BotResult<Option<User>> {
+        let users = self.users.read().await;
+        Ok(users.iter().find(|u| u.discord_id == discord_id).cloned())
+    }
+
+    async fn get_all(&self) -> BotResult<Vec<User>> {
+        Ok(self.users.read().await.clone())
+    }
+}
+
+pub struct InMemoryGuildSettingsRepository {
+    settings: RwLock<HashMap<(GuildId, String), GuildSetting>>,
+}
+
+impl InMemoryGuildSettingsRepository {
+    pub fn new() -> Self {
+        Self {
+            settings: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl GuildSettingsRepository for InMemoryGuildSettingsRepository {
+    async fn get_all(&self, guild_id: GuildId) -> BotResult<HashMap<String, String>> {
+        let settings = self.settings.read().await;
+        Ok(settings.iter()
+            .filter(|((id, _), _)| *id == guild_id)
+            .map(|((_, name), setting)| (name.clone(), setting.value.clone()))
+            .collect())
+    }
+
+    async fn set(&self, guild_id: GuildId, name: &str, value: &str) -> BotResult<()> {
+        let mut settings = self.settings.write().await;
+        settings.insert((guild_id, name.to_string()), GuildSetting {
+            guild_id: guild_id.0 as i64,
+            name: name.to_string(),
+            value: value.to_string(),
+            updated_at: Utc::now(),
+        });
+        Ok(())
+    }
+
+    async fn remove(&self, guild_id: GuildId, name: &str) -> BotResult<()> {
+        let mut settings = self.settings.write().await;
+        settings.remove(&(guild_id, name.to_string()));
+        Ok(())
+    }
+
+    async fn clear(&self, guild_id: GuildId) -> BotResult<()> {
+        let mut settings = self.settings.write().await;
+        settings.retain(|(id, _), _| *id != guild_id);
+        Ok(())
+    }
+
+    async fn export(&self) -> BotResult<Vec<GuildSetting>> {
+        let settings = self.settings.read().await;
+        let mut exported: Vec<GuildSetting> = settings.values().cloned().collect();
+        exported.sort_by(|a, b| (a.guild_id, &a.name).cmp(&(b.guild_id, &b.name)));
+        Ok(exported)
+    }
+}
+
+pub struct InMemoryArchiveRepository {
+    users: Arc<InMemoryUserRepository>,
+    guild_settings: Arc<InMemoryGuildSettingsRepository>,
+}
+
+impl InMemoryArchiveRepository {
+    pub fn new(users: Arc<InMemoryUserRepository>, guild_settings: Arc<InMemoryGuildSettingsRepository>) -> Self {
+        Self { users, guild_settings }
+    }
+}
+
+#[async_trait]
+impl ArchiveRepository for InMemoryArchiveRepository {
+    async fn replace_all(&self, users: &[User], settings: &[GuildSetting]) -> BotResult<()> {
+        // Both locks are taken first, so nobody sees the users replaced but not the settings.
+        let mut stored_users = self.users.users.write().await;
+        let mut stored_settings = self.guild_settings.settings.write().await;
+
+        *stored_users = users.to_vec();
+        stored_users.sort_by_key(|u| u.id);
+        stored_settings.clear();
+        for setting in settings {
+            stored_settings.insert((GuildId(setting.guild_id as u64), setting.name.clone()), setting.clone());
+        }
+        Ok(())
+    }
+}
+
+pub struct InMemoryAuditLogRepository {
+    entries: RwLock<Vec<AuditEntry>>,
+}
+
+impl InMemoryAuditLogRepository {
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditLogRepository for InMemoryAuditLogRepository {
+    async fn record(&self, user_id: i64, action: &str, details: &str, succeeded: bool) -> BotResult<AuditEntry> {
+        let mut entries = self.entries.write().await;
+        let entry = AuditEntry {
+            id: entries.last().map_or(0, |e| e.id) + 1,
+            user_id,
+            action: action.to_string(),
+            details: details.to_string(),
+            succeeded,
+            created_at: Utc::now(),
+        };
+        entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    async fn recent(&self, limit: i64) -> BotResult<Vec<AuditEntry>> {
+        let entries = self.entries.read().await;
+        Ok(entries.iter().rev().take(limit.max(0) as usize).cloned().collect())
+    }
+}
+
+pub struct InMemoryScheduledJobRepository {
+    jobs: RwLock<HashMap<String, ScheduledJob>>,
+}
+
+impl InMemoryScheduledJobRepository {
+    pub fn new() -> Self {
+        Self {
+            jobs: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ScheduledJobRepository for InMemoryScheduledJobRepository {
+    async fn get(&self, name: &str) -> BotResult<Option<ScheduledJob>> {
+        Ok(self.jobs.read().await.get(name).cloned())
+    }
+
+    async fn list(&self) -> BotResult<Vec<ScheduledJob>> {
+        let jobs = self.jobs.read().await;
+        let mut listed: Vec<ScheduledJob> = jobs.values().cloned().collect();
+        listed.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(listed)
+    }
+
+    async fn upsert(&self, name: &str, schedule: &str, next_run_at: Option<DateTime<Utc>>, missed_runs: i64) -> BotResult<()> {
+        let mut jobs = self.jobs.write().await;
+        let job = jobs.entry(name.to_string()).or_insert_with(|| ScheduledJob {
+            name: name.to_string(),
+            schedule: schedule.to_string(),
+            next_run_at: None,
+            last_run_at: None,
+            last_status: None,
+            last_error: None,
+            missed_runs: 0,
+            locked_until: None,
+            lock_token: None,
+            created_at: Utc::now(),
+        });
+        job.schedule = schedule.to_string();
+        job.next_run_at = next_run_at;
+        job.missed_runs = missed_runs;
+        Ok(())
+    }
+
+    async fn try_lock(&self, name: &str, token: &str, now: DateTime<Utc>, locked_until: DateTime<Utc>, next_run_at: Option<DateTime<Utc>>) -> BotResult<bool> {
+        let mut jobs = self.jobs.write().await;
+        match jobs.get_mut(name) {
+            Some(job) if job.locked_until.map_or(true, |until| until <= now) => {
+                job.locked_until = Some(locked_until);
+                job.lock_token = Some(token.to_string());
+                job.next_run_at = next_run_at;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn complete(&self, name: &str, token: &str, finished_at: DateTime<Utc>, status: &str, error: Option<&str>) -> BotResult<bool> {
+        let mut jobs = self.jobs.write().await;
+        match jobs.get_mut(name) {
+            Some(job) if job.lock_token.as_deref() == Some(token) => {
+                job.last_run_at = Some(finished_at);
+                job.last_status = Some(status.to_string());
+                job.last_error = error.map(str::to_string);
+                job.locked_until = None;
+                job.lock_token = None;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn remove(&self, name: &str) -> BotResult<()> {
+        self.jobs.write().await.remove(name);
+        Ok(())
+    }
+}
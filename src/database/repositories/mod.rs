@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serenity::model::id::GuildId;
+use crate::bot::error::BotResult;
+use crate::database::Database;
+use chrono::{DateTime, Utc};
+use crate::database::models::{AuditEntry, GuildSetting, ScheduledJob, User};
+
+pub mod memory;
+pub mod sql;
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn create(&self, discord_id: i64, username: &str) -> BotResult<User>;
+    async fn get_by_discord_id(&self, discord_id: i64) -> BotResult<Option<User>>;
+    async fn get_all(&self) -> BotResult<Vec<User>>;
+}
+
+#[async_trait]
+pub trait GuildSettingsRepository: Send + Sync {
+    async fn get_all(&self, guild_id: GuildId) -> BotResult<HashMap<String, String>>;
+    async fn set(&self, guild_id: GuildId, name: &str, value: &str) -> BotResult<()>;
+    async fn remove(&self, guild_id: GuildId, name: &str) -> BotResult<()>;
+    async fn clear(&self, guild_id: GuildId) -> BotResult<()>;
+    async fn export(&self) -> BotResult<Vec<GuildSetting>>;
+}
+
+#[async_trait]
+pub trait ArchiveRepository: Send + Sync {
+    /// Replaces all users and guild settings together; if anything fails, neither is changed.
+    async fn replace_all(&self, users: &[User], settings: &[GuildSetting]) -> BotResult<()>;
+}
+
+#[async_trait]
+pub trait AuditLogRepository: Send + Sync {
+    async fn record(&self, user_id: i64, action: &str, details: &str, succeeded: bool) -> BotResult<AuditEntry>;
+    async fn recent(&self, limit: i64) -> BotResult<Vec<AuditEntry>>;
+}
+
+#[async_trait]
+pub trait ScheduledJobRepository: Send + Sync {
+    async fn get(&self, name: &str) -> BotResult<Option<ScheduledJob>>;
+    async fn list(&self) -> BotResult<Vec<ScheduledJob>>;
+    /// Creates the job or updates its schedule, keeping the history of previous runs.
+    async fn upsert(&self, name: &str, schedule: &str, next_run_at: Option<DateTime<Utc>>, missed_runs: i64) -> BotResult<()>;
+    /// Locks the job for `token` until `locked_until` unless another run holds an unexpired lock.
+    async fn try_lock(&self, name: &str, token: &str, now: DateTime<Utc>, locked_until: DateTime<Utc>, next_run_at: Option<DateTime<Utc>>) -> BotResult<bool>;
+    /// Records the result and releases the lock if `token` still holds it. Returns false, and
+    /// changes nothing, when the lock expired and another run has taken it since.
+    async fn complete(&self, name: &str, token: &str, finished_at: DateTime<Utc>, status: &str, error: Option<&str>) -> BotResult<bool>;
+    async fn remove(&self, name: &str) -> BotResult<()>;
+}
+
+#[derive(Clone)]
+pub struct Repositories {
+    pub users: Arc<dyn UserRepository>,
+    pub guild_settings: Arc<dyn GuildSettingsRepository>,
+    pub audit_log: Arc<dyn AuditLogRepository>,
+    pub scheduled_jobs: Arc<dyn ScheduledJobRepository>,
+    pub archive: Arc<dyn ArchiveRepository>,
+}
+
+impl Repositories {
+    pub fn sql(database: Arc<Database>) -> Self {
+        Self {
+            users: Arc::new(sql::SqlUserRepository::new(Arc::clone(&database))),
+            guild_settings: Arc::new(sql::SqlGuildSettingsRepository::new(Arc::clone(&database))),
+            audit_log: Arc::new(sql::SqlAuditLogRepository::new(Arc::clone(&database))),
+            scheduled_jobs: Arc::new(sql::SqlScheduledJobRepository::new(Arc::clone(&database))),
+            archive: Arc::new(sql::SqlArchiveRepository::new(database)),
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        let users = Arc::new(memory::InMemoryUserRepository::new());
+        let guild_settings = Arc::new(memory::InMemoryGuildSettingsRepository::new());
+        Self {
+            archive: Arc::new(memory::InMemoryArchiveRepository::new(Arc::clone(&users), Arc::clone(&guild_settings))),
+            users,
+            guild_settings,
+            audit_log: Arc::new(memory::InMemoryAuditLogRepository::new()),
+            scheduled_jobs: Arc::new(memory::InMemoryScheduledJobRepository::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use chrono::Duration;
+    use super::*;
+
+    // Two processes share a job: the first run outlives its lock and the second takes it over.
+    pub(crate) async fn lock_is_released_only_by_its_holder(jobs: &dyn ScheduledJobRepository) {
+        let now = Utc::now();
+        jobs.upsert("backup", "every 1h", Some(now), 0).await.unwrap();
+        assert!(jobs.try_lock("backup", "first", now, now + Duration::minutes(5), None).await.unwrap());
+
+        let later = now + Duration::minutes(6);
+        assert!(jobs.try_lock("backup", "second", later, later + Duration::minutes(5), None).await.unwrap());
+        // The first run finishing late neither records its result nor releases the new lock.
+        assert!(!jobs.complete("backup", "first", later, "ok", None).await.unwrap());
+        let job = jobs.get("backup").await.unwrap().unwrap();
+        assert!(job.locked_until.is_some());
+        assert!(job.last_status.is_none());
+        assert!(!jobs.try_lock("backup", "third", later, later + Duration::minutes(5), None).await.unwrap());
+
+        assert!(jobs.complete("backup", "second", later, "ok", None).await.unwrap());
+        let job = jobs.get("backup").await.unwrap().unwrap();
+        assert!(job.locked_until.is_none());
+        assert_eq!(job.last_status.as_deref(), Some("ok"));
+    }
+
+    #[tokio::test]
+    async fn in_memory_lock_is_released_only_by_its_holder() {
+        lock_is_released_only_by_its_holder(Repositories::in_memory().scheduled_jobs.as_ref()).await;
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serenity::model::id::GuildId;
+use crate::bot::error::BotResult;
+use crate::database::{Database, DatabaseBackend};
+use crate::database::models::{AuditEntry, GuildSetting, ScheduledJob, User};
+use crate::database::repositories::{ArchiveRepository, AuditLogRepository, GuildSettingsRepository, ScheduledJobRepository, UserRepository};
+use crate::with_pool;
+
+pub struct SqlUserRepository {
+    database: Arc<Database>,
+}
+
+impl SqlUserRepository {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqlUserRepository {
+    async fn create(&self, discord_id: i64, username: &str) -> BotResult<User> {
+        let user = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, User>(
+                "INSERT INTO users (discord_id, username, joined_at)
+                 VALUES ($1, $2, $3)
+                 RETURNING id, discord_id, username, joined_at, experience, level",
+            )
+            .bind(discord_id)
+            .bind(username)
+            .bind(Utc::now())
+            .fetch_one(pool)
+            .await
+        })?;
+        Ok(user)
+    }
+
+    async fn get_by_discord_id(&self, discord_id: i64) -> BotResult<Option<User>> {
+        let user = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, User>(
+                "SELECT id, discord_id, username, joined_at, experience, level FROM users WHERE discord_id = $1",
+            )
+            .bind(discord_id)
+            .fetch_optional(pool)
+            .await
+        })?;
+        Ok(user)
+    }
+
+    async fn get_all(&self) -> BotResult<Vec<User>> {
+        let users = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, User>(
+                "SELECT id, discord_id, username, joined_at, experience, level FROM users ORDER BY id",
+            )
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(users)
+    }
+}
+
+pub struct SqlGuildSettingsRepository {
+    database: Arc<Database>,
+}
+
+impl SqlGuildSettingsRepository {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl GuildSettingsRepository for SqlGuildSettingsRepository {
+    async fn get_all(&self, guild_id: GuildId) -> BotResult<HashMap<String, String>> {
+        let rows: Vec<(String, String)> = with_pool!(self.database, |pool| {
+            sqlx::query_as("SELECT name, value FROM guild_settings WHERE guild_id = $1")
+                .bind(guild_id.0 as i64)
+                .fetch_all(pool)
+                .await
+        })?;
+        Ok(rows.into_iter().collect())
+    }
+
+    async fn set(&self, guild_id: GuildId, name: &str, value: &str) -> BotResult<()> {
+        with_pool!(self.database, |pool| {
+            sqlx::query(
+                "INSERT INTO guild_settings (guild_id, name, value, updated_at)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (guild_id, name) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            )
+            .bind(guild_id.0 as i64)
+            .bind(name)
+            .bind(value)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+        })?;
+        Ok(())
+    }
+
+    async fn remove(&self, guild_id: GuildId, name: &str) -> BotResult<()> {
+        with_pool!(self.database, |pool| {
+            sqlx::query("DELETE FROM guild_settings WHERE guild_id = $1 AND name = $2")
+                .bind(guild_id.0 as i64)
+                .bind(name)
+                .execute(pool)
+                .await
+        })?;
+        Ok(())
+    }
+
+    async fn clear(&self, guild_id: GuildId) -> BotResult<()> {
+        with_pool!(self.database, |pool| {
+            sqlx::query("DELETE FROM guild_settings WHERE guild_id = $1")
+                .bind(guild_id.0 as i64)
+                .execute(pool)
+                .await
+        })?;
+        Ok(())
+    }
+
+    async fn export(&self) -> BotResult<Vec<GuildSetting>> {
+        let settings = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, GuildSetting>(
+                "SELECT guild_id, name, value, updated_at FROM guild_settings ORDER BY guild_id, name",
+            )
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(settings)
+    }
+}
+
+pub struct SqlAuditLogRepository {
+    database: Arc<Database>,
+}
+
+impl SqlAuditLogRepository {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl AuditLogRepository for SqlAuditLogRepository {
+    async fn record(&self, user_id: i64, action: &str, details: &str, succeeded: bool) -> BotResult<AuditEntry> {
+        let entry = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, AuditEntry>(
+                "INSERT INTO audit_log (user_id, action, details, succeeded, created_at)
+                 VALUES ($1, $2, $3, $4, $5)
+                 RETURNING id, user_id, action, details, succeeded, created_at",
+            )
+            .bind(user_id)
+            .bind(action)
+            .bind(details)
+            .bind(succeeded)
+            .bind(Utc::now())
+            .fetch_one(pool)
+            .await
+        })?;
+        Ok(entry)
+    }
+
+    async fn recent(&self, limit: i64) -> BotResult<Vec<AuditEntry>> {
+        let entries = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, AuditEntry>(
+                "SELECT id, user_id, action, details, succeeded, created_at FROM audit_log ORDER BY id DESC LIMIT $1",
+            )
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(entries)
+    }
+}
+
+const SCHEDULED_JOB_COLUMNS: &str = "name, schedule, next_run_at, last_run_at, last_status, last_error, missed_runs, locked_until, lock_token, created_at";
+
+pub struct SqlScheduledJobRepository {
+    database: Arc<Database>,
+}
+
+impl SqlScheduledJobRepository {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl ScheduledJobRepository for SqlScheduledJobRepository {
+    async fn get(&self, name: &str) -> BotResult<Option<ScheduledJob>> {
+        let query = format!("SELECT {} FROM scheduled_jobs WHERE name = $1", SCHEDULED_JOB_COLUMNS);
+        let job = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, ScheduledJob>(&query)
+                .bind(name)
+                .fetch_optional(pool)
+                .await
+        })?;
+        Ok(job)
+    }
+
+    async fn list(&self) -> BotResult<Vec<ScheduledJob>> {
+        let query = format!("SELECT {} FROM scheduled_jobs ORDER BY name", SCHEDULED_JOB_COLUMNS);
+        let jobs = with_pool!(self.database, |pool| {
+            sqlx::query_as::<_, ScheduledJob>(&query)
+                .fetch_all(pool)
+                .await
+        })?;
+        Ok(jobs)
+    }
+
+    async fn upsert(&self, name: &str, schedule: &str, next_run_at: Option<DateTime<Utc>>, missed_runs: i64) -> BotResult<()> {
+        with_pool!(self.database, |pool| {
+            sqlx::query(
+                "INSERT INTO scheduled_jobs (name, schedule, next_run_at, missed_runs, created_at)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (name) DO UPDATE SET schedule = excluded.schedule, next_run_at = excluded.next_run_at, missed_runs = excluded.missed_runs",
+            )
+            .bind(name)
+            .bind(schedule)
+            .bind(next_run_at)
+            .bind(missed_runs)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+        })?;
+        Ok(())
+    }
+
+    async fn try_lock(&self, name: &str, token: &str, now: DateTime<Utc>, locked_until: DateTime<Utc>, next_run_at: Option<DateTime<Utc>>) -> BotResult<bool> {
+        let result = with_pool!(self.database, |pool| {
+            sqlx::query(
+                "UPDATE scheduled_jobs SET locked_until = $2, next_run_at = $3, lock_token = $5
+                 WHERE name = $1 AND (locked_until IS NULL OR locked_until <= $4)",
+            )
+            .bind(name)
+            .bind(locked_until)
+            .bind(next_run_at)
+            .bind(now)
+            .bind(token)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected())
+        })?;
+        Ok(result == 1)
+    }
+
+    async fn complete(&self, name: &str, token: &str, finished_at: DateTime<Utc>, status: &str, error: Option<&str>) -> BotResult<bool> {
+        let result = with_pool!(self.database, |pool| {
+            sqlx::query(
+                "UPDATE scheduled_jobs SET last_run_at = $2, last_status = $3, last_error = $4, locked_until = NULL, lock_token = NULL
+                 WHERE name = $1 AND lock_token = $5",
+            )
+            .bind(name)
+            .bind(finished_at)
+            .bind(status)
+            .bind(error)
+            .bind(token)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected())
+        })?;
+        Ok(result == 1)
+    }
+
+    async fn remove(&self, name: &str) -> BotResult<()> {
+        with_pool!(self.database, |pool| {
+            sqlx::query("DELETE FROM scheduled_jobs WHERE name = $1")
+                .bind(name)
+                .execute(pool)
+                .await
+        })?;
+        Ok(())
+    }
+}
+
+pub struct SqlArchiveRepository {
+    database: Arc<Database>,
+}
+
+impl SqlArchiveRepository {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl ArchiveRepository for SqlArchiveRepository {
+    async fn replace_all(&self, users: &[User], settings: &[GuildSetting]) -> BotResult<()> {
+        with_pool!(self.database, |pool| {
+            let mut tx = pool.begin().await?;
+
+            sqlx::query("DELETE FROM users").execute(&mut tx).await?;
+            for user in users {
+                sqlx::query(
+                    "INSERT INTO users (id, discord_id, username, joined_at, experience, level)
+                     VALUES ($1, $2, $3, $4, $5, $6)",
+                )
+                .bind(user.id)
+                .bind(user.discord_id)
+                .bind(&user.username)
+                .bind(user.joined_at)
+                .bind(user.experience)
+                .bind(user.level)
+                .execute(&mut tx)
+                .await?;
+            }
+
+            // SQLite bumps its AUTOINCREMENT counter on explicit ids, Postgres needs its sequence moved.
+            if self.database.backend() == DatabaseBackend::Postgres {
+                sqlx::query("SELECT setval(pg_get_serial_sequence('users', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM users")
+                    .execute(&mut tx)
+                    .await?;
+            }
+
+            sqlx::query("DELETE FROM guild_settings").execute(&mut tx).await?;
+            for setting in settings {
+                sqlx::query(
+                    "INSERT INTO guild_settings (guild_id, name, value, updated_at) VALUES ($1, $2, $3, $4)",
+                )
+                .bind(setting.guild_id)
+                .bind(&setting.name)
+                .bind(&setting.value)
+                .bind(setting.updated_at)
+                .execute(&mut tx)
+                .await?;
+            }
+
+            tx.commit().await
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::config::DatabaseConfig;
+    use crate::database::repositories::Repositories;
+
+    async fn repositories() -> Repositories {
+        let database = Database::new(&DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            max_connections: 1,
+        })
+        .await
+        .unwrap();
+        database.run_migrations().await.unwrap();
+        Repositories::sql(Arc::new(database))
+    }
+
+    #[tokio::test]
+    async fn users_round_trip() {
+        let repositories = repositories().await;
+        let created = repositories.users.create(42, "alice").await.unwrap();
+        assert_eq!(created.level, 1);
+
+        let found = repositories.users.get_by_discord_id(42).await.unwrap().unwrap();
+        assert_eq!(found.id, created.id);
+        assert_eq!(found.username, "alice");
+        assert!(repositories.users.get_by_discord_id(7).await.unwrap().is_none());
+        // discord_id is unique.
+        assert!(repositories.users.create(42, "alice again").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn guild_settings_are_scoped_per_guild() {
+        let repositories = repositories().await;
+        let settings = &repositories.guild_settings;
+        settings.set(GuildId(1), "prefix", "!").await.unwrap();
+        settings.set(GuildId(1), "prefix", "?").await.unwrap();
+        settings.set(GuildId(1), "language", "de").await.unwrap();
+        settings.set(GuildId(2), "prefix", "$").await.unwrap();
+
+        let first = settings.get_all(GuildId(1)).await.unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first["prefix"], "?");
+
+        settings.remove(GuildId(1), "language").await.unwrap();
+        assert_eq!(settings.get_all(GuildId(1)).await.unwrap().len(), 1);
+        settings.clear(GuildId(1)).await.unwrap();
+        assert!(settings.get_all(GuildId(1)).await.unwrap().is_empty());
+        assert_eq!(settings.export().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn audit_log_lists_newest_first() {
+        let repositories = repositories().await;
+        repositories.audit_log.record(1, "plugin_load", "example", true).await.unwrap();
+        repositories.audit_log.record(1, "plugin_unload", "example", false).await.unwrap();
+
+        let recent = repositories.audit_log.recent(10).await.unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].action, "plugin_unload");
+        assert!(!recent[0].succeeded);
+        assert_eq!(repositories.audit_log.recent(1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn scheduled_job_lock_is_exclusive_until_completed() {
+        let repositories = repositories().await;
+        let jobs = &repositories.scheduled_jobs;
+        let now = Utc::now();
+        jobs.upsert("backup", "every 1h", Some(now), 0).await.unwrap();
+
+        assert!(jobs.try_lock("backup", "first", now, now + Duration::minutes(5), Some(now + Duration::hours(1))).await.unwrap());
+        assert!(!jobs.try_lock("backup", "second", now, now + Duration::minutes(5), None).await.unwrap());
+        // An expired lock can be taken over.
+        assert!(jobs.try_lock("backup", "second", now + Duration::minutes(6), now + Duration::minutes(10), None).await.unwrap());
+
+        assert!(jobs.complete("backup", "second", now, "failed", Some("disk full")).await.unwrap());
+        let job = jobs.get("backup").await.unwrap().unwrap();
+        assert_eq!(job.last_status.as_deref(), Some("failed"));
+        assert_eq!(job.last_error.as_deref(), Some("disk full"));
+        assert!(job.locked_until.is_none());
+
+        jobs.remove("backup").await.unwrap();
+        assert!(jobs.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn scheduled_job_lock_is_released_only_by_its_holder() {
+        let repositories = repositories().await;
+        crate::database::repositories::tests::lock_is_released_only_by_its_holder(repositories.scheduled_jobs.as_ref()).await;
+    }
+
+    #[tokio::test]
+    async fn archive_replace_is_all_or_nothing() {
+        let repositories = repositories().await;
+        let alice = repositories.users.create(1, "alice").await.unwrap();
+        repositories.guild_settings.set(GuildId(10), "prefix", "!").await.unwrap();
+
+        let setting = GuildSetting { guild_id: 20, name: "prefix".to_string(), value: "?".to_string(), updated_at: Utc::now() };
+        let bob = User { id: 7, discord_id: 2, username: "bob".to_string(), ..alice.clone() };
+        // The duplicate (guild_id, name) fails after the users were already replaced.
+        let result = repositories.archive.replace_all(&[bob.clone()], &[setting.clone(), setting.clone()]).await;
+        assert!(result.is_err());
+        assert_eq!(repositories.users.get_all().await.unwrap()[0].username, "alice");
+        assert_eq!(repositories.guild_settings.export().await.unwrap().len(), 1);
+
+        repositories.archive.replace_all(&[bob], &[setting]).await.unwrap();
+        assert_eq!(repositories.users.get_all().await.unwrap()[0].id, 7);
+        assert_eq!(repositories.guild_settings.get_all(GuildId(20)).await.unwrap()["prefix"], "?");
+        // New users continue after the restored ids.
+        assert!(repositories.users.create(3, "carol").await.unwrap().id > 7);
+    }
+}
@@ -1,10 +1,19 @@
 use std::collections::HashMap;
-use serde::Deserialize;
+use std::sync::Arc;
+use arc_swap::ArcSwap;
 use crate::bot::error::{BotResult, BotError};
+use crate::config::{ApplyConfig, Config, ConfigListener};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+const LANG_DIRECTORY: &str = "lang";
+const FALLBACK_LANGUAGE: &str = "en";
+
+pub fn language_path(lang: &str) -> PathBuf {
+    Path::new(LANG_DIRECTORY).join(format!("{}.toml", lang))
+}
+
+#[derive(Debug)]
 pub struct Lang {
     messages: HashMap<String, String>,
     fallback: Option<Box<Lang>>,
@@ -12,18 +21,23 @@ pub struct Lang {
 
 impl Lang {
     pub fn load(lang: &str) -> BotResult<Self> {
-        let lang_path = format!("lang/{}.toml", lang);
+        let lang_path = language_path(lang);
         let file_content = fs::read_to_string(&lang_path)
-            .map_err(|e| BotError::Config(format!("Failed to read language file {}: {}", lang_path, e)))?;
+            .map_err(|e| BotError::Config(format!("Failed to read language file {}: {}", lang_path.display(), e)))?;
         
-        let mut lang: Lang = toml::from_str(&file_content)
-            .map_err(|e| BotError::Config(format!("Failed to parse language file {}: {}", lang_path, e)))?;
+        let table: toml::Table = toml::from_str(&file_content)
+            .map_err(|e| BotError::Config(format!("Failed to parse language file {}: {}", lang_path.display(), e)))?;
+        let mut messages = HashMap::new();
+        flatten_messages("", table, &mut messages)
+            .map_err(|key| BotError::Config(format!("Language file {} has a non-text value for '{}'", lang_path.display(), key)))?;
 
-        if lang != "en" {
-            lang.fallback = Some(Box::new(Lang::load("en")?));
-        }
+        let fallback = if lang != FALLBACK_LANGUAGE {
+            Some(Box::new(Lang::load(FALLBACK_LANGUAGE)?))
+        } else {
+            None
+        };
 
-        Ok(lang)
+        Ok(Self { messages, fallback })
     }
 
     pub fn get(&self, key: &str) -> &str {
@@ -59,8 +73,24 @@ impl Lang {
     }
 }
 
+// Nested tables become dotted keys, so `list_title` under `[config]` is `config.list_title`.
+// Returns the key of the first value that isn't a string.
+fn flatten_messages(prefix: &str, table: toml::Table, messages: &mut HashMap<String, String>) -> Result<(), String> {
+    for (key, value) in table {
+        let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+        match value {
+            toml::Value::String(message) => {
+                messages.insert(key, message);
+            }
+            toml::Value::Table(table) => flatten_messages(&key, table, messages)?,
+            _ => return Err(key),
+        }
+    }
+    Ok(())
+}
+
 pub fn load_all_languages(config: &crate::config::Config) -> BotResult<HashMap<String, Lang>> {
-    let lang_dir = Path::new("lang");
+    let lang_dir = Path::new(LANG_DIRECTORY);
     let mut languages = HashMap::new();
 
     for entry in fs::read_dir(lang_dir)
@@ -80,4 +110,48 @@ pub fn load_all_languages(config: &crate::config::Config) -> BotResult<HashMap<S
     }
 
     Ok(languages)
-}
\ No newline at end of file
+}
+
+// The bot shares its language as an `ArcSwap`, so a new default language can be swapped in.
+impl ConfigListener for ArcSwap<Lang> {
+    fn prepare(self: Arc<Self>, previous: &Config, config: &Config) -> BotResult<Option<ApplyConfig>> {
+        if previous.bot.default_language == config.bot.default_language {
+            return Ok(None);
+        }
+        let lang = Arc::new(Lang::load(&config.bot.default_language)?);
+        let language = config.bot.default_language.clone();
+        Ok(Some(Box::new(move || {
+            self.store(lang);
+            log::info!("Switched the default language to '{}'", language);
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_language_file_resolves_section_keys() {
+        let lang = Lang::load("en").unwrap();
+        assert_eq!(lang.get("config.list_title"), "Server Settings");
+        for key in ["owner.title", "errors.rate_limit_retry", "errors.shutting_down", "errors.user_blocked", "errors.database"] {
+            assert!(lang.has_key(key), "missing {}", key);
+        }
+        // Unknown keys come back unchanged.
+        assert_eq!(lang.get("config.no_such_key"), "config.no_such_key");
+    }
+
+    #[test]
+    fn nested_tables_are_flattened() {
+        let table: toml::Table = toml::from_str("top = \"a\"\n[outer]\nkey = \"b\"\n[outer.inner]\nkey = \"c\"").unwrap();
+        let mut messages = HashMap::new();
+        flatten_messages("", table, &mut messages).unwrap();
+        assert_eq!(messages["top"], "a");
+        assert_eq!(messages["outer.key"], "b");
+        assert_eq!(messages["outer.inner.key"], "c");
+
+        let table: toml::Table = toml::from_str("[limits]\ncount = 3").unwrap();
+        assert_eq!(flatten_messages("", table, &mut HashMap::new()), Err("limits.count".to_string()));
+    }
+}
@@ -1,7 +1,9 @@
 use dotenv::dotenv;
 use serenity::prelude::*;
 use std::env;
+use std::error::Error;
 use std::sync::Arc;
+use arc_swap::ArcSwap;
 use tokio::sync::broadcast;
 
 mod bot;
@@ -14,16 +16,24 @@ mod plugins;
 mod security;
 mod telemetry;
 mod backup;
+mod cli;
+#[cfg(test)]
+mod testing;
 
 use bot::handler::Handler;
-use config::Config;
+use config::{ConfigListener, ConfigLoader, ConfigWatcher};
 use database::Database;
+use database::repositories::Repositories;
 use utils::logger;
 use utils::metrics::Metrics;
+use utils::prometheus::PrometheusExporter;
 use utils::cache::Cache;
-use utils::task_manager::TaskManager;
+use utils::task_manager::{RestartPolicy, TaskManager, TaskOptions};
 use utils::rate_limiter::RateLimiter;
 use utils::guild_data::GuildData;
+use utils::settings::SettingsRegistry;
+use utils::scheduler::{JobDefinition, Schedule, Scheduler};
+use utils::shutdown::ShutdownCoordinator;
 use lang::Lang;
 use utils::event_bus::EventBus;
 use plugins::PluginManager;
@@ -31,48 +41,120 @@ use security::SecurityManager;
 use telemetry::TelemetryManager;
 use backup::BackupManager;
 
-use crate::plugins::example_plugin::ExamplePlugin;
-use crate::telemetry::TelemetryManager;
+use cli::{Cli, CliCommand};
+
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
     dotenv().ok();
-    logger::init();
 
-    let config = Arc::new(Config::load()?);
-    let database = Arc::new(Database::new(&config.database.url).await?);
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(why) => {
+            eprintln!("{}\n\n{}", why, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(why) = execute(cli).await {
+        eprintln!("Error: {}", why);
+        std::process::exit(1);
+    }
+}
+
+async fn execute(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let loader = cli.config_loader();
+    match cli.command {
+        CliCommand::Run => run(loader).await,
+        CliCommand::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        CliCommand::CheckConfig { print } => Ok(cli::check_config(&loader, print)?),
+        CliCommand::RegisterCommands { guild_id, dry_run } => {
+            Ok(cli::register_commands(&loader.load()?, guild_id, dry_run).await?)
+        }
+        CliCommand::Migrate(action) => {
+            let database = Database::new(&loader.load_storage()?.database).await?;
+            let result = cli::migrate(&database, &action).await;
+            database.close().await;
+            Ok(result?)
+        }
+        command => {
+            let config = loader.load_storage()?;
+            let database = Arc::new(Database::new(&config.database).await?);
+            database.run_migrations().await?;
+            let backup_manager = BackupManager::new(&config.backup, Repositories::sql(Arc::clone(&database)))?;
+            let result = match &command {
+                CliCommand::ExportData { output } => cli::export_data(&backup_manager, output.as_deref()).await,
+                CliCommand::ImportData { input } => cli::import_data(&backup_manager, input).await,
+                CliCommand::RestoreBackup { target } => cli::restore_backup(&backup_manager, target).await,
+                _ => unreachable!("handled above"),
+            };
+            database.close().await;
+            Ok(result?)
+        }
+    }
+}
+
+async fn run(loader: ConfigLoader) -> Result<(), Box<dyn Error>> {
+    let config_watcher = Arc::new(ConfigWatcher::new(loader)?);
+    let config = config_watcher.current();
+    logger::init(&config.logging)?;
+    log::info!("Loaded configuration from {}", config.sources.join(", "));
+    log::debug!("Effective configuration:\n{}", config.redacted_toml()?);
+    let database = Arc::new(Database::new(&config.database).await?);
     database.run_migrations().await?;
+    let repositories = Repositories::sql(Arc::clone(&database));
     
-    let metrics = Arc::new(Metrics::new());
-    let cache = Arc::new(Cache::new(std::time::Duration::from_secs(300)));
-    let task_manager = Arc::new(TaskManager::new(5));
+    let metrics = Arc::new(Metrics::new().with_guild_label(config.metrics.guild_label));
+    let cache = Arc::new(
+        Cache::new(10_000)
+            .with_ttl(std::time::Duration::from_secs(300))
+            .with_metrics("default", Arc::clone(&metrics)),
+    );
+    let telemetry_manager = Arc::new(
+        TelemetryManager::new(&config.telemetry, &config.bot.name)?
+            .with_metrics(Arc::clone(&metrics)),
+    );
+    // The scheduler, config watcher, metrics exporter and telemetry flush hold a slot for the
+    // bot's whole lifetime, so leave room for scheduled jobs and plugin tasks.
+    let task_manager = Arc::new(TaskManager::new(8).with_telemetry(Arc::clone(&telemetry_manager)));
     let rate_limiter = Arc::new(RateLimiter::new());
-    let guild_data = Arc::new(GuildData::new());
-    let lang = Arc::new(Lang::load(&config.bot.default_language)?);
+    let lang = Arc::new(ArcSwap::from_pointee(Lang::load(&config.bot.default_language)?));
+    let settings_registry = Arc::new(SettingsRegistry::with_core_settings(&lang.load()));
+    let guild_data = Arc::new(GuildData::new(Arc::clone(&repositories.guild_settings), settings_registry));
 
     let (event_sender, _) = broadcast::channel(100);
-    let event_bus = Arc::new(EventBus::new(event_sender));
+    let event_bus = Arc::new(EventBus::new(event_sender, Arc::clone(&metrics)));
 
     let plugin_manager = Arc::new(PluginManager::new());
-    let security_manager = Arc::new(SecurityManager::new());
-    let telemetry_manager = Arc::new(TelemetryManager::new(&config.telemetry)?);
-    let backup_manager = Arc::new(BackupManager::new(&config.backup, Arc::clone(&database))?);
+    let security_manager = Arc::new(SecurityManager::new(Arc::clone(&rate_limiter)));
+    config_watcher.subscribe(Arc::clone(&lang) as Arc<dyn ConfigListener>);
+    config_watcher.subscribe(Arc::clone(&telemetry_manager) as Arc<dyn ConfigListener>);
+    config_watcher.subscribe(Arc::clone(&security_manager) as Arc<dyn ConfigListener>);
+    let backup_manager = Arc::new(BackupManager::new(&config.backup, repositories.clone())?);
+    let shutdown = Arc::new(ShutdownCoordinator::new());
+    let scheduler = Arc::new(Scheduler::new(
+        Arc::clone(&repositories.scheduled_jobs),
+        Arc::clone(&task_manager),
+        Arc::clone(&metrics),
+    ));
 
-    let telemetry_manager = Arc::new(TelemetryManager::new(&config.telemetry));
-    telemetry_manager.start_periodic_flush().await;
+    telemetry_manager.start_periodic_flush(&task_manager).await?;
 
-    plugin_manager.load_plugin(Box::new(ExamplePlugin)).await?;
+    for (name, factory) in plugins::builtin_plugins() {
+        plugin_manager.register_available(name, factory).await;
+    }
 
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
     let intents = GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT
         | GatewayIntents::GUILDS;
 
-    let mut client = Client::builder(&token, intents)
+    let mut client = Client::builder(&config.discord.token, intents)
         .event_handler(Handler::new(
-            Arc::clone(&config),
-            Arc::clone(&database),
+            Arc::clone(&config_watcher),
+            repositories.clone(),
             Arc::clone(&metrics),
             Arc::clone(&cache),
             Arc::clone(&task_manager),
@@ -84,36 +166,94 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             Arc::clone(&security_manager),
             Arc::clone(&telemetry_manager),
             Arc::clone(&backup_manager),
+            Arc::clone(&shutdown),
+            Arc::clone(&scheduler),
         ))
         .await
         .expect("Err creating client");
 
-    task_manager.spawn("metrics_reporter", {
-        let metrics = Arc::clone(&metrics);
-        async move {
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
-                metrics.report().await;
+    if config.metrics.enabled {
+        let exporter = Arc::new(PrometheusExporter::new(&config.metrics, Arc::clone(&metrics))?);
+        // Binding inside the task means a port that is briefly taken is retried with backoff.
+        task_manager.spawn_supervised("metrics_exporter", TaskOptions::restart(RestartPolicy::OnFailure), move |shutdown| {
+            let exporter = Arc::clone(&exporter);
+            async move {
+                let listener = exporter.bind().await?;
+                exporter.serve(listener, shutdown).await;
+                Ok(())
             }
-        }
-    }).await?;
+        }).await?;
+    }
 
-    task_manager.spawn("cache_cleaner", {
+    scheduler.register(JobDefinition::new("cache_cleaner", Schedule::every(std::time::Duration::from_secs(300)), {
         let cache = Arc::clone(&cache);
-        async move {
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(300)).await;
+        move || {
+            let cache = Arc::clone(&cache);
+            async move {
                 cache.cleanup().await;
+                Ok(())
+            }
+        }
+    })).await?;
+
+    scheduler.register(JobDefinition::new("rate_limiter_cleanup", Schedule::every(std::time::Duration::from_secs(600)), {
+        let rate_limiter = Arc::clone(&rate_limiter);
+        move || {
+            let removed = rate_limiter.clean_up();
+            async move {
+                if removed > 0 {
+                    log::debug!("Removed {} idle rate limit buckets", removed);
+                }
+                Ok(())
+            }
+        }
+    })).await?;
+
+    if config.backup.enabled {
+        scheduler.register(JobDefinition::new("backup", backup_manager.schedule()?, {
+            let backup_manager = Arc::clone(&backup_manager);
+            move || {
+                let backup_manager = Arc::clone(&backup_manager);
+                async move { backup_manager.run_scheduled_backup().await }
+            }
+        })).await?;
+    } else {
+        log::info!("Scheduled backups are disabled");
+    }
+
+    task_manager.spawn_supervised("scheduler", TaskOptions::restart(RestartPolicy::Always).max_restarts(None), {
+        let scheduler = Arc::clone(&scheduler);
+        move |shutdown| {
+            let scheduler = Arc::clone(&scheduler);
+            async move {
+                scheduler.run(shutdown).await;
+                Ok(())
             }
         }
     }).await?;
 
-    task_manager.spawn("backup_scheduler", {
-        let backup_manager = Arc::clone(&backup_manager);
+    if config.bot.config_reload_secs > 0 {
+        task_manager.spawn_supervised("config_watcher", TaskOptions::restart(RestartPolicy::Always).max_restarts(None), {
+            let config_watcher = Arc::clone(&config_watcher);
+            move |shutdown| Arc::clone(&config_watcher).watch(shutdown)
+        }).await?;
+    }
+
+    tokio::spawn({
+        let shutdown = Arc::clone(&shutdown);
+        let shard_manager = Arc::clone(&client.shard_manager);
+        let config_watcher = Arc::clone(&config_watcher);
         async move {
-            backup_manager.run_scheduled_backups().await;
+            utils::shutdown::wait_for_signal().await;
+            let shutdown_timeout = std::time::Duration::from_secs(config_watcher.current().bot.shutdown_timeout_secs);
+            log::info!("Shutting down, waiting for {} in-flight command(s)", shutdown.in_flight());
+            shutdown.begin_shutdown();
+            if !shutdown.drain(shutdown_timeout).await {
+                log::warn!("{} command(s) were still running after {:?}", shutdown.in_flight(), shutdown_timeout);
+            }
+            shard_manager.lock().await.shutdown_all().await;
         }
-    }).await?;
+    });
 
     log::info!("Starting bot...");
     telemetry_manager.log_event("bot_start").await?;
@@ -123,5 +263,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         telemetry_manager.log_error("client_error", &why.to_string()).await?;
     }
 
+    // The client also returns when the gateway fails, so make sure nothing new starts either way.
+    shutdown.begin_shutdown();
+
+    // Let running jobs finish first, so what they log is part of the final flush.
+    task_manager.shutdown(std::time::Duration::from_secs(config_watcher.current().bot.shutdown_timeout_secs)).await;
+
+    telemetry_manager.log_metrics_snapshot(&metrics).await;
+    telemetry_manager.log_event("bot_stop").await?;
+    if let Err(why) = telemetry_manager.flush().await {
+        log::error!("Failed to flush telemetry on shutdown: {:?}", why);
+    }
+    database.close().await;
+    log::info!("Shutdown complete");
+
     Ok(())
 }
\ No newline at end of file
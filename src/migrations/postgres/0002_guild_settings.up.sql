@@ -0,0 +1,7 @@
+CREATE TABLE guild_settings (
+    guild_id BIGINT NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    value TEXT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (guild_id, name)
+);
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE scheduled_jobs ADD COLUMN lock_token VARCHAR(64);
\ No newline at end of file
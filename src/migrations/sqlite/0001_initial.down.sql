@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_users_discord_id;
+DROP TABLE users;
\ No newline at end of file
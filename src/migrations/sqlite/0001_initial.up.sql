@@ -0,0 +1,10 @@
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    discord_id BIGINT UNIQUE NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    joined_at TIMESTAMP NOT NULL,
+    experience INTEGER NOT NULL DEFAULT 0,
+    level INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE INDEX idx_users_discord_id ON users(discord_id);
\ No newline at end of file
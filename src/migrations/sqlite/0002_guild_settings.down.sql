@@ -0,0 +1 @@
+DROP TABLE guild_settings;
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_audit_log_created_at;
+DROP TABLE audit_log;
\ No newline at end of file
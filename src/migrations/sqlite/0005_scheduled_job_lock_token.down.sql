@@ -0,0 +1 @@
+ALTER TABLE scheduled_jobs DROP COLUMN lock_token;
\ No newline at end of file
@@ -6,13 +6,27 @@ use serenity::builder::CreateApplicationCommands;
 use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
 use serenity::prelude::*;
 use crate::bot::Bot;
-use crate::bot::error::BotResult;
+use crate::bot::error::{BotResult, BotError};
+use crate::utils::rate_limiter::RateLimit;
+use crate::utils::scheduler::JobDefinition;
+use crate::utils::settings::SettingDefinition;
+
+pub mod example_plugin;
+
+use example_plugin::ExamplePlugin;
 
 #[async_trait]
 pub trait Plugin: Send + Sync {
     fn name(&self) -> &str;
     fn description(&self) -> &str;
     fn commands(&self) -> Vec<Box<dyn PluginCommand>>;
+    fn settings(&self) -> Vec<SettingDefinition> {
+        Vec::new()
+    }
+    /// Scheduled jobs, registered as `<plugin>:<job>` while the plugin is loaded.
+    fn jobs(&self) -> Vec<JobDefinition> {
+        Vec::new()
+    }
     async fn on_load(&self, bot: &Bot) -> BotResult<()>;
     async fn on_unload(&self, bot: &Bot) -> BotResult<()>;
 }
@@ -22,12 +36,29 @@ pub trait PluginCommand: Send + Sync {
     fn name(&self) -> &str;
     fn description(&self) -> &str;
     fn register(&self, command: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands;
+    fn rate_limit(&self) -> Option<RateLimit> {
+        None
+    }
     async fn run(&self, bot: &Bot, ctx: &Context, command: &ApplicationCommandInteraction) -> BotResult<String>;
 }
 
+pub type PluginFactory = fn() -> Box<dyn Plugin>;
+
+/// Plugins shipped with the bot. They are loaded once the bot connects and stay available to
+/// `/owner plugin load`, and `register-commands` registers their commands alongside the built-in ones.
+pub fn builtin_plugins() -> Vec<(&'static str, PluginFactory)> {
+    vec![("example", example_plugin)]
+}
+
+fn example_plugin() -> Box<dyn Plugin> {
+    Box::new(ExamplePlugin)
+}
+
 pub struct PluginManager {
     plugins: RwLock<HashMap<String, Box<dyn Plugin>>>,
     commands: RwLock<HashMap<String, Box<dyn PluginCommand>>>,
+    // Plugins that can be loaded by name at runtime, e.g. from `/owner plugin load`.
+    available: RwLock<HashMap<String, PluginFactory>>,
 }
 
 impl PluginManager {
@@ -35,12 +66,72 @@ impl PluginManager {
         Self {
             plugins: RwLock::new(HashMap::new()),
             commands: RwLock::new(HashMap::new()),
+            available: RwLock::new(HashMap::new()),
         }
     }
 
+    pub async fn register_available(&self, name: &str, factory: PluginFactory) {
+        let mut available = self.available.write().await;
+        available.insert(name.to_string(), factory);
+    }
+
+    pub async fn get_available_plugins(&self) -> Vec<String> {
+        let available = self.available.read().await;
+        let mut names: Vec<String> = available.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub async fn load_by_name(&self, bot: &Bot, plugin_name: &str) -> BotResult<()> {
+        if self.plugins.read().await.contains_key(plugin_name) {
+            return Err(BotError::Command(format!("Plugin '{}' is already loaded", plugin_name)));
+        }
+        let factory = self.available.read().await.get(plugin_name).copied()
+            .ok_or_else(|| BotError::Command(format!("Unknown plugin '{}'", plugin_name)))?;
+        self.load_plugin(bot, factory()).await
+    }
+
+    pub async fn is_loaded(&self, plugin_name: &str) -> bool {
+        self.plugins.read().await.contains_key(plugin_name)
+    }
+
     pub async fn load_plugin(&self, bot: &Bot, plugin: Box<dyn Plugin>) -> BotResult<()> {
         let plugin_name = plugin.name().to_string();
-        plugin.on_load(bot).await?;
+        let mut settings = Vec::new();
+        let mut jobs = Vec::new();
+        let mut loaded = false;
+        let result: BotResult<()> = async {
+            for setting in plugin.settings() {
+                let key = setting.key.clone();
+                bot.guild_data.registry().register(setting).await?;
+                settings.push(key);
+            }
+            plugin.on_load(bot).await?;
+            loaded = true;
+            for mut job in plugin.jobs() {
+                job.name = plugin_job_name(&plugin_name, &job.name);
+                let name = job.name.clone();
+                bot.scheduler.register(job).await?;
+                jobs.push(name);
+            }
+            Ok(())
+        }.await;
+
+        // A plugin that fails to load leaves none of its settings or jobs behind.
+        if let Err(why) = result {
+            if loaded {
+                if let Err(e) = plugin.on_unload(bot).await {
+                    log::warn!("Failed to unload plugin '{}' after it failed to load: {:?}", plugin_name, e);
+                }
+            }
+            for key in &settings {
+                bot.guild_data.registry().unregister(key).await;
+            }
+            for name in &jobs {
+                bot.scheduler.unregister(name).await;
+            }
+            return Err(why);
+        }
 
         let mut plugins = self.plugins.write().await;
         let mut commands = self.commands.write().await;
@@ -62,6 +153,12 @@ impl PluginManager {
             for command in plugin.commands() {
                 commands.remove(command.name());
             }
+            for setting in plugin.settings() {
+                bot.guild_data.registry().unregister(&setting.key).await;
+            }
+            for job in plugin.jobs() {
+                bot.scheduler.unregister(&plugin_job_name(plugin_name, &job.name)).await;
+            }
         }
 
         Ok(())
@@ -89,4 +186,8 @@ impl PluginManager {
         let commands = self.commands.read().await;
         commands.values().cloned().collect()
     }
-}
\ No newline at end of file
+}
+
+fn plugin_job_name(plugin_name: &str, job_name: &str) -> String {
+    format!("{}:{}", plugin_name, job_name)
+}
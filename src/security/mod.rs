@@ -3,24 +3,35 @@ use serenity::model::permissions::Permissions;
 use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
 use serenity::prelude::*;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
 use crate::bot::error::BotResult;
+use crate::config::{ApplyConfig, Config, ConfigListener, RateLimitConfig};
+use crate::utils::rate_limiter::{RateLimit, RateLimitResult, RateLimitSubject, RateLimiter};
+
+/// Rate limit key shared by every command, on top of each command's own limit.
+pub const GLOBAL_RATE_LIMIT: &str = "command";
 
 pub struct SecurityManager {
     command_permissions: Arc<RwLock<HashMap<String, Permissions>>>,
     user_roles: Arc<RwLock<HashMap<(GuildId, UserId), Vec<String>>>>,
     blocked_users: Arc<RwLock<Vec<UserId>>>,
-    rate_limits: Arc<RwLock<HashMap<String, (u32, std::time::Duration)>>>,
+    rate_limiter: Arc<RateLimiter>,
+    // The limits commands declare themselves and the configured overrides, kept so the
+    // effective limits can be worked out again when the configuration is reloaded.
+    declared_rate_limits: Mutex<HashMap<String, Option<RateLimit>>>,
+    rate_limit_config: Mutex<Option<RateLimitConfig>>,
 }
 
 impl SecurityManager {
-    pub fn new() -> Self {
+    pub fn new(rate_limiter: Arc<RateLimiter>) -> Self {
         Self {
             command_permissions: Arc::new(RwLock::new(HashMap::new())),
             user_roles: Arc::new(RwLock::new(HashMap::new())),
             blocked_users: Arc::new(RwLock::new(Vec::new())),
-            rate_limits: Arc::new(RwLock::new(HashMap::new())),
+            rate_limiter,
+            declared_rate_limits: Mutex::new(HashMap::new()),
+            rate_limit_config: Mutex::new(None),
         }
     }
 
@@ -49,6 +60,11 @@ impl SecurityManager {
         Ok(true)
     }
 
+    /// `member_permissions` are the ones Discord resolves for the member sending an interaction.
+    pub fn member_has_permissions(&self, member_permissions: Option<Permissions>, permissions: Permissions) -> bool {
+        member_permissions.map_or(false, |member_permissions| member_permissions.administrator() || member_permissions.contains(permissions))
+    }
+
     pub async fn set_user_roles(&self, guild_id: GuildId, user_id: UserId, roles: Vec<String>) {
         let mut user_roles = self.user_roles.write().await;
         user_roles.insert((guild_id, user_id), roles);
@@ -81,13 +97,43 @@ impl SecurityManager {
         blocked_users.contains(&user_id)
     }
 
-    pub async fn set_rate_limit(&self, command_name: &str, limit: u32, duration: std::time::Duration) {
-        let mut rate_limits = self.rate_limits.write().await;
-        rate_limits.insert(command_name.to_string(), (limit, duration));
+    pub fn set_rate_limit(&self, command_name: &str, limit: RateLimit) {
+        self.rate_limiter.add_limit(&rate_limit_key(command_name), limit);
+    }
+
+    /// Records the limit a command declares and applies it, unless `[rate_limits.commands]`
+    /// overrides it.
+    pub fn declare_rate_limit(&self, command_name: &str, declared: Option<RateLimit>) {
+        self.declared_rate_limits.lock().unwrap().insert(command_name.to_string(), declared);
+        let config = self.rate_limit_config.lock().unwrap();
+        self.apply_rate_limit(command_name, declared, config.as_ref());
+    }
+
+    /// Applies the global limit and re-applies every declared command limit with the
+    /// overrides from `config`.
+    pub fn configure_rate_limits(&self, config: &RateLimitConfig) {
+        self.rate_limiter.add_limit(GLOBAL_RATE_LIMIT, config.global.to_rate_limit());
+        let declared = self.declared_rate_limits.lock().unwrap().clone();
+        for (command_name, limit) in declared {
+            self.apply_rate_limit(&command_name, limit, Some(config));
+        }
+        *self.rate_limit_config.lock().unwrap() = Some(config.clone());
+    }
+
+    fn apply_rate_limit(&self, command_name: &str, declared: Option<RateLimit>, config: Option<&RateLimitConfig>) {
+        let configured = config
+            .and_then(|config| config.commands.get(command_name))
+            .map(|rule| rule.to_rate_limit());
+        match configured.or(declared) {
+            Some(limit) => self.set_rate_limit(command_name, limit),
+            None => self.rate_limiter.remove_limit(&rate_limit_key(command_name)),
+        }
     }
 
-    pub async fn check_rate_limit(&self, command_name: &str, user_id: UserId) -> bool {
-        true
+    /// Checks the command's own limit and the global limit together; a request denied by
+    /// either uses up neither.
+    pub fn check_rate_limit(&self, command_name: &str, subject: &RateLimitSubject) -> RateLimitResult {
+        self.rate_limiter.check_all(&[&rate_limit_key(command_name), GLOBAL_RATE_LIMIT], subject)
     }
 
     pub fn validate_url(&self, url: &str) -> bool {
@@ -104,4 +150,22 @@ impl SecurityManager {
     pub fn sanitize_input(&self, input: &str) -> String {
         input.replace('<', "&lt;").replace('>', "&gt;")
     }
-}
\ No newline at end of file
+}
+
+impl ConfigListener for SecurityManager {
+    // The rules were checked by `Config::validate`, so applying them cannot fail.
+    fn prepare(self: Arc<Self>, previous: &Config, config: &Config) -> BotResult<Option<ApplyConfig>> {
+        if previous.rate_limits == config.rate_limits {
+            return Ok(None);
+        }
+        let rate_limits = config.rate_limits.clone();
+        Ok(Some(Box::new(move || {
+            self.configure_rate_limits(&rate_limits);
+            log::info!("Applied the reloaded rate limits");
+        })))
+    }
+}
+
+fn rate_limit_key(command_name: &str) -> String {
+    format!("command:{}", command_name)
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use regex::Regex;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::bot::error::{BotResult, BotError};
+use crate::config::TelemetryConfig;
+use super::random_u64;
+
+const REDACTED: &str = "[redacted]";
+// Errors are rare and the most useful thing we record, so they are never sampled away.
+const ALWAYS_KEPT: &[&str] = &["error"];
+
+/// Decides which telemetry events are kept and strips personal data from the ones that are.
+pub struct TelemetryFilter {
+    sampling: HashMap<String, f64>,
+    salt: Option<String>,
+    id_fields: Vec<String>,
+    drop_fields: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl TelemetryFilter {
+    pub fn new(config: &TelemetryConfig) -> BotResult<Self> {
+        let redaction = &config.redaction;
+        let patterns = redaction.patterns.iter()
+            .map(|pattern| Regex::new(pattern).map_err(|e| BotError::Config(format!("Invalid telemetry redaction pattern '{}': {}", pattern, e))))
+            .collect::<BotResult<Vec<_>>>()?;
+
+        Ok(Self {
+            sampling: config.sampling.clone(),
+            salt: Some(redaction.salt.clone()).filter(|_| redaction.hash_ids),
+            id_fields: redaction.id_fields.clone(),
+            drop_fields: redaction.drop_fields.clone(),
+            patterns,
+        })
+    }
+
+    pub fn should_sample(&self, event_type: &str) -> bool {
+        if ALWAYS_KEPT.contains(&event_type) {
+            return true;
+        }
+        match self.sampling.get(event_type) {
+            None => true,
+            Some(&rate) if rate >= 1.0 => true,
+            Some(&rate) if rate <= 0.0 => false,
+            Some(&rate) => (random_u64() as f64 / u64::MAX as f64) < rate,
+        }
+    }
+
+    /// Salted hash of a Discord ID, or the ID itself when hashing is disabled.
+    pub fn hash_id(&self, id: &str) -> String {
+        match &self.salt {
+            Some(salt) => {
+                let digest = Sha256::new().chain_update(salt.as_bytes()).chain_update(id.as_bytes()).finalize();
+                digest.iter().take(8).map(|byte| format!("{:02x}", byte)).collect()
+            }
+            None => id.to_string(),
+        }
+    }
+
+    pub fn redact(&self, data: &mut Value) {
+        match data {
+            Value::Object(fields) => {
+                fields.retain(|key, _| !self.drop_fields.contains(key));
+                for (key, value) in fields.iter_mut() {
+                    if self.salt.is_some() && self.id_fields.contains(key) {
+                        self.redact_id(value);
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item)),
+            Value::String(text) => *text = self.scrub(text),
+            _ => {}
+        }
+    }
+
+    fn redact_id(&self, value: &mut Value) {
+        let id = match value {
+            Value::Number(number) => number.to_string(),
+            Value::String(text) => text.clone(),
+            _ => return,
+        };
+        *value = Value::String(self.hash_id(&id));
+    }
+
+    pub fn scrub(&self, text: &str) -> String {
+        self.patterns.iter()
+            .fold(text.to_string(), |text, pattern| pattern.replace_all(&text, REDACTED).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+    use crate::config::RedactionConfig;
+
+    fn filter_config() -> TelemetryConfig {
+        TelemetryConfig {
+            enabled: true,
+            log_file: String::new(),
+            batch_size: 1,
+            max_file_size_mb: 50,
+            rotate_daily: false,
+            keep_files: 1,
+            compress: false,
+            otlp: Default::default(),
+            redaction: Default::default(),
+            sampling: Default::default(),
+        }
+    }
+
+    fn filter(redaction: RedactionConfig, sampling: &[(&str, f64)]) -> TelemetryFilter {
+        let sampling = sampling.iter().map(|(event, rate)| (event.to_string(), *rate)).collect();
+        TelemetryFilter::new(&TelemetryConfig { redaction, sampling, ..filter_config() }).unwrap()
+    }
+
+    fn hashing(salt: &str) -> RedactionConfig {
+        RedactionConfig { hash_ids: true, salt: salt.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn ids_are_hashed_with_the_salt() {
+        let salted = filter(hashing("pepper"), &[]);
+        let hashed = salted.hash_id("1234");
+        assert_eq!(hashed.len(), 16);
+        assert_ne!(hashed, "1234");
+        assert_eq!(hashed, salted.hash_id("1234"));
+        assert_ne!(hashed, filter(hashing("other"), &[]).hash_id("1234"));
+
+        // Numeric and string IDs hash the same way.
+        let mut data = json!({ "user_id": 1234, "guild_id": "1234", "command": "ping" });
+        salted.redact(&mut data);
+        assert_eq!(data, json!({ "user_id": hashed, "guild_id": hashed, "command": "ping" }));
+    }
+
+    #[test]
+    fn ids_are_kept_without_hashing() {
+        let plain = filter(RedactionConfig::default(), &[]);
+        assert_eq!(plain.hash_id("1234"), "1234");
+        let mut data = json!({ "user_id": 1234 });
+        plain.redact(&mut data);
+        assert_eq!(data, json!({ "user_id": 1234 }));
+    }
+
+    #[test]
+    fn nested_values_are_redacted() {
+        let redaction = RedactionConfig {
+            drop_fields: vec!["username".to_string()],
+            patterns: vec![r"\b[\w.]+@[\w.]+\b".to_string()],
+            ..hashing("pepper")
+        };
+        let filter = filter(redaction, &[]);
+        let hashed = filter.hash_id("7");
+        let mut data = json!({
+            "username": "alice",
+            "members": [{ "user_id": 7, "username": "bob", "note": "mail bob@example.com" }],
+            "context": { "channel_id": "7", "tags": ["a@b.c", "plain"] },
+        });
+        filter.redact(&mut data);
+        assert_eq!(data, json!({
+            "members": [{ "user_id": hashed, "note": "mail [redacted]" }],
+            "context": { "channel_id": hashed, "tags": ["[redacted]", "plain"] },
+        }));
+    }
+
+    #[test]
+    fn patterns_scrub_every_match() {
+        let redaction = RedactionConfig { patterns: vec![r"\d{4,}".to_string(), "secret".to_string()], ..Default::default() };
+        let filter = filter(redaction, &[]);
+        assert_eq!(filter.scrub("code 12345 and 67890 is secret"), "code [redacted] and [redacted] is [redacted]");
+        assert_eq!(filter.scrub("nothing here"), "nothing here");
+
+        let invalid = RedactionConfig { patterns: vec!["(".to_string()], ..Default::default() };
+        assert!(TelemetryFilter::new(&TelemetryConfig { redaction: invalid, ..filter_config() }).is_err());
+    }
+
+    #[test]
+    fn sampling_follows_the_configured_rates() {
+        let filter = filter(RedactionConfig::default(), &[("command_used", 0.0), ("member_join", 1.0), ("error", 0.0)]);
+        for _ in 0..100 {
+            assert!(!filter.should_sample("command_used"));
+            assert!(filter.should_sample("member_join"));
+            // Unlisted events are always kept, and errors even when sampled at 0.
+            assert!(filter.should_sample("guild_join"));
+            assert!(filter.should_sample("error"));
+        }
+    }
+}
@@ -1,96 +1,268 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use arc_swap::ArcSwap;
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 use serde_json::json;
+use std::time::Duration;
 use crate::bot::error::BotResult;
+use crate::config::{ApplyConfig, Config, ConfigListener, TelemetryConfig};
+use crate::utils::metrics::{CommandOutcome, Metrics};
+use crate::utils::task_manager::{RestartPolicy, ShutdownToken, TaskManager, TaskOptions};
+
+pub mod filter;
+pub mod otlp;
+pub mod sink;
+
+use filter::TelemetryFilter;
+use otlp::{CommandSpan, OtlpExporter};
+use sink::JsonLinesSink;
+
+// Bump when a field is renamed or removed, so consumers can tell old lines apart.
+pub const TELEMETRY_SCHEMA_VERSION: u32 = 1;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(300);
+
+// Events kept for a retry after failed writes, in multiples of `batch_size`.
+const MAX_BUFFERED_BATCHES: usize = 10;
+
+static RANDOM_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// RandomState is seeded per process, which is plenty for sampling and trace IDs.
+pub(crate) fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(RANDOM_COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+    hasher.finish()
+}
 
 pub struct TelemetryManager {
     events: Arc<Mutex<Vec<TelemetryEvent>>>,
-    config: TelemetryConfig,
+    // Swapped on config reload; shared with clones so they see the new settings too.
+    config: Arc<ArcSwap<TelemetryConfig>>,
+    sink: JsonLinesSink,
+    otlp: Option<OtlpExporter>,
+    filter: Arc<ArcSwap<TelemetryFilter>>,
 }
 
+#[derive(Debug, Serialize)]
 struct TelemetryEvent {
+    schema_version: u32,
     timestamp: DateTime<Utc>,
+    #[serde(rename = "event")]
     event_type: String,
     data: serde_json::Value,
 }
 
-pub struct TelemetryConfig {
-    pub enabled: bool,
-    pub log_file: String,
-    pub batch_size: usize,
-}
-
 impl TelemetryManager {
-    pub fn new(config: &TelemetryConfig) -> Self {
-        Self {
+    pub fn new(config: &TelemetryConfig, service_name: &str) -> BotResult<Self> {
+        let otlp = if config.otlp.enabled {
+            Some(OtlpExporter::new(&config.otlp, service_name)?)
+        } else {
+            None
+        };
+        Ok(Self {
             events: Arc::new(Mutex::new(Vec::new())),
-            config: config.clone(),
+            config: Arc::new(ArcSwap::from_pointee(config.clone())),
+            sink: JsonLinesSink::new(config)?,
+            otlp,
+            filter: Arc::new(ArcSwap::from_pointee(TelemetryFilter::new(config)?)),
+        })
+    }
+
+    /// Exports the command counters and durations in `metrics` along with the OTLP gauges.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.otlp = self.otlp.map(|otlp| otlp.with_metrics(metrics));
+        self
+    }
+
+    /// Applies new sampling, redaction, batching and `enabled` settings. The log file and OTLP
+    /// exporter are set up once, so changes to them only take effect after a restart.
+    fn apply_config(&self, config: &TelemetryConfig, filter: TelemetryFilter) {
+        let previous = self.config.load_full();
+        let file_changed = previous.log_file != config.log_file
+            || previous.max_file_size_mb != config.max_file_size_mb
+            || previous.rotate_daily != config.rotate_daily
+            || previous.keep_files != config.keep_files
+            || previous.compress != config.compress;
+        if file_changed || previous.otlp != config.otlp {
+            log::warn!("Changes to the telemetry log file or [telemetry.otlp] take effect after a restart");
+        }
+        self.filter.store(Arc::new(filter));
+        self.config.store(Arc::new(config.clone()));
+    }
+
+    pub async fn set_shard(&self, shard_id: u64, shard_count: u64) {
+        if let Some(otlp) = &self.otlp {
+            otlp.set_shard(shard_id, shard_count).await;
+        }
+    }
+
+    pub async fn record_command_span(&self, mut span: CommandSpan, outcome: CommandOutcome) {
+        let enabled = self.config.load().enabled;
+        if let (true, Some(otlp)) = (enabled, &self.otlp) {
+            let filter = self.filter.load_full();
+            if !filter.should_sample("command_span") {
+                return;
+            }
+            span.map_guild_id(|guild_id| filter.hash_id(guild_id));
+            otlp.record_span(span, outcome).await;
         }
     }
 
-    pub async fn log_event(&self, event_type: &str, data: serde_json::Value) -> BotResult<()> {
-        if !self.config.enabled {
+    pub async fn log_event(&self, event_type: &str) -> BotResult<()> {
+        self.log_event_with(event_type, json!({})).await
+    }
+
+    pub async fn log_event_with(&self, event_type: &str, mut data: serde_json::Value) -> BotResult<()> {
+        let filter = self.filter.load_full();
+        let enabled = self.config.load().enabled;
+        if !enabled || !filter.should_sample(event_type) {
             return Ok(());
         }
+        filter.redact(&mut data);
+
+        if let Some(otlp) = &self.otlp {
+            otlp.record_event(event_type, &data).await;
+        }
 
         let event = TelemetryEvent {
+            schema_version: TELEMETRY_SCHEMA_VERSION,
             timestamp: Utc::now(),
             event_type: event_type.to_string(),
             data,
         };
 
-        let mut events = self.events.lock().await;
-        events.push(event);
+        let batch_full = {
+            let mut events = self.events.lock().await;
+            events.push(event);
+            events.len() >= self.config.load().batch_size
+        };
 
-        if events.len() >= self.config.batch_size {
-            self.flush_events().await?;
+        if batch_full {
+            self.flush().await?;
         }
 
         Ok(())
     }
 
-    pub async fn log_command(&self, command_name: &str) -> BotResult<()> {
-        self.log_event("command_used", json!({ "command": command_name })).await
+    pub async fn log_command(&self, command_name: &str, guild_id: Option<u64>, outcome: CommandOutcome, duration: Duration) -> BotResult<()> {
+        self.log_event_with("command_used", json!({
+            "command": command_name,
+            "guild_id": guild_id,
+            "outcome": outcome.as_str(),
+            "duration_ms": duration.as_millis() as u64
+        })).await
     }
 
+    /// The message is only recorded when `redaction.error_messages` is set; it is still
+    /// subject to the other redaction rules then.
     pub async fn log_error(&self, error_type: &str, error_message: &str) -> BotResult<()> {
-        self.log_event("error", json!({
-            "type": error_type,
-            "message": error_message
-        })).await
+        let mut data = json!({ "type": error_type });
+        if self.config.load().redaction.error_messages {
+            data["message"] = json!(error_message);
+        }
+        self.log_event_with("error", data).await
     }
 
     pub async fn log_metric(&self, metric_name: &str, value: f64) -> BotResult<()> {
-        self.log_event("metric", json!({
+        let enabled = self.config.load().enabled;
+        if let (true, Some(otlp)) = (enabled, &self.otlp) {
+            otlp.record_gauge(metric_name, value).await;
+        }
+        self.log_event_with("metric", json!({
             "name": metric_name,
             "value": value
         })).await
     }
 
-    async fn flush_events(&self) -> BotResult<()> {
-        let mut events = self.events.lock().await;
-        if events.is_empty() {
-            return Ok(());
+    // Records the final state of in-memory metrics, which would otherwise be lost on exit.
+    pub async fn log_metrics_snapshot(&self, metrics: &Metrics) {
+        let mut values: Vec<(String, f64)> = metrics.get_gauges().await.into_iter().collect();
+        values.push(("errors_total".to_string(), metrics.get_error_count().await as f64));
+        let latency = metrics.get_latency().await;
+        if latency.count > 0 {
+            values.push(("latency_p50_ms".to_string(), latency.p50));
+            values.push(("latency_p99_ms".to_string(), latency.p99));
+        }
+        for (command, count) in metrics.get_command_usage().await {
+            values.push((format!("command_usage.{}", command), count as f64));
+        }
+
+        for (name, value) in values {
+            if let Err(why) = self.log_metric(&name, value).await {
+                log::error!("Failed to log metric '{}': {:?}", name, why);
+            }
         }
+    }
 
-        let events_json = serde_json::to_string(&events.drain(..).collect::<Vec<_>>())?;
+    pub async fn flush(&self) -> BotResult<()> {
+        if let Some(otlp) = &self.otlp {
+            if let Err(why) = otlp.flush().await {
+                log::warn!("Failed to export telemetry over OTLP: {:?}", why);
+            }
+        }
 
-        tokio::fs::append_to_file(&self.config.log_file, events_json.as_bytes()).await?;
+        let events: Vec<TelemetryEvent> = self.events.lock().await.drain(..).collect();
+        if events.is_empty() {
+            return Ok(());
+        }
 
+        let lines = events.iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Err(why) = self.sink.write_lines(lines).await {
+            self.requeue(events).await;
+            return Err(why);
+        }
         Ok(())
     }
 
-    pub async fn start_periodic_flush(&self) {
+    // Puts unwritten events back ahead of anything logged since, dropping the oldest once the
+    // buffer is full so a sink that keeps failing can't grow it without bound.
+    async fn requeue(&self, mut events: Vec<TelemetryEvent>) {
+        let mut buffered = self.events.lock().await;
+        events.append(&mut buffered);
+        let capacity = self.config.load().batch_size.max(1) * MAX_BUFFERED_BATCHES;
+        if events.len() > capacity {
+            let dropped = events.len() - capacity;
+            events.drain(..dropped);
+            log::warn!("Dropped {} telemetry events that could not be written", dropped);
+        }
+        *buffered = events;
+    }
+
+    /// Flushes events and runs the OTLP export loop under `task_manager` until shutdown. The
+    /// caller flushes one last time once the other tasks have stopped.
+    pub async fn start_periodic_flush(&self, task_manager: &TaskManager) -> BotResult<()> {
         let telemetry_manager = self.clone();
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(300)).await;
-                if let Err(e) = telemetry_manager.flush_events().await {
-                    log::error!("Failed to flush telemetry events: {:?}", e);
+        task_manager.spawn_supervised("telemetry_flush", TaskOptions::restart(RestartPolicy::Always).max_restarts(None), move |shutdown| {
+            let telemetry_manager = telemetry_manager.clone();
+            async move {
+                match &telemetry_manager.otlp {
+                    Some(otlp) => {
+                        tokio::join!(telemetry_manager.flush_periodically(shutdown.clone()), otlp.run(shutdown));
+                    }
+                    None => telemetry_manager.flush_periodically(shutdown).await,
                 }
+                Ok(())
             }
-        });
+        }).await
+    }
+
+    async fn flush_periodically(&self, shutdown: ShutdownToken) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(FLUSH_INTERVAL) => {}
+                _ = shutdown.cancelled() => return,
+            }
+            if let Err(e) = self.flush().await {
+                log::error!("Failed to flush telemetry events: {:?}", e);
+            }
+        }
     }
 }
 
@@ -98,17 +270,93 @@ impl Clone for TelemetryManager {
     fn clone(&self) -> Self {
         Self {
             events: Arc::clone(&self.events),
-            config: self.config.clone(),
+            config: Arc::clone(&self.config),
+            sink: self.sink.clone(),
+            otlp: self.otlp.clone(),
+            filter: Arc::clone(&self.filter),
         }
     }
 }
 
-impl Clone for TelemetryConfig {
-    fn clone(&self) -> Self {
-        Self {
-            enabled: self.enabled,
-            log_file: self.log_file.clone(),
-            batch_size: self.batch_size,
+impl ConfigListener for TelemetryManager {
+    fn prepare(self: Arc<Self>, previous: &Config, config: &Config) -> BotResult<Option<ApplyConfig>> {
+        if previous.telemetry == config.telemetry {
+            return Ok(None);
+        }
+        let filter = TelemetryFilter::new(&config.telemetry)?;
+        let telemetry = config.telemetry.clone();
+        Ok(Some(Box::new(move || {
+            self.apply_config(&telemetry, filter);
+            log::info!("Applied the reloaded telemetry settings");
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn config(log_file: &std::path::Path, batch_size: usize) -> TelemetryConfig {
+        TelemetryConfig {
+            enabled: true,
+            log_file: log_file.display().to_string(),
+            batch_size,
+            max_file_size_mb: 50,
+            rotate_daily: false,
+            keep_files: 2,
+            compress: false,
+            otlp: Default::default(),
+            redaction: Default::default(),
+            sampling: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_writes_keep_the_events_for_the_next_flush() {
+        let log_file = temp_dir().join("telemetry.log");
+        let telemetry = TelemetryManager::new(&config(&log_file, 100), "test").unwrap();
+        telemetry.log_event("first").await.unwrap();
+
+        // A directory in place of the log file makes every write fail.
+        std::fs::create_dir(&log_file).unwrap();
+        assert!(telemetry.flush().await.is_err());
+        telemetry.log_event("second").await.unwrap();
+
+        std::fs::remove_dir(&log_file).unwrap();
+        telemetry.flush().await.unwrap();
+        let written = std::fs::read_to_string(&log_file).unwrap();
+        let events: Vec<&str> = written.lines().collect();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].contains("\"first\"") && events[1].contains("\"second\""));
+    }
+
+    #[tokio::test]
+    async fn error_messages_are_left_out_unless_enabled() {
+        let directory = temp_dir();
+        let telemetry = TelemetryManager::new(&config(&directory.join("off.log"), 100), "test").unwrap();
+        telemetry.log_error("welcome_message_error", "Could not greet Alice").await.unwrap();
+        telemetry.flush().await.unwrap();
+        let written = std::fs::read_to_string(directory.join("off.log")).unwrap();
+        assert!(written.contains("welcome_message_error") && !written.contains("Alice"));
+
+        let mut enabled = config(&directory.join("on.log"), 100);
+        enabled.redaction.error_messages = true;
+        let telemetry = TelemetryManager::new(&enabled, "test").unwrap();
+        telemetry.log_error("welcome_message_error", "Could not greet Alice").await.unwrap();
+        telemetry.flush().await.unwrap();
+        assert!(std::fs::read_to_string(directory.join("on.log")).unwrap().contains("Could not greet Alice"));
+    }
+
+    #[tokio::test]
+    async fn requeued_events_are_capped() {
+        let log_file = temp_dir().join("telemetry.log");
+        std::fs::create_dir(&log_file).unwrap();
+        let telemetry = TelemetryManager::new(&config(&log_file, 1), "test").unwrap();
+
+        for _ in 0..MAX_BUFFERED_BATCHES + 5 {
+            assert!(telemetry.log_event("lost").await.is_err());
         }
+        assert_eq!(telemetry.events.lock().await.len(), MAX_BUFFERED_BATCHES);
     }
-}
\ No newline at end of file
+}
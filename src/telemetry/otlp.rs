@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use crate::bot::error::{BotResult, BotError};
+use crate::config::OtlpConfig;
+use crate::utils::histogram::HistogramSnapshot;
+use crate::utils::metrics::{CommandOutcome, Metrics};
+use crate::utils::task_manager::ShutdownToken;
+use super::random_u64;
+
+const SCOPE_NAME: &str = "discord-bot";
+// Protects memory when the collector is unreachable; anything beyond this is dropped.
+const MAX_BUFFERED: usize = 10_000;
+
+// OTLP span kinds and status codes.
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_OK: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+const AGGREGATION_TEMPORALITY_CUMULATIVE: u8 = 2;
+
+fn trace_id() -> String {
+    format!("{:016x}{:016x}", random_u64(), random_u64())
+}
+
+fn span_id() -> String {
+    format!("{:016x}", random_u64())
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+/// Times one command invocation, from the interaction arriving to the response being sent.
+/// Each call to `phase` closes the running phase and opens the next as a child span.
+pub struct CommandSpan {
+    command: String,
+    guild_id: Option<String>,
+    started: u128,
+    phases: Vec<(String, u128, u128)>,
+    current: Option<(String, u128)>,
+}
+
+impl CommandSpan {
+    pub fn start(command: &str, guild_id: Option<u64>) -> Self {
+        Self {
+            command: command.to_string(),
+            guild_id: guild_id.map(|id| id.to_string()),
+            started: unix_nanos(),
+            phases: Vec::new(),
+            current: None,
+        }
+    }
+
+    pub fn map_guild_id(&mut self, f: impl FnOnce(&str) -> String) {
+        self.guild_id = self.guild_id.as_deref().map(f);
+    }
+
+    pub fn phase(&mut self, name: &str) {
+        let now = unix_nanos();
+        if let Some((previous, started)) = self.current.take() {
+            self.phases.push((previous, started, now));
+        }
+        self.current = Some((name.to_string(), now));
+    }
+
+    fn finish(mut self, outcome: CommandOutcome) -> Vec<Value> {
+        let ended = unix_nanos();
+        if let Some((name, started)) = self.current.take() {
+            self.phases.push((name, started, ended));
+        }
+
+        let trace_id = trace_id();
+        let root_id = span_id();
+        let status = match outcome {
+            CommandOutcome::InternalError => json!({ "code": STATUS_ERROR, "message": outcome.as_str() }),
+            _ => json!({ "code": STATUS_OK }),
+        };
+        let mut attributes = vec![
+            attribute("command.name", &json!(self.command)),
+            attribute("command.outcome", &json!(outcome.as_str())),
+        ];
+        if let Some(guild_id) = &self.guild_id {
+            attributes.push(attribute("discord.guild.id", &json!(guild_id)));
+        }
+
+        let mut spans = vec![json!({
+            "traceId": trace_id,
+            "spanId": root_id,
+            "name": format!("command {}", self.command),
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": self.started.to_string(),
+            "endTimeUnixNano": ended.to_string(),
+            "attributes": attributes,
+            "status": status,
+        })];
+        spans.extend(self.phases.into_iter().map(|(name, started, ended)| json!({
+            "traceId": trace_id,
+            "spanId": span_id(),
+            "parentSpanId": root_id,
+            "name": name,
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": started.to_string(),
+            "endTimeUnixNano": ended.to_string(),
+        })));
+        spans
+    }
+}
+
+#[derive(Default)]
+struct Buffers {
+    spans: Vec<Value>,
+    logs: Vec<Value>,
+    metrics: Vec<Value>,
+    dropped: u64,
+}
+
+impl Buffers {
+    fn push(items: &mut Vec<Value>, dropped: &mut u64, new: impl IntoIterator<Item = Value>) {
+        for item in new {
+            if items.len() >= MAX_BUFFERED {
+                *dropped += 1;
+            } else {
+                items.push(item);
+            }
+        }
+    }
+
+    // Puts items that failed to export back ahead of newer ones, dropping the oldest of them
+    // when there isn't room for all.
+    fn requeue(items: &mut Vec<Value>, dropped: &mut u64, mut failed: Vec<Value>) {
+        let excess = failed.len().saturating_sub(MAX_BUFFERED.saturating_sub(items.len()));
+        *dropped += excess as u64;
+        failed.drain(..excess);
+        failed.append(items);
+        *items = failed;
+    }
+
+    fn items(&mut self, signal: Signal) -> (&mut Vec<Value>, &mut u64) {
+        match signal {
+            Signal::Traces => (&mut self.spans, &mut self.dropped),
+            Signal::Logs => (&mut self.logs, &mut self.dropped),
+            Signal::Metrics => (&mut self.metrics, &mut self.dropped),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Signal {
+    Traces,
+    Logs,
+    Metrics,
+}
+
+impl Signal {
+    fn path(self) -> &'static str {
+        match self {
+            Signal::Traces => "/v1/traces",
+            Signal::Logs => "/v1/logs",
+            Signal::Metrics => "/v1/metrics",
+        }
+    }
+
+    fn body(self, resource: &Value, scope: &Value, items: &[Value]) -> Value {
+        match self {
+            Signal::Traces => json!({ "resourceSpans": [{ "resource": resource, "scopeSpans": [{ "scope": scope, "spans": items }] }] }),
+            Signal::Logs => json!({ "resourceLogs": [{ "resource": resource, "scopeLogs": [{ "scope": scope, "logRecords": items }] }] }),
+            Signal::Metrics => json!({ "resourceMetrics": [{ "resource": resource, "scopeMetrics": [{ "scope": scope, "metrics": items }] }] }),
+        }
+    }
+}
+
+struct Endpoint {
+    host: String,
+    port: u16,
+    base_path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> BotResult<Self> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| BotError::Config(format!("OTLP endpoint '{}' must be an http:// URL", url)))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| BotError::Config(format!("Invalid OTLP endpoint port in '{}'", url)))?),
+            None => (authority, 4318),
+        };
+        if host.is_empty() {
+            return Err(BotError::Config(format!("OTLP endpoint '{}' has no host", url)));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            base_path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/// Sends spans, logs and metrics to an OpenTelemetry collector using OTLP/HTTP with JSON encoding.
+#[derive(Clone)]
+pub struct OtlpExporter {
+    endpoint: Arc<Endpoint>,
+    headers: HashMap<String, String>,
+    timeout: Duration,
+    interval: Duration,
+    service_name: String,
+    shard: Arc<Mutex<Option<(u64, u64)>>>,
+    buffers: Arc<Mutex<Buffers>>,
+    // Command counters and durations are read from here on every export.
+    metrics: Option<Arc<Metrics>>,
+    started: u128,
+}
+
+impl OtlpExporter {
+    pub fn new(config: &OtlpConfig, service_name: &str) -> BotResult<Self> {
+        Ok(Self {
+            endpoint: Arc::new(Endpoint::parse(&config.endpoint)?),
+            headers: config.headers.clone(),
+            timeout: Duration::from_secs(config.timeout_secs),
+            interval: Duration::from_secs(config.export_interval_secs),
+            service_name: service_name.to_string(),
+            shard: Arc::new(Mutex::new(None)),
+            buffers: Arc::new(Mutex::new(Buffers::default())),
+            metrics: None,
+            started: unix_nanos(),
+        })
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub async fn set_shard(&self, shard_id: u64, shard_count: u64) {
+        *self.shard.lock().await = Some((shard_id, shard_count));
+    }
+
+    pub async fn record_span(&self, span: CommandSpan, outcome: CommandOutcome) {
+        let mut buffers = self.buffers.lock().await;
+        let Buffers { spans, dropped, .. } = &mut *buffers;
+        Buffers::push(spans, dropped, span.finish(outcome));
+    }
+
+    pub async fn record_event(&self, event_type: &str, data: &Value) {
+        let attributes: Vec<Value> = match data {
+            Value::Object(fields) => fields.iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| attribute(key, value))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let record = json!({
+            "timeUnixNano": unix_nanos().to_string(),
+            "severityText": if event_type == "error" { "ERROR" } else { "INFO" },
+            "body": { "stringValue": event_type },
+            "attributes": attributes,
+        });
+        let mut buffers = self.buffers.lock().await;
+        let Buffers { logs, dropped, .. } = &mut *buffers;
+        Buffers::push(logs, dropped, std::iter::once(record));
+    }
+
+    pub async fn record_gauge(&self, name: &str, value: f64) {
+        let metric = json!({
+            "name": name,
+            "gauge": {
+                "dataPoints": [{ "timeUnixNano": unix_nanos().to_string(), "asDouble": value }]
+            }
+        });
+        let mut buffers = self.buffers.lock().await;
+        let Buffers { metrics, dropped, .. } = &mut *buffers;
+        Buffers::push(metrics, dropped, std::iter::once(metric));
+    }
+
+    async fn resource(&self) -> Value {
+        let mut attributes = vec![
+            attribute("service.name", &json!(self.service_name)),
+            attribute("service.version", &json!(env!("CARGO_PKG_VERSION"))),
+            attribute("bot.name", &json!(self.service_name)),
+        ];
+        if let Some((shard_id, shard_count)) = *self.shard.lock().await {
+            attributes.push(attribute("discord.shard.id", &json!(shard_id)));
+            attributes.push(attribute("discord.shard.count", &json!(shard_count)));
+        }
+        json!({ "attributes": attributes })
+    }
+
+    // Cumulative sums and summaries of the command metrics collected so far.
+    async fn command_metrics(&self, metrics: &Metrics) -> Vec<Value> {
+        let now = unix_nanos().to_string();
+        let started = self.started.to_string();
+        let mut result = Vec::new();
+
+        let outcomes = metrics.get_command_outcomes().await;
+        if !outcomes.is_empty() {
+            let points: Vec<Value> = outcomes.iter().map(|(labels, count)| {
+                let mut attributes = vec![
+                    attribute("command", &json!(labels.command)),
+                    attribute("context", &json!(labels.context())),
+                    attribute("outcome", &json!(labels.outcome.as_str())),
+                ];
+                if let Some(guild_id) = labels.guild_id {
+                    attributes.push(attribute("guild", &json!(guild_id.to_string())));
+                }
+                json!({
+                    "startTimeUnixNano": started,
+                    "timeUnixNano": now,
+                    "asInt": count.to_string(),
+                    "attributes": attributes,
+                })
+            }).collect();
+            result.push(json!({
+                "name": "commands_total",
+                "sum": {
+                    "dataPoints": points,
+                    "aggregationTemporality": AGGREGATION_TEMPORALITY_CUMULATIVE,
+                    "isMonotonic": true,
+                }
+            }));
+        }
+
+        let durations = metrics.get_command_durations().await;
+        if !durations.is_empty() {
+            let points: Vec<Value> = durations.iter()
+                .map(|(command, snapshot)| summary_point(command, snapshot, &started, &now))
+                .collect();
+            result.push(json!({
+                "name": "command_duration_milliseconds",
+                "unit": "ms",
+                "summary": { "dataPoints": points }
+            }));
+        }
+        result
+    }
+
+    pub async fn flush(&self) -> BotResult<()> {
+        let (spans, logs, metrics, dropped) = {
+            let mut buffers = self.buffers.lock().await;
+            let taken = std::mem::take(&mut *buffers);
+            (taken.spans, taken.logs, taken.metrics, taken.dropped)
+        };
+        if dropped > 0 {
+            log::warn!("Dropped {} OTLP items because the export buffer was full", dropped);
+        }
+        // Only buffered items are re-queued on failure; the command metrics are cumulative and
+        // are read again on the next export.
+        let command_metrics = match &self.metrics {
+            Some(source) => self.command_metrics(source).await,
+            None => Vec::new(),
+        };
+
+        let resource = self.resource().await;
+        let scope = json!({ "name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION") });
+        let mut result = Ok(());
+
+        for (signal, items, extra) in [
+            (Signal::Traces, spans, Vec::new()),
+            (Signal::Logs, logs, Vec::new()),
+            (Signal::Metrics, metrics, command_metrics),
+        ] {
+            if items.is_empty() && extra.is_empty() {
+                continue;
+            }
+            let mut all = items.clone();
+            all.extend(extra);
+            if let Err(why) = self.post(signal.path(), &signal.body(&resource, &scope, &all)).await {
+                let mut buffers = self.buffers.lock().await;
+                let (buffered, dropped) = buffers.items(signal);
+                Buffers::requeue(buffered, dropped, items);
+                result = result.and(Err(why));
+            }
+        }
+        result
+    }
+
+    /// Exports every `export_interval_secs` until shutdown; the final export is left to the
+    /// last `flush`.
+    pub async fn run(&self, shutdown: ShutdownToken) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => {}
+                _ = shutdown.cancelled() => return,
+            }
+            if let Err(e) = self.flush().await {
+                log::warn!("Failed to export telemetry over OTLP: {:?}", e);
+            }
+        }
+    }
+
+    async fn post(&self, signal_path: &str, body: &Value) -> BotResult<()> {
+        tokio::time::timeout(self.timeout, self.send(signal_path, body))
+            .await
+            .map_err(|_| BotError::Api(format!("OTLP export to {} timed out", signal_path)))?
+    }
+
+    async fn send(&self, signal_path: &str, body: &Value) -> BotResult<()> {
+        let endpoint = &self.endpoint;
+        let payload = serde_json::to_vec(body)?;
+        let mut request = format!(
+            "POST {}{} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            endpoint.base_path, signal_path, endpoint.host, endpoint.port, payload.len()
+        );
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+
+        let mut stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port)).await?;
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(&payload).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let response = String::from_utf8_lossy(&response);
+        let status = response.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+        match status {
+            Some(code) if (200..300).contains(&code) => Ok(()),
+            Some(code) => Err(BotError::Api(format!("OTLP collector rejected {} with status {}", signal_path, code))),
+            None => Err(BotError::Api(format!("OTLP collector sent an invalid response for {}", signal_path))),
+        }
+    }
+}
+
+fn summary_point(command: &str, snapshot: &HistogramSnapshot, started: &str, now: &str) -> Value {
+    json!({
+        "startTimeUnixNano": started,
+        "timeUnixNano": now,
+        "count": snapshot.count.to_string(),
+        "sum": snapshot.sum as f64,
+        "quantileValues": [
+            { "quantile": 0.5, "value": snapshot.p50 },
+            { "quantile": 0.95, "value": snapshot.p95 },
+            { "quantile": 0.99, "value": snapshot.p99 },
+        ],
+        "attributes": [attribute("command", &json!(command))],
+    })
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    json!({ "key": key, "value": any_value(value) })
+}
+
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::String(text) => json!({ "stringValue": text }),
+        Value::Bool(flag) => json!({ "boolValue": flag }),
+        // OTLP/JSON encodes 64-bit integers as strings.
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => json!({ "intValue": integer.to_string() }),
+            None => json!({ "doubleValue": number.as_f64().unwrap_or_default() }),
+        },
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn exporter(port: u16) -> OtlpExporter {
+        let config = OtlpConfig {
+            enabled: true,
+            endpoint: format!("http://127.0.0.1:{}", port),
+            headers: HashMap::new(),
+            timeout_secs: 5,
+            export_interval_secs: 10,
+        };
+        OtlpExporter::new(&config, "test-bot").unwrap()
+    }
+
+    // Answers `count` requests with 200 and returns each request's path and JSON body.
+    async fn collect(listener: TcpListener, count: usize) -> Vec<(String, Value)> {
+        let mut requests = Vec::new();
+        for _ in 0..count {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            let (head_end, length) = loop {
+                let n = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+                if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&request[..end]).to_string();
+                    let length = head.lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .and_then(|length| length.parse::<usize>().ok())
+                        .unwrap();
+                    break (end + 4, length);
+                }
+            };
+            while request.len() < head_end + length {
+                let n = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            stream.shutdown().await.unwrap();
+
+            let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+            let path = head.split_whitespace().nth(1).unwrap().to_string();
+            requests.push((path, serde_json::from_slice(&request[head_end..]).unwrap()));
+        }
+        requests
+    }
+
+    #[tokio::test]
+    async fn exports_traces_logs_and_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let collector = tokio::spawn(collect(listener, 3));
+
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_command("ping", Some(1), CommandOutcome::Ok, Duration::from_millis(12)).await;
+        let exporter = exporter(port).with_metrics(metrics);
+        let mut span = CommandSpan::start("ping", Some(1));
+        span.phase("dispatch");
+        exporter.record_span(span, CommandOutcome::Ok).await;
+        exporter.record_event("command_used", &json!({ "command": "ping" })).await;
+        exporter.record_gauge("connected_guilds", 3.0).await;
+        exporter.flush().await.unwrap();
+
+        let requests = collector.await.unwrap();
+        let paths: Vec<&str> = requests.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["/v1/traces", "/v1/logs", "/v1/metrics"]);
+
+        let spans = &requests[0].1["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["name"], "command ping");
+        assert_eq!(spans[1]["name"], "dispatch");
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+
+        let logs = &requests[1].1["resourceLogs"][0]["scopeLogs"][0]["logRecords"];
+        assert_eq!(logs[0]["body"]["stringValue"], "command_used");
+
+        let exported = &requests[2].1["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        let names: Vec<&str> = exported.as_array().unwrap().iter().map(|metric| metric["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["connected_guilds", "commands_total", "command_duration_milliseconds"]);
+        assert_eq!(exported[1]["sum"]["dataPoints"][0]["asInt"], "1");
+        assert_eq!(exported[1]["sum"]["isMonotonic"], true);
+        assert_eq!(exported[2]["summary"]["dataPoints"][0]["count"], "1");
+    }
+
+    #[tokio::test]
+    async fn failed_exports_are_requeued() {
+        // Bind and drop a listener to find a port nothing is listening on.
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let exporter = exporter(port);
+        exporter.record_gauge("first", 1.0).await;
+        assert!(exporter.flush().await.is_err());
+        exporter.record_gauge("second", 2.0).await;
+
+        let buffers = exporter.buffers.lock().await;
+        let names: Vec<&str> = buffers.metrics.iter().map(|metric| metric["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["first", "second"]);
+    }
+
+    #[test]
+    fn requeue_keeps_the_newest_items_within_the_cap() {
+        let mut items = vec![json!("new"); MAX_BUFFERED - 1];
+        let mut dropped = 0;
+        Buffers::requeue(&mut items, &mut dropped, vec![json!("oldest"), json!("old")]);
+
+        assert_eq!(items.len(), MAX_BUFFERED);
+        assert_eq!(items[0], "old");
+        assert_eq!(dropped, 1);
+    }
+}
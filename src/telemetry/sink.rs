@@ -0,0 +1,276 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::bot::error::{BotResult, BotError};
+use crate::config::TelemetryConfig;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+struct SinkState {
+    size: u64,
+    opened_on: NaiveDate,
+}
+
+/// Appends JSON Lines to `log_file`, rotating it by size and/or day and keeping `keep_files`
+/// rotated files. All file work happens on the blocking pool.
+#[derive(Clone)]
+pub struct JsonLinesSink {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    rotate_daily: bool,
+    keep_files: usize,
+    compress: bool,
+    state: Arc<Mutex<SinkState>>,
+}
+
+impl JsonLinesSink {
+    pub fn new(config: &TelemetryConfig) -> BotResult<Self> {
+        let path = PathBuf::from(&config.log_file);
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let (size, opened_on) = match fs::metadata(&path) {
+            Ok(metadata) => {
+                let modified: DateTime<Utc> = metadata.modified().map(DateTime::from).unwrap_or_else(|_| Utc::now());
+                (metadata.len(), modified.date_naive())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (0, Utc::now().date_naive()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            path,
+            max_bytes: Some(config.max_file_size_mb * BYTES_PER_MB).filter(|&bytes| bytes > 0),
+            rotate_daily: config.rotate_daily,
+            keep_files: config.keep_files,
+            compress: config.compress,
+            state: Arc::new(Mutex::new(SinkState { size, opened_on })),
+        })
+    }
+
+    pub async fn write_lines(&self, lines: Vec<String>) -> BotResult<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let sink = self.clone();
+        tokio::task::spawn_blocking(move || sink.write_lines_blocking(&lines, Utc::now()))
+            .await
+            .map_err(|e| BotError::Internal(format!("Telemetry writer panicked: {}", e)))?
+    }
+
+    fn write_lines_blocking(&self, lines: &[String], now: DateTime<Utc>) -> BotResult<()> {
+        let mut payload = lines.join("\n");
+        payload.push('\n');
+
+        let mut state = self.state.lock().unwrap();
+        let too_big = self.max_bytes.map_or(false, |max| state.size + payload.len() as u64 > max);
+        let new_day = self.rotate_daily && now.date_naive() != state.opened_on;
+        if state.size > 0 && (too_big || new_day) {
+            self.rotate(now)?;
+            state.size = 0;
+        }
+        if state.size == 0 {
+            state.opened_on = now.date_naive();
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(payload.as_bytes())?;
+        file.flush()?;
+        state.size += payload.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self, now: DateTime<Utc>) -> BotResult<()> {
+        let rotated = self.rotated_path(now);
+        match fs::rename(&self.path, &rotated) {
+            Ok(()) if self.compress => compress_file(&rotated)?,
+            Ok(()) => {}
+            // Someone removed the file; the next write creates it again.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::warn!("Telemetry file {} disappeared, starting a new one", self.path.display());
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.apply_retention()
+    }
+
+    fn rotated_path(&self, now: DateTime<Utc>) -> PathBuf {
+        let (stem, extension) = self.file_name_parts();
+        let name = format!("{}.{}{}", stem, now.format("%Y%m%dT%H%M%S%.3fZ"), extension);
+        self.path.with_file_name(name)
+    }
+
+    fn file_name_parts(&self) -> (String, String) {
+        let stem = self.path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("telemetry").to_string();
+        let extension = self.path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| format!(".{}", extension))
+            .unwrap_or_default();
+        (stem, extension)
+    }
+
+    fn apply_retention(&self) -> BotResult<()> {
+        let directory = match self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            Some(parent) => parent.to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let (stem, extension) = self.file_name_parts();
+        let prefix = format!("{}.", stem);
+        let current = self.path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+
+        // Rotated names embed a UTC timestamp, so sorting by name sorts them by age.
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+                name != current
+                    && name.starts_with(&prefix)
+                    && (name.ends_with(&extension) || name.ends_with(&format!("{}.gz", extension)))
+            })
+            .collect();
+        rotated.sort();
+
+        let excess = rotated.len().saturating_sub(self.keep_files);
+        for path in rotated.into_iter().take(excess) {
+            if let Err(why) = fs::remove_file(&path) {
+                log::warn!("Failed to remove old telemetry file {}: {}", path.display(), why);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn compress_file(path: &Path) -> BotResult<()> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".gz");
+    let compressed = PathBuf::from(compressed);
+
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(&compressed)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use chrono::TimeZone;
+    use flate2::read::GzDecoder;
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn sink(path: &Path, max_bytes: Option<u64>, rotate_daily: bool, keep_files: usize, compress: bool) -> JsonLinesSink {
+        JsonLinesSink {
+            path: path.to_path_buf(),
+            max_bytes,
+            rotate_daily,
+            keep_files,
+            compress,
+            state: Arc::new(Mutex::new(SinkState { size: 0, opened_on: at(1, 0).date_naive() })),
+        }
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, day, hour, 0, 0).unwrap()
+    }
+
+    fn lines(line: &str) -> Vec<String> {
+        vec![line.to_string()]
+    }
+
+    // File names in the directory other than the current file, oldest first.
+    fn rotated_files(path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(path.parent().unwrap()).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.as_str() != "telemetry.log")
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn files_rotate_when_they_would_grow_past_the_limit() {
+        let path = temp_dir().join("telemetry.log");
+        let sink = sink(&path, Some(16), false, 5, false);
+        sink.write_lines_blocking(&lines("{\"n\":1}"), at(1, 1)).unwrap();
+        sink.write_lines_blocking(&lines("{\"n\":2}"), at(1, 2)).unwrap();
+        assert!(rotated_files(&path).is_empty());
+
+        sink.write_lines_blocking(&lines("{\"n\":3}"), at(1, 3)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":3}\n");
+        let rotated = rotated_files(&path);
+        assert_eq!(rotated, ["telemetry.20261001T030000.000Z.log"]);
+        let rotated = fs::read_to_string(path.with_file_name(&rotated[0])).unwrap();
+        assert_eq!(rotated, "{\"n\":1}\n{\"n\":2}\n");
+    }
+
+    #[test]
+    fn files_rotate_on_a_new_day() {
+        let path = temp_dir().join("telemetry.log");
+        let sink = sink(&path, None, true, 5, false);
+        sink.write_lines_blocking(&lines("first"), at(1, 1)).unwrap();
+        sink.write_lines_blocking(&lines("second"), at(1, 23)).unwrap();
+        assert!(rotated_files(&path).is_empty());
+
+        sink.write_lines_blocking(&lines("third"), at(2, 0)).unwrap();
+        assert_eq!(rotated_files(&path), ["telemetry.20261002T000000.000Z.log"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+    }
+
+    #[test]
+    fn rotated_files_are_compressed() {
+        let path = temp_dir().join("telemetry.log");
+        let sink = sink(&path, Some(8), false, 5, true);
+        sink.write_lines_blocking(&lines("{\"n\":1}"), at(1, 1)).unwrap();
+        sink.write_lines_blocking(&lines("{\"n\":2}"), at(1, 2)).unwrap();
+
+        let rotated = rotated_files(&path);
+        assert_eq!(rotated, ["telemetry.20261001T020000.000Z.log.gz"]);
+        let mut contents = String::new();
+        GzDecoder::new(File::open(path.with_file_name(&rotated[0])).unwrap()).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "{\"n\":1}\n");
+    }
+
+    #[test]
+    fn retention_counts_plain_and_compressed_files() {
+        let path = temp_dir().join("telemetry.log");
+        for name in [
+            "telemetry.20261001T000000.000Z.log",
+            "telemetry.20261002T000000.000Z.log.gz",
+            "telemetry.20261003T000000.000Z.log",
+            "telemetry.20261004T000000.000Z.log.gz",
+            "telemetry.log",
+            "other.log",
+        ] {
+            fs::write(path.with_file_name(name), "x").unwrap();
+        }
+
+        sink(&path, None, false, 2, true).apply_retention().unwrap();
+        assert_eq!(rotated_files(&path), [
+            "other.log",
+            "telemetry.20261003T000000.000Z.log",
+            "telemetry.20261004T000000.000Z.log.gz",
+        ]);
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn a_deleted_file_is_recreated_on_rotation() {
+        let path = temp_dir().join("telemetry.log");
+        let sink = sink(&path, Some(16), false, 2, false);
+
+        sink.write_lines(lines("{\"n\":1}")).await.unwrap();
+        fs::remove_file(&path).unwrap();
+        sink.write_lines(lines("{\"n\":2,\"padding\":true}")).await.unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":2,\"padding\":true}\n");
+    }
+}
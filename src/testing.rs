@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use arc_swap::ArcSwap;
+use tokio::sync::broadcast;
+use crate::backup::BackupManager;
+use crate::bot::Bot;
+use crate::config::{ConfigLoader, ConfigWatcher};
+use crate::database::repositories::Repositories;
+use crate::lang::Lang;
+use crate::plugins::PluginManager;
+use crate::security::SecurityManager;
+use crate::telemetry::TelemetryManager;
+use crate::utils::cache::Cache;
+use crate::utils::event_bus::EventBus;
+use crate::utils::guild_data::GuildData;
+use crate::utils::metrics::Metrics;
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::scheduler::Scheduler;
+use crate::utils::settings::SettingsRegistry;
+use crate::utils::shutdown::ShutdownCoordinator;
+use crate::utils::task_manager::TaskManager;
+
+static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+
+/// A new empty directory under the system temp directory.
+pub fn temp_dir() -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "discord-bot-test-{}-{}",
+        std::process::id(),
+        NEXT_DIRECTORY.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Writes `directory/config.toml` with the settings that have no default, followed by `extra`.
+pub fn write_config(directory: &Path, extra: &str) -> PathBuf {
+    let path = directory.join("config.toml");
+    let contents = format!(
+        "[discord]\ntoken = \"test-token\"\napplication_id = 1\n\n[database]\nurl = \"sqlite::memory:\"\n\n[backup]\ndirectory = \"{}\"\n\n{}\n",
+        directory.join("backups").display(),
+        extra
+    );
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// A `Bot` on `Repositories::in_memory()`, configured by `write_config` with `extra`. The
+/// environment is ignored, so `BOT__*` variables on the machine running the tests have no effect.
+pub fn test_bot(extra: &str) -> Bot {
+    let directory = temp_dir();
+    let path = write_config(&directory, extra);
+    let config_watcher = Arc::new(ConfigWatcher::new(ConfigLoader::from_vars(HashMap::new()).with_path(path)).unwrap());
+    let config = config_watcher.current();
+
+    let repositories = Repositories::in_memory();
+    let metrics = Arc::new(Metrics::new());
+    let task_manager = Arc::new(TaskManager::new(5));
+    let rate_limiter = Arc::new(RateLimiter::new());
+    let lang = Arc::new(ArcSwap::from_pointee(Lang::load(&config.bot.default_language).unwrap()));
+    let settings_registry = Arc::new(SettingsRegistry::with_core_settings(&lang.load()));
+    let (event_sender, _) = broadcast::channel(100);
+
+    Bot::new(
+        Arc::clone(&config_watcher),
+        repositories.clone(),
+        Arc::clone(&metrics),
+        Arc::new(Cache::new(100)),
+        Arc::clone(&task_manager),
+        Arc::clone(&rate_limiter),
+        Arc::new(GuildData::new(Arc::clone(&repositories.guild_settings), settings_registry)),
+        lang,
+        Arc::new(EventBus::new(event_sender, Arc::clone(&metrics))),
+        Arc::new(PluginManager::new()),
+        Arc::new(SecurityManager::new(rate_limiter)),
+        Arc::new(TelemetryManager::new(&config.telemetry, &config.bot.name).unwrap()),
+        Arc::new(BackupManager::new(&config.backup, repositories.clone()).unwrap()),
+        Arc::new(ShutdownCoordinator::new()),
+        Arc::new(Scheduler::new(Arc::clone(&repositories.scheduled_jobs), task_manager, metrics)),
+    )
+}
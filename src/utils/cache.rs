@@ -1,17 +1,95 @@
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex as SyncMutex};
+use tokio::sync::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::future::Future;
 use std::time::{Duration, Instant};
 use std::hash::Hash;
+use crate::utils::metrics::{CacheEvent, Metrics};
 
 pub struct CacheEntry<T> {
     data: T,
-    expires_at: Instant,
+    expires_at: Option<Instant>,
+    recency: u64,
+    id: u64,
+}
+
+struct CacheState<K, V> {
+    entries: HashMap<K, CacheEntry<V>>,
+    // Least recently used entries come first.
+    recency: BTreeMap<u64, K>,
+    // Soonest expiring entries come first, so cleanup only visits what has expired.
+    expiries: BTreeMap<(Instant, u64), K>,
+    next_tick: u64,
 }
 
 pub struct Cache<K, V> {
-    data: Arc<RwLock<HashMap<K, CacheEntry<V>>>>,
-    ttl: Duration,
+    name: String,
+    state: Arc<Mutex<CacheState<K, V>>>,
+    // Only held briefly and never across an await, so `LoadingGuard` can clean up on drop.
+    loading: Arc<SyncMutex<HashMap<K, Arc<Mutex<()>>>>>,
+    capacity: usize,
+    ttl: Option<Duration>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl<K, V> CacheState<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    fn touch(&mut self, key: &K) {
+        let tick = self.tick();
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.recency);
+            entry.recency = tick;
+            self.recency.insert(tick, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<CacheEntry<V>> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.recency);
+        if let Some(expires_at) = entry.expires_at {
+            self.expiries.remove(&(expires_at, entry.id));
+        }
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: K, data: V, expires_at: Option<Instant>) {
+        self.remove(&key);
+        let tick = self.tick();
+        self.recency.insert(tick, key.clone());
+        if let Some(expires_at) = expires_at {
+            self.expiries.insert((expires_at, tick), key.clone());
+        }
+        self.entries.insert(key, CacheEntry { data, expires_at, recency: tick, id: tick });
+    }
+
+    fn remove_expired(&mut self, now: Instant) -> usize {
+        let mut removed = 0;
+        while let Some((&(expires_at, _), key)) = self.expiries.iter().next() {
+            if expires_at > now {
+                break;
+            }
+            let key = key.clone();
+            self.remove(&key);
+            removed += 1;
+        }
+        removed
+    }
+
+    fn evict_lru(&mut self) -> bool {
+        let key = match self.recency.values().next() {
+            Some(key) => key.clone(),
+            None => return false,
+        };
+        self.remove(&key).is_some()
+    }
 }
 
 impl<K, V> Cache<K, V>
@@ -19,56 +97,228 @@ where
     K: Eq + Hash + Clone,
     V: Clone,
 {
-    pub fn new(ttl: Duration) -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
-            ttl,
+            name: "default".to_string(),
+            state: Arc::new(Mutex::new(CacheState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                expiries: BTreeMap::new(),
+                next_tick: 0,
+            })),
+            loading: Arc::new(SyncMutex::new(HashMap::new())),
+            capacity: capacity.max(1),
+            ttl: None,
+            metrics: None,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_metrics(mut self, name: &str, metrics: Arc<Metrics>) -> Self {
+        self.name = name.to_string();
+        self.metrics = Some(metrics);
+        self
+    }
+
+    async fn record(&self, event: CacheEvent, count: usize) {
+        if let (Some(metrics), true) = (&self.metrics, count > 0) {
+            metrics.record_cache_event(&self.name, event, count as u64).await;
         }
     }
 
     pub async fn get(&self, key: &K) -> Option<V> {
-        let data = self.data.read().await;
-        if let Some(entry) = data.get(key) {
-            if entry.expires_at > Instant::now() {
-                return Some(entry.data.clone());
+        let value = self.lookup(key).await;
+        self.record(if value.is_some() { CacheEvent::Hit } else { CacheEvent::Miss }, 1).await;
+        value
+    }
+
+    // Like `get`, but leaves counting hits and misses to the caller.
+    async fn lookup(&self, key: &K) -> Option<V> {
+        let now = Instant::now();
+        let (value, expired) = {
+            let mut state = self.state.lock().await;
+            match state.entries.get(key).map(|entry| entry.expires_at.map_or(false, |at| at <= now)) {
+                Some(true) => {
+                    state.remove(key);
+                    (None, true)
+                }
+                Some(false) => {
+                    state.touch(key);
+                    (state.entries.get(key).map(|entry| entry.data.clone()), false)
+                }
+                None => (None, false),
             }
+        };
+
+        if expired {
+            self.record(CacheEvent::Expiration, 1).await;
         }
-        None
+        value
     }
 
     pub async fn set(&self, key: K, value: V) {
-        let mut data = self.data.write().await;
-        data.insert(key, CacheEntry {
-            data: value,
-            expires_at: Instant::now() + self.ttl,
-        });
+        self.insert(key, value, self.ttl).await;
+    }
+
+    pub async fn set_with_ttl(&self, key: K, value: V, ttl: Option<Duration>) {
+        self.insert(key, value, ttl).await;
+    }
+
+    async fn insert(&self, key: K, value: V, ttl: Option<Duration>) {
+        let now = Instant::now();
+        let (expired, evicted) = {
+            let mut state = self.state.lock().await;
+            let mut expired = 0;
+            let mut evicted = 0;
+            if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+                expired = state.remove_expired(now);
+                while state.entries.len() >= self.capacity && state.evict_lru() {
+                    evicted += 1;
+                }
+            }
+            state.insert(key, value, ttl.map(|ttl| now + ttl));
+            (expired, evicted)
+        };
+
+        self.record(CacheEvent::Expiration, expired).await;
+        self.record(CacheEvent::Eviction, evicted).await;
     }
 
     pub async fn remove(&self, key: &K) {
-        let mut data = self.data.write().await;
-        data.remove(key);
+        let mut state = self.state.lock().await;
+        state.remove(key);
     }
 
     pub async fn clear(&self) {
-        let mut data = self.data.write().await;
-        data.clear();
+        let mut state = self.state.lock().await;
+        state.entries.clear();
+        state.recency.clear();
+        state.expiries.clear();
+    }
+
+    pub async fn len(&self) -> usize {
+        self.state.lock().await.entries.len()
     }
 
     pub async fn cleanup(&self) {
-        let mut data = self.data.write().await;
-        data.retain(|_, v| v.expires_at > Instant::now());
+        let removed = self.state.lock().await.remove_expired(Instant::now());
+        self.record(CacheEvent::Expiration, removed).await;
+    }
+
+    /// Loads a missing value once, even when several callers miss the same key at the same time.
+    pub async fn get_or_load<F, Fut, E>(&self, key: K, loader: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        if let Some(value) = self.lookup(&key).await {
+            self.record(CacheEvent::Hit, 1).await;
+            return Ok(value);
+        }
+
+        let loading = LoadingGuard::new(Arc::clone(&self.loading), key);
+        let _guard = loading.lock.lock().await;
+
+        // Whoever held the lock before us may have filled the entry already.
+        if let Some(value) = self.lookup(&loading.key).await {
+            self.record(CacheEvent::Hit, 1).await;
+            return Ok(value);
+        }
+        self.record(CacheEvent::Miss, 1).await;
+
+        let result = loader().await;
+        if let Ok(value) = &result {
+            self.set(loading.key.clone(), value.clone()).await;
+        }
+        result
     }
 
     pub async fn get_or_insert_with<F>(&self, key: K, f: F) -> V
     where
         F: FnOnce() -> V,
     {
-        if let Some(value) = self.get(&key).await {
-            return value;
+        match self.get_or_load(key, move || async move { Ok::<V, Infallible>(f()) }).await {
+            Ok(value) => value,
+            Err(never) => match never {},
         }
+    }
+}
 
-        let value = f();
-        self.set(key, value.clone()).await;
-        value
+// Shares a per-key load lock between concurrent callers and removes it from the map once the
+// last of them is done, including callers whose future was dropped while waiting.
+struct LoadingGuard<K: Eq + Hash> {
+    loading: Arc<SyncMutex<HashMap<K, Arc<Mutex<()>>>>>,
+    key: K,
+    lock: Arc<Mutex<()>>,
+}
+
+impl<K: Eq + Hash + Clone> LoadingGuard<K> {
+    fn new(loading: Arc<SyncMutex<HashMap<K, Arc<Mutex<()>>>>>, key: K) -> Self {
+        let lock = Arc::clone(loading.lock().unwrap().entry(key.clone()).or_insert_with(|| Arc::new(Mutex::new(()))));
+        Self { loading, key, lock }
+    }
+}
+
+impl<K: Eq + Hash> Drop for LoadingGuard<K> {
+    fn drop(&mut self) {
+        let mut loading = self.loading.lock().unwrap();
+        // One reference lives in the map and one is ours, anything more is a waiting caller.
+        if Arc::strong_count(&self.lock) <= 2 {
+            loading.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn concurrent_misses_load_once_and_count_one_miss() {
+        let metrics = Arc::new(Metrics::new());
+        let cache = Cache::new(10).with_metrics("users", Arc::clone(&metrics));
+        let loads = &AtomicUsize::new(0);
+        let load = move || async move {
+            loads.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            Ok::<_, Infallible>(42)
+        };
+
+        let (first, second) = tokio::join!(cache.get_or_load(1u64, load), cache.get_or_load(1u64, load));
+        assert_eq!((first.unwrap(), second.unwrap()), (42, 42));
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        let stats = metrics.get_cache_stats().await["users"];
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert!(cache.loading.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_load_does_not_leave_its_lock_behind() {
+        let cache: Cache<u64, u64> = Cache::new(10);
+        let pending = cache.get_or_load(1, || std::future::pending::<Result<u64, Infallible>>());
+        assert!(tokio::time::timeout(Duration::from_millis(10), pending).await.is_err());
+        assert!(cache.loading.lock().unwrap().is_empty());
+
+        assert_eq!(cache.get_or_load(1, || async { Ok::<_, Infallible>(7) }).await.unwrap(), 7);
+        assert!(cache.loading.lock().unwrap().is_empty());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn least_recently_used_entries_are_evicted_first() {
+        let cache = Cache::new(2);
+        cache.set(1, "one").await;
+        cache.set(2, "two").await;
+        cache.get(&1).await;
+        cache.set(3, "three").await;
+
+        assert_eq!(cache.get(&1).await, Some("one"));
+        assert_eq!(cache.get(&2).await, None);
+        assert_eq!(cache.len().await, 2);
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use crate::bot::error::{BotResult, BotError};
+
+const MONTH_NAMES: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+// An expression such as `0 0 30 2 *` never matches, so the search has to stop somewhere.
+const SEARCH_LIMIT_DAYS: i64 = 366 * 5;
+
+/// A standard five-field cron expression (`minute hour day-of-month month day-of-week`),
+/// evaluated in UTC. Supports `*`, lists, ranges, steps, month and weekday names and the
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shorthands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpression {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Cron matches a day if *either* day field matches when both are restricted.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for CronExpression {
+    type Err = BotError;
+
+    fn from_str(source: &str) -> BotResult<Self> {
+        let source = source.trim();
+        let expression = match source {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(BotError::Config(format!("Cron expression '{}' must have 5 fields", source)));
+        }
+
+        let mut weekdays = parse_field(source, fields[4], 0, 7, WEEKDAY_NAMES, 0)?;
+        // Both 0 and 7 mean Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            minutes: parse_field(source, fields[0], 0, 59, &[], 0)?,
+            hours: parse_field(source, fields[1], 0, 23, &[], 0)?,
+            days: parse_field(source, fields[2], 1, 31, &[], 0)?,
+            months: parse_field(source, fields[3], 1, 12, MONTH_NAMES, 1)?,
+            weekdays,
+            any_day: fields[2] == "*" || fields[2] == "?",
+            any_weekday: fields[4] == "*" || fields[4] == "?",
+        })
+    }
+}
+
+impl fmt::Display for CronExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl CronExpression {
+    /// The first matching minute strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let limit = after + Duration::days(SEARCH_LIMIT_DAYS);
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+
+        while time <= limit {
+            if !contains(self.months, time.month()) {
+                let (year, month) = if time.month() == 12 { (time.year() + 1, 1) } else { (time.year(), time.month() + 1) };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+                continue;
+            }
+            if !self.day_matches(time.date_naive()) {
+                time = Utc.from_utc_datetime(&time.date_naive().succ_opt()?.and_hms_opt(0, 0, 0)?);
+                continue;
+            }
+            if !contains(self.hours, time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+                continue;
+            }
+            if !contains(self.minutes, time.minute()) {
+                time = time + Duration::minutes(1);
+                continue;
+            }
+            return Some(time);
+        }
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = contains(self.days, date.day());
+        let weekday = contains(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+fn contains(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+fn parse_field(source: &str, field: &str, min: u32, max: u32, names: &[&str], name_offset: u32) -> BotResult<u64> {
+    let invalid = |why: &str| BotError::Config(format!("Invalid cron field '{}' in '{}': {}", field, source, why));
+    let value = |text: &str| -> BotResult<u32> {
+        let lower = text.to_ascii_lowercase();
+        match names.iter().position(|name| *name == lower) {
+            Some(index) => Ok(index as u32 + name_offset),
+            None => text.parse().map_err(|_| invalid(&format!("'{}' is not a number", text))),
+        }
+    };
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>().ok().filter(|step| *step > 0).ok_or_else(|| invalid("step must be a positive number"))?)),
+            None => (part, None),
+        };
+        let (start, end) = if range == "*" || range == "?" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else {
+            // `5/15` means "every 15 starting at 5".
+            let start = value(range)?;
+            (start, if step.is_some() { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid(&format!("values must be between {} and {}", min, max)));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(values: &[u32]) -> u64 {
+        values.iter().fold(0, |bits, value| bits | (1 << value))
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn fields_support_steps_ranges_and_lists() {
+        assert_eq!(parse_field("", "*/15", 0, 59, &[], 0).unwrap(), bits(&[0, 15, 30, 45]));
+        assert_eq!(parse_field("", "5/15", 0, 59, &[], 0).unwrap(), bits(&[5, 20, 35, 50]));
+        assert_eq!(parse_field("", "1-10/3", 0, 59, &[], 0).unwrap(), bits(&[1, 4, 7, 10]));
+        assert_eq!(parse_field("", "1,3-4", 0, 59, &[], 0).unwrap(), bits(&[1, 3, 4]));
+        assert_eq!(parse_field("", "jan-mar", 1, 12, MONTH_NAMES, 1).unwrap(), bits(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        assert!(parse_field("", "*/0", 0, 59, &[], 0).is_err());
+        assert!(parse_field("", "60", 0, 59, &[], 0).is_err());
+        assert!(parse_field("", "10-5", 0, 59, &[], 0).is_err());
+        assert!(parse_field("", "abc", 0, 59, &[], 0).is_err());
+        assert!("0 0 * *".parse::<CronExpression>().is_err());
+    }
+
+    #[test]
+    fn weekday_seven_is_sunday() {
+        let sunday: CronExpression = "0 0 * * 7".parse().unwrap();
+        assert_eq!(sunday.weekdays, bits(&[0]));
+        let weekend: CronExpression = "0 0 * * 5-7".parse().unwrap();
+        assert_eq!(weekend.weekdays, bits(&[0, 5, 6]));
+        // 2026-10-01 is a Thursday.
+        assert_eq!(sunday.next_after(at(1, 0, 0)), Some(at(4, 0, 0)));
+    }
+
+    #[test]
+    fn next_run_is_strictly_after_the_given_time() {
+        let quarterly: CronExpression = "*/15 * * * *".parse().unwrap();
+        assert_eq!(quarterly.next_after(at(1, 10, 15)), Some(at(1, 10, 30)));
+        assert_eq!(quarterly.next_after(at(1, 10, 15) + Duration::seconds(30)), Some(at(1, 10, 30)));
+        assert_eq!(quarterly.next_after(at(1, 23, 50)), Some(at(2, 0, 0)));
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either_one() {
+        // The 13th or any Friday.
+        let expression: CronExpression = "0 0 13 * 5".parse().unwrap();
+        let mut runs = Vec::new();
+        let mut time = at(1, 0, 0);
+        for _ in 0..4 {
+            time = expression.next_after(time).unwrap();
+            runs.push(time.day());
+        }
+        assert_eq!(runs, [2, 9, 13, 16]);
+
+        // With the weekday unrestricted, only the day of the month counts.
+        let thirteenth: CronExpression = "0 0 13 * *".parse().unwrap();
+        assert_eq!(thirteenth.next_after(at(1, 0, 0)), Some(at(13, 0, 0)));
+    }
+
+    #[test]
+    fn impossible_dates_never_match() {
+        let expression: CronExpression = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(expression.next_after(at(1, 0, 0)), None);
+    }
+}
@@ -19,6 +19,14 @@ impl<'a> EmbedBuilder<'a> {
         embed
     }
 
+    pub fn build_text(&self, title_key: &str, description: &str, color: Colour) -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+        embed.title(self.lang.get(title_key))
+            .description(description)
+            .color(color);
+        embed
+    }
+
     pub fn build_error(&self, title_key: &str, description_key: &str) -> CreateEmbed {
         self.build_simple(title_key, description_key, Colour::RED)
     }
@@ -41,6 +49,10 @@ impl<'a> EmbedBuilder<'a> {
         embed.field(self.lang.get(name_key), self.lang.get(value_key), inline)
     }
 
+    pub fn add_value_field(&self, embed: &mut CreateEmbed, name_key: &str, value: &str, inline: bool) -> &mut CreateEmbed {
+        embed.field(self.lang.get(name_key), value, inline)
+    }
+
     pub fn set_footer(&self, embed: &mut CreateEmbed, text_key: &str, icon_url: Option<&str>) -> &mut CreateEmbed {
         embed.footer(|f| {
             f.text(self.lang.get(text_key));
@@ -7,6 +7,7 @@ pub async fn handle_error(ctx: &Context, msg: &Message, error: BotError, lang: &
     let error_message = match error {
         BotError::Serenity(why) => format!("{}: {:?}", lang.get("errors.discord_api"), why),
         BotError::Database(why) => format!("{}: {:?}", lang.get("errors.database"), why),
+        BotError::Migration(why) => format!("{}: {}", lang.get("errors.database"), why),
         BotError::UnknownCommand(cmd) => format!("{}: {}", lang.get("errors.unknown_command"), cmd),
         BotError::Config(why) => format!("{}: {}", lang.get("errors.configuration"), why),
         BotError::Internal(why) => format!("{}: {}", lang.get("errors.internal"), why),
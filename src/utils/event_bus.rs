@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use futures::stream::{self, Stream};
+use serenity::model::id::{GuildId, UserId};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use crate::utils::metrics::Metrics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Ready,
+    MemberJoined,
+    MemberLeft,
+    GuildJoined,
+    GuildLeft,
+    CommandExecuted,
+    CommandFailed,
+}
+
+#[derive(Debug, Clone)]
+pub enum BotEvent {
+    Ready { user_name: String, guild_count: usize },
+    MemberJoined { guild_id: GuildId, user_id: UserId },
+    MemberLeft { guild_id: GuildId, user_id: UserId },
+    GuildJoined { guild_id: GuildId, name: String },
+    GuildLeft { guild_id: GuildId },
+    CommandExecuted { command: String, guild_id: Option<GuildId>, user_id: UserId },
+    CommandFailed { command: String, guild_id: Option<GuildId>, user_id: UserId, error: String },
+}
+
+impl BotEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            BotEvent::Ready { .. } => EventKind::Ready,
+            BotEvent::MemberJoined { .. } => EventKind::MemberJoined,
+            BotEvent::MemberLeft { .. } => EventKind::MemberLeft,
+            BotEvent::GuildJoined { .. } => EventKind::GuildJoined,
+            BotEvent::GuildLeft { .. } => EventKind::GuildLeft,
+            BotEvent::CommandExecuted { .. } => EventKind::CommandExecuted,
+            BotEvent::CommandFailed { .. } => EventKind::CommandFailed,
+        }
+    }
+
+    pub fn guild_id(&self) -> Option<GuildId> {
+        match self {
+            BotEvent::Ready { .. } => None,
+            BotEvent::MemberJoined { guild_id, .. }
+            | BotEvent::MemberLeft { guild_id, .. }
+            | BotEvent::GuildJoined { guild_id, .. }
+            | BotEvent::GuildLeft { guild_id } => Some(*guild_id),
+            BotEvent::CommandExecuted { guild_id, .. }
+            | BotEvent::CommandFailed { guild_id, .. } => *guild_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    kinds: Option<HashSet<EventKind>>,
+    guild_id: Option<GuildId>,
+}
+
+impl EventFilter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn kinds(kinds: &[EventKind]) -> Self {
+        Self {
+            kinds: Some(kinds.iter().copied().collect()),
+            guild_id: None,
+        }
+    }
+
+    pub fn guild(mut self, guild_id: GuildId) -> Self {
+        self.guild_id = Some(guild_id);
+        self
+    }
+
+    pub fn matches(&self, event: &BotEvent) -> bool {
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&event.kind()) {
+                return false;
+            }
+        }
+        match self.guild_id {
+            Some(guild_id) => event.guild_id() == Some(guild_id),
+            None => true,
+        }
+    }
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<BotEvent>,
+    metrics: Arc<Metrics>,
+}
+
+impl EventBus {
+    pub fn new(sender: broadcast::Sender<BotEvent>, metrics: Arc<Metrics>) -> Self {
+        Self { sender, metrics }
+    }
+
+    pub fn publish(&self, event: BotEvent) {
+        // Sending only fails when nobody is subscribed, which is not an error for a bus.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self, name: &str, filter: EventFilter) -> EventSubscription {
+        EventSubscription {
+            name: name.to_string(),
+            filter,
+            receiver: self.sender.subscribe(),
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+
+    pub fn subscribe_with<F, Fut>(&self, name: &str, filter: EventFilter, handler: F) -> JoinHandle<()>
+    where
+        F: Fn(BotEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut subscription = self.subscribe(name, filter);
+        tokio::spawn(async move {
+            while let Some(event) = subscription.recv().await {
+                handler(event).await;
+            }
+        })
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+pub struct EventSubscription {
+    name: String,
+    filter: EventFilter,
+    receiver: broadcast::Receiver<BotEvent>,
+    metrics: Arc<Metrics>,
+}
+
+impl EventSubscription {
+    pub async fn recv(&mut self) -> Option<BotEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if self.filter.matches(&event) => return Some(event),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("Event bus subscriber '{}' lagged and missed {} events", self.name, skipped);
+                    self.metrics.log_events("event_bus_lagged", skipped as usize).await;
+                    self.metrics.log_events(&format!("event_bus_lagged.{}", self.name), skipped as usize).await;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = BotEvent> + Send {
+        stream::unfold(self, |mut subscription| async move {
+            subscription.recv().await.map(|event| (event, subscription))
+        })
+    }
+}
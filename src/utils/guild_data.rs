@@ -1,57 +1,132 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
 use serenity::model::id::GuildId;
-use crate::bot::error::BotResult;
+use crate::bot::error::{BotResult, BotError};
+use crate::database::repositories::GuildSettingsRepository;
+use crate::utils::settings::{SettingValue, SettingsRegistry};
+
+// `None` until the guild's settings have been loaded from the repository.
+type GuildEntry = Arc<RwLock<Option<HashMap<String, String>>>>;
 
 pub struct GuildData {
-    data: Arc<RwLock<HashMap<GuildId, HashMap<String, String>>>>,
+    data: Arc<RwLock<HashMap<GuildId, GuildEntry>>>,
+    repository: Arc<dyn GuildSettingsRepository>,
+    registry: Arc<SettingsRegistry>,
 }
 
 impl GuildData {
-    pub fn new() -> Self {
+    pub fn new(repository: Arc<dyn GuildSettingsRepository>, registry: Arc<SettingsRegistry>) -> Self {
         Self {
             data: Arc::new(RwLock::new(HashMap::new())),
+            repository,
+            registry,
         }
     }
 
-    pub async fn set(&self, guild_id: GuildId, key: &str, value: &str) -> BotResult<()> {
+    pub fn registry(&self) -> &Arc<SettingsRegistry> {
+        &self.registry
+    }
+
+    async fn entry(&self, guild_id: GuildId) -> GuildEntry {
+        if let Some(entry) = self.data.read().await.get(&guild_id) {
+            return Arc::clone(entry);
+        }
         let mut data = self.data.write().await;
-        let guild_data = data.entry(guild_id).or_insert_with(HashMap::new);
-        guild_data.insert(key.to_string(), value.to_string());
-        Ok(())
+        Arc::clone(data.entry(guild_id).or_insert_with(|| Arc::new(RwLock::new(None))))
+    }
+
+    async fn read_with<T>(&self, guild_id: GuildId, f: impl FnOnce(&HashMap<String, String>) -> T) -> BotResult<T> {
+        let entry = self.entry(guild_id).await;
+        if let Some(settings) = entry.read().await.as_ref() {
+            return Ok(f(settings));
+        }
+        let guard = self.load(guild_id, entry).await?;
+        Ok(f(guard.as_ref().expect("guild settings were just loaded")))
+    }
+
+    // Writers hold the guild's write lock across the repository call, so writes to one guild
+    // are applied to the database and the cache in the same order.
+    async fn load(&self, guild_id: GuildId, entry: GuildEntry) -> BotResult<OwnedRwLockWriteGuard<Option<HashMap<String, String>>>> {
+        let mut guard = entry.write_owned().await;
+        if guard.is_none() {
+            *guard = Some(self.repository.get_all(guild_id).await?);
+        }
+        Ok(guard)
+    }
+
+    pub async fn set(&self, guild_id: GuildId, key: &str, value: &str) -> BotResult<SettingValue> {
+        let value = self.registry.validate(key, value).await?;
+        let stored = value.to_storage();
+
+        let entry = self.entry(guild_id).await;
+        let mut guard = self.load(guild_id, entry).await?;
+        self.repository.set(guild_id, key, &stored).await?;
+        if let Some(settings) = guard.as_mut() {
+            settings.insert(key.to_string(), stored);
+        }
+        Ok(value)
     }
 
-    pub async fn get(&self, guild_id: GuildId, key: &str) -> BotResult<Option<String>> {
-        let data = self.data.read().await;
-        Ok(data.get(&guild_id)
-            .and_then(|guild_data| guild_data.get(key))
-            .cloned())
+    pub async fn get(&self, guild_id: GuildId, key: &str) -> BotResult<SettingValue> {
+        let definition = self.registry.get(key).await
+            .ok_or_else(|| BotError::Settings(format!("Unknown setting '{}'", key)))?;
+
+        match self.get_raw(guild_id, key).await? {
+            Some(raw) => Ok(definition.kind.parse(&raw).unwrap_or_else(|e| {
+                log::warn!("Stored value for '{}' in guild {} is invalid ({}), using default", key, guild_id, e);
+                definition.default.clone()
+            })),
+            None => Ok(definition.default),
+        }
+    }
+
+    pub async fn get_raw(&self, guild_id: GuildId, key: &str) -> BotResult<Option<String>> {
+        self.read_with(guild_id, |settings| settings.get(key).cloned()).await
     }
 
     pub async fn remove(&self, guild_id: GuildId, key: &str) -> BotResult<()> {
-        let mut data = self.data.write().await;
-        if let Some(guild_data) = data.get_mut(&guild_id) {
-            guild_data.remove(key);
+        let entry = self.entry(guild_id).await;
+        let mut guard = self.load(guild_id, entry).await?;
+        self.repository.remove(guild_id, key).await?;
+        if let Some(settings) = guard.as_mut() {
+            settings.remove(key);
         }
         Ok(())
     }
 
     pub async fn get_all(&self, guild_id: GuildId) -> BotResult<Option<HashMap<String, String>>> {
-        let data = self.data.read().await;
-        Ok(data.get(&guild_id).cloned())
+        self.read_with(guild_id, |settings| {
+            if settings.is_empty() {
+                None
+            } else {
+                Some(settings.clone())
+            }
+        }).await
     }
 
     pub async fn clear(&self, guild_id: GuildId) -> BotResult<()> {
-        let mut data = self.data.write().await;
-        data.remove(&guild_id);
+        let entry = self.entry(guild_id).await;
+        let mut guard = entry.write_owned().await;
+        self.repository.clear(guild_id).await?;
+        *guard = Some(HashMap::new());
         Ok(())
     }
 
     pub async fn has_key(&self, guild_id: GuildId, key: &str) -> BotResult<bool> {
-        let data = self.data.read().await;
-        Ok(data.get(&guild_id)
-            .map(|guild_data| guild_data.contains_key(key))
-            .unwrap_or(false))
+        self.read_with(guild_id, |settings| settings.contains_key(key)).await
     }
-}
\ No newline at end of file
+
+    pub async fn unload(&self, guild_id: GuildId) {
+        let mut data = self.data.write().await;
+        data.remove(&guild_id);
+    }
+
+    /// Drops every cached guild so settings are reloaded from the repository on next use.
+    pub async fn unload_all(&self) -> usize {
+        let mut data = self.data.write().await;
+        let count = data.len();
+        data.clear();
+        count
+    }
+}
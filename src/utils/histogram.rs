@@ -0,0 +1,293 @@
+use std::time::{Duration, Instant};
+
+// Each bucket is 5% wider than the one before it, so a reported quantile is within ~2.5% of the
+// true value, and 400 buckets reach well past a day when recording milliseconds.
+const GROWTH: f64 = 1.05;
+const BUCKETS: usize = 400;
+
+const SLOT_WIDTH: Duration = Duration::from_secs(10);
+const SLOTS: usize = 360;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: u64,
+    pub min: u64,
+    pub max: u64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl HistogramSnapshot {
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum as f64 / self.count as f64)
+        }
+    }
+}
+
+/// Log-bucketed histogram with fixed memory, regardless of how many values are recorded.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    fn bucket(value: u64) -> usize {
+        if value == 0 {
+            return 0;
+        }
+        let index = ((value as f64).ln() / GROWTH.ln()).floor() as usize + 1;
+        index.min(BUCKETS - 1)
+    }
+
+    fn bucket_midpoint(index: usize) -> f64 {
+        if index == 0 {
+            return 0.0;
+        }
+        let lower = GROWTH.powi(index as i32 - 1);
+        let upper = GROWTH.powi(index as i32);
+        (lower + upper) / 2.0
+    }
+
+    pub fn record(&mut self, value: u64) {
+        self.counts[Self::bucket(value)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn quantile(&self, quantile: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                // Exact extremes are tracked separately, so never report outside of them.
+                return Some(Self::bucket_midpoint(index).clamp(self.min as f64, self.max as f64));
+            }
+        }
+        Some(self.max as f64)
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        if self.count == 0 {
+            return HistogramSnapshot::default();
+        }
+        HistogramSnapshot {
+            count: self.count,
+            sum: self.sum,
+            min: self.min,
+            max: self.max,
+            p50: self.quantile(0.50).unwrap_or_default(),
+            p95: self.quantile(0.95).unwrap_or_default(),
+            p99: self.quantile(0.99).unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts events in 10 second slots covering the last hour, plus a lifetime total.
+#[derive(Debug, Clone)]
+pub struct WindowedCounter {
+    // (slot number, count); a slot is reused once its number falls out of the window.
+    slots: Vec<(u64, u64)>,
+    started: Instant,
+    total: u64,
+}
+
+impl WindowedCounter {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![(0, 0); SLOTS],
+            started: Instant::now(),
+            total: 0,
+        }
+    }
+
+    fn slot_at(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.started).as_secs() / SLOT_WIDTH.as_secs()
+    }
+
+    pub fn record(&mut self, count: u64) {
+        self.record_at(count, Instant::now());
+    }
+
+    fn record_at(&mut self, count: u64, now: Instant) {
+        let slot = self.slot_at(now);
+        let entry = &mut self.slots[(slot % SLOTS as u64) as usize];
+        if entry.0 != slot {
+            *entry = (slot, 0);
+        }
+        entry.1 += count;
+        self.total += count;
+    }
+
+    /// Windows are rounded up to whole slots and capped at one hour.
+    pub fn count_within(&self, window: Duration) -> u64 {
+        self.count_within_at(window, Instant::now())
+    }
+
+    fn count_within_at(&self, window: Duration, now: Instant) -> u64 {
+        let current = self.slot_at(now);
+        let span = ((window.as_secs() + SLOT_WIDTH.as_secs() - 1) / SLOT_WIDTH.as_secs()).clamp(1, SLOTS as u64);
+        self.slots.iter()
+            .filter(|&&(slot, _)| slot <= current && current - slot < span)
+            .map(|&(_, count)| count)
+            .sum()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+impl Default for WindowedCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() <= expected * 0.025, "{} is not within 2.5% of {}", actual, expected);
+    }
+
+    #[test]
+    fn values_land_in_their_buckets() {
+        assert_eq!(Histogram::bucket(0), 0);
+        assert_eq!(Histogram::bucket(1), 1);
+        assert_eq!(Histogram::bucket(2), 15);
+        assert_eq!(Histogram::bucket(u64::MAX), BUCKETS - 1);
+        assert_eq!(Histogram::bucket(GROWTH.powi(BUCKETS as i32) as u64), BUCKETS - 1);
+
+        for value in [2u64, 7, 100, 1_234, 98_765] {
+            let index = Histogram::bucket(value) as i32;
+            assert!(GROWTH.powi(index - 1) <= value as f64 && (value as f64) < GROWTH.powi(index), "{}", value);
+        }
+        for value in 1..10_000u64 {
+            assert!(Histogram::bucket(value) >= Histogram::bucket(value - 1));
+        }
+    }
+
+    #[test]
+    fn quantiles_are_within_the_bucket_error() {
+        let mut histogram = Histogram::new();
+        for value in 1..=1_000 {
+            histogram.record(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert_close(Some(snapshot.p50), 500.0);
+        assert_close(Some(snapshot.p95), 950.0);
+        assert_close(Some(snapshot.p99), 990.0);
+        assert_close(histogram.quantile(0.0), 1.0);
+        assert_close(histogram.quantile(1.0), 1_000.0);
+    }
+
+    #[test]
+    fn quantiles_stay_within_the_recorded_range() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.quantile(0.5), None);
+
+        histogram.record(0);
+        assert_eq!(histogram.quantile(0.99), Some(0.0));
+
+        let mut histogram = Histogram::new();
+        histogram.record(1);
+        histogram.record(1);
+        assert_eq!(histogram.quantile(0.5), Some(1.0));
+
+        let mut histogram = Histogram::new();
+        histogram.record(u64::MAX);
+        assert_eq!(histogram.quantile(0.5), Some(u64::MAX as f64));
+    }
+
+    #[test]
+    fn snapshots_track_sum_count_and_extremes() {
+        assert_eq!(Histogram::new().snapshot(), HistogramSnapshot::default());
+        assert_eq!(HistogramSnapshot::default().mean(), None);
+
+        let mut histogram = Histogram::new();
+        for value in [0, 10, 20, 30] {
+            histogram.record(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!((snapshot.count, snapshot.sum, snapshot.min, snapshot.max), (4, 60, 0, 30));
+        assert_eq!(snapshot.mean(), Some(15.0));
+        assert_eq!(histogram.count(), 4);
+
+        histogram.record(u64::MAX);
+        assert_eq!(histogram.snapshot().sum, u64::MAX);
+    }
+
+    #[test]
+    fn windows_only_count_recent_slots() {
+        let mut counter = WindowedCounter::new();
+        let start = counter.started;
+        counter.record_at(1, start);
+        counter.record_at(2, start + 10 * MINUTE);
+        counter.record_at(3, start + 14 * MINUTE);
+        counter.record_at(4, start + 14 * MINUTE + Duration::from_secs(50));
+
+        let now = start + 15 * MINUTE;
+        assert_eq!(counter.count_within_at(MINUTE, now), 4);
+        assert_eq!(counter.count_within_at(5 * MINUTE, now), 7);
+        assert_eq!(counter.count_within_at(60 * MINUTE, now), 10);
+
+        let later = start + 70 * MINUTE;
+        assert_eq!(counter.count_within_at(MINUTE, later), 0);
+        assert_eq!(counter.count_within_at(5 * MINUTE, later), 0);
+        assert_eq!(counter.count_within_at(60 * MINUTE, later), 7);
+        assert_eq!(counter.total(), 10);
+    }
+
+    #[test]
+    fn slots_are_reused_after_an_hour() {
+        let mut counter = WindowedCounter::new();
+        let start = counter.started;
+        counter.record_at(1, start);
+        counter.record_at(5, start + 60 * MINUTE);
+
+        assert_eq!(counter.count_within_at(MINUTE, start + 60 * MINUTE), 5);
+        assert_eq!(counter.count_within_at(60 * MINUTE, start + 60 * MINUTE), 5);
+        // Longer windows are capped at the hour the counter covers.
+        assert_eq!(counter.count_within_at(120 * MINUTE, start + 60 * MINUTE), 5);
+        assert_eq!(counter.total(), 6);
+    }
+}
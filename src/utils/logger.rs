@@ -1,44 +1,275 @@
 use log::{Level, LevelFilter, Metadata, Record};
-use chrono::Local;
+use chrono::{Local, Utc};
 use colored::*;
-use std::sync::Once;
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, Once, OnceLock, RwLock};
+use crate::bot::error::{BotResult, BotError};
+use crate::config::{LogFormat, LoggingConfig};
 
-pub struct Logger;
+const BYTES_PER_MB: u64 = 1024 * 1024;
 
 static INIT: Once = Once::new();
+static LOGGER: OnceLock<&'static Logger> = OnceLock::new();
+
+tokio::task_local! {
+    static LOG_CONTEXT: LogContext;
+}
+
+/// Fields attached to every log line emitted while a command is running.
+#[derive(Debug, Clone, Default)]
+pub struct LogContext {
+    pub command: String,
+    pub guild_id: Option<u64>,
+    pub user_id: u64,
+}
+
+impl LogContext {
+    /// Runs `future` with this context attached to its log lines.
+    pub async fn scope<F: std::future::Future>(self, future: F) -> F::Output {
+        LOG_CONTEXT.scope(self, future).await
+    }
+
+    fn current() -> Option<LogContext> {
+        LOG_CONTEXT.try_with(|context| context.clone()).ok()
+    }
+}
+
+/// `RUST_LOG`-style filter, e.g. `info,serenity=warn,bot::commands=debug`.
+struct Directives {
+    default: LevelFilter,
+    // Longest target first, so the most specific directive wins.
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Directives {
+    fn parse(spec: &str) -> BotResult<Self> {
+        let mut directives = Self { default: LevelFilter::Info, targets: Vec::new() };
+        for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    directives.targets.push((target.trim().to_string(), parse_level(level.trim())?));
+                }
+                None => match LevelFilter::from_str(directive) {
+                    Ok(level) => directives.default = level,
+                    // A bare target enables everything for it, as env_logger does.
+                    Err(_) => directives.targets.push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        directives.targets.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+        Ok(directives)
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .find(|(prefix, _)| target == prefix || target.strip_prefix(prefix.as_str()).map_or(false, |rest| rest.starts_with("::")))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).fold(self.default, |a, b| a.max(b))
+    }
+}
+
+fn parse_level(level: &str) -> BotResult<LevelFilter> {
+    LevelFilter::from_str(level).map_err(|_| BotError::Config(format!("Invalid log level '{}'", level)))
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep_files: usize) -> BotResult<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size, max_bytes, keep_files })
+    }
+
+    fn numbered(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    // bot.log becomes bot.log.1, bot.log.1 becomes bot.log.2, and so on up to keep_files.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.keep_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            let _ = fs::remove_file(self.numbered(self.keep_files));
+            for index in (1..self.keep_files).rev() {
+                let _ = fs::rename(self.numbered(index), self.numbered(index + 1));
+            }
+            match fs::rename(&self.path, self.numbered(1)) {
+                Ok(()) => {}
+                // Someone removed the file, so there is nothing to keep; start a new one below.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        // Reopen even when truncating, in case the file was deleted out from under the handle.
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.max_bytes > 0 && self.size > 0 && self.size + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+pub struct Logger {
+    // Behind a lock so the default level can be changed at runtime.
+    directives: RwLock<Directives>,
+    format: LogFormat,
+    color: bool,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Logger {
+    fn format_text(&self, record: &Record, context: Option<&LogContext>, color: bool) -> String {
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let level = record.level().to_string();
+        let level = if color {
+            match record.level() {
+                Level::Error => level.red().to_string(),
+                Level::Warn => level.yellow().to_string(),
+                Level::Info => level.green().to_string(),
+                Level::Debug => level.blue().to_string(),
+                Level::Trace => level.purple().to_string(),
+            }
+        } else {
+            level
+        };
+        let mut line = format!("[{}] {} - {} - {}", now, level, record.target(), record.args());
+        if let Some(context) = context {
+            line.push_str(&format!(" command={}", context.command));
+            if let Some(guild_id) = context.guild_id {
+                line.push_str(&format!(" guild_id={}", guild_id));
+            }
+            line.push_str(&format!(" user_id={}", context.user_id));
+        }
+        line
+    }
+
+    fn format_json(&self, record: &Record, context: Option<&LogContext>) -> String {
+        let mut line = json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        });
+        if let Some(context) = context {
+            line["command"] = json!(context.command);
+            line["guild_id"] = json!(context.guild_id);
+            line["user_id"] = json!(context.user_id);
+        }
+        line.to_string()
+    }
+}
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        match self.directives.read() {
+            Ok(directives) => metadata.level() <= directives.level_for(metadata.target()),
+            Err(_) => false,
+        }
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            let now = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-            let level = record.level().to_string();
-            let colored_level = match record.level() {
-                Level::Error => level.red(),
-                Level::Warn => level.yellow(),
-                Level::Info => level.green(),
-                Level::Debug => level.blue(),
-                Level::Trace => level.purple(),
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let context = LogContext::current();
+
+        let line = match self.format {
+            LogFormat::Text => self.format_text(record, context.as_ref(), self.color),
+            LogFormat::Json => self.format_json(record, context.as_ref()),
+        };
+        println!("{}", line);
+
+        if let Some(file) = &self.file {
+            // Files never get ANSI colours, whatever the console uses.
+            let line = match self.format {
+                LogFormat::Text if self.color => self.format_text(record, context.as_ref(), false),
+                _ => line,
             };
-            println!("[{}] {} - {} - {}", now, colored_level, record.target(), record.args());
+            if let Ok(mut file) = file.lock() {
+                if let Err(why) = file.write_line(&line) {
+                    eprintln!("Failed to write log file: {}", why);
+                }
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
 }
 
-pub fn init() {
+pub fn init(config: &LoggingConfig) -> BotResult<()> {
+    let spec = std::env::var("RUST_LOG").unwrap_or_else(|_| config.level.clone());
+    let directives = Directives::parse(&spec)?;
+    let file = match &config.file {
+        Some(path) => Some(Mutex::new(RotatingFile::open(
+            PathBuf::from(path),
+            config.max_file_size_mb * BYTES_PER_MB,
+            config.keep_files,
+        )?)),
+        None => None,
+    };
+
+    let mut result = Ok(());
     INIT.call_once(|| {
-        log::set_logger(&Logger).unwrap();
-        log::set_max_level(LevelFilter::Info);
+        let max_level = directives.max_level();
+        let logger = Logger { directives: RwLock::new(directives), format: config.format, color: config.color, file };
+        // The logger lives for the rest of the process, so leaking it is what we want.
+        let logger: &'static Logger = Box::leak(Box::new(logger));
+        let _ = LOGGER.set(logger);
+        result = log::set_logger(logger)
+            .map(|_| log::set_max_level(max_level))
+            .map_err(|e| BotError::Internal(format!("Failed to install logger: {}", e)));
     });
+    result
 }
 
+/// Changes the default level; per-target directives from `RUST_LOG` or the config stay in place.
 pub fn set_log_level(level: LevelFilter) {
-    log::set_max_level(level);
+    match LOGGER.get().map(|logger| logger.directives.write()) {
+        Some(Ok(mut directives)) => {
+            directives.default = level;
+            log::set_max_level(directives.max_level());
+        }
+        _ => log::set_max_level(level),
+    }
+}
+
+pub fn log_level() -> LevelFilter {
+    LOGGER.get()
+        .and_then(|logger| logger.directives.read().ok().map(|directives| directives.default))
+        .unwrap_or_else(log::max_level)
 }
 
 #[macro_export]
@@ -74,4 +305,110 @@ macro_rules! log_trace {
     ($($arg:tt)+) => {
         log::trace!($($arg)+);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn json_logger() -> Logger {
+        Logger { directives: RwLock::new(Directives::parse("info").unwrap()), format: LogFormat::Json, color: false, file: None }
+    }
+
+    #[test]
+    fn a_bare_level_sets_the_default() {
+        let directives = Directives::parse("debug").unwrap();
+        assert_eq!(directives.level_for("bot"), LevelFilter::Debug);
+        assert_eq!(directives.level_for("serenity::gateway"), LevelFilter::Debug);
+        assert_eq!(Directives::parse("").unwrap().level_for("bot"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn the_longest_matching_target_wins() {
+        let directives = Directives::parse("warn, bot=info, bot::commands=trace, serenity=error").unwrap();
+        assert_eq!(directives.level_for("bot"), LevelFilter::Info);
+        assert_eq!(directives.level_for("bot::database"), LevelFilter::Info);
+        assert_eq!(directives.level_for("bot::commands"), LevelFilter::Trace);
+        assert_eq!(directives.level_for("bot::commands::config"), LevelFilter::Trace);
+        assert_eq!(directives.level_for("serenity::http"), LevelFilter::Error);
+        assert_eq!(directives.level_for("tokio"), LevelFilter::Warn);
+        assert_eq!(directives.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn targets_only_match_whole_path_segments() {
+        let directives = Directives::parse("error,bot=debug").unwrap();
+        assert_eq!(directives.level_for("bot::commands"), LevelFilter::Debug);
+        assert_eq!(directives.level_for("bottle"), LevelFilter::Error);
+        assert_eq!(directives.level_for("bot_extra::commands"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn a_bare_target_enables_everything_for_it() {
+        let directives = Directives::parse("warn,bot::commands").unwrap();
+        assert_eq!(directives.level_for("bot::commands"), LevelFilter::Trace);
+        assert_eq!(directives.level_for("bot"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn invalid_levels_are_rejected() {
+        assert!(matches!(Directives::parse("bot=loud"), Err(BotError::Config(_))));
+        assert!(Directives::parse("info,serenity=").is_err());
+    }
+
+    #[test]
+    fn json_lines_include_the_log_context() {
+        let context = LogContext { command: "ping".to_string(), guild_id: Some(42), user_id: 7 };
+        let line = json_logger().format_json(
+            &Record::builder().args(format_args!("pong")).level(Level::Warn).target("bot::commands").build(),
+            Some(&context),
+        );
+        let line: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["target"], "bot::commands");
+        assert_eq!(line["message"], "pong");
+        assert_eq!(line["command"], "ping");
+        assert_eq!(line["guild_id"], 42);
+        assert_eq!(line["user_id"], 7);
+        assert!(line["timestamp"].is_string());
+    }
+
+    #[test]
+    fn json_lines_without_context_leave_it_out() {
+        let line = json_logger().format_json(
+            &Record::builder().args(format_args!("ready")).level(Level::Info).target("bot").build(),
+            None,
+        );
+        let line: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["message"], "ready");
+        assert!(line.get("command").is_none());
+        assert!(line.get("user_id").is_none());
+    }
+
+    #[test]
+    fn rotation_starts_a_new_file_when_the_old_one_was_deleted() {
+        let path = temp_dir().join("bot.log");
+        let mut file = RotatingFile::open(path.clone(), 16, 2).unwrap();
+        file.write_line("first line").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        file.write_line("second line").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second line\n");
+        assert!(!file.numbered(1).exists());
+    }
+
+    #[test]
+    fn rotation_keeps_numbered_files() {
+        let path = temp_dir().join("bot.log");
+        let mut file = RotatingFile::open(path.clone(), 16, 2).unwrap();
+        for line in ["first line", "second line", "third line"] {
+            file.write_line(line).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third line\n");
+        assert_eq!(fs::read_to_string(file.numbered(1)).unwrap(), "second line\n");
+        assert_eq!(fs::read_to_string(file.numbered(2)).unwrap(), "first line\n");
+    }
+}
@@ -1,41 +1,141 @@
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use std::collections::HashMap;
-use chrono::{DateTime, Utc};
+use std::time::Duration;
+use crate::utils::histogram::{Histogram, HistogramSnapshot, WindowedCounter};
+
+#[derive(Debug, Clone, Copy)]
+pub enum CacheEvent {
+    Hit,
+    Miss,
+    Eviction,
+    Expiration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandOutcome {
+    Ok,
+    UserError,
+    InternalError,
+    RateLimited,
+    PermissionDenied,
+}
+
+impl CommandOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandOutcome::Ok => "ok",
+            CommandOutcome::UserError => "user_error",
+            CommandOutcome::InternalError => "internal_error",
+            CommandOutcome::RateLimited => "rate_limited",
+            CommandOutcome::PermissionDenied => "permission_denied",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommandLabels {
+    pub command: String,
+    pub in_guild: bool,
+    // Only kept with `with_guild_label`, since every guild becomes a series of its own.
+    pub guild_id: Option<u64>,
+    pub outcome: CommandOutcome,
+}
+
+impl CommandLabels {
+    pub fn context(&self) -> &'static str {
+        if self.in_guild { "guild" } else { "dm" }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+impl CacheStats {
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            None
+        } else {
+            Some(self.hits as f64 / lookups as f64)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ErrorCounts {
+    pub last_minute: u64,
+    pub last_five_minutes: u64,
+    pub last_hour: u64,
+    pub total: u64,
+}
 
 #[derive(Debug, Clone)]
 pub struct Metrics {
     command_usage: Arc<Mutex<HashMap<String, usize>>>,
-    errors: Arc<Mutex<Vec<(DateTime<Utc>, String)>>>,
-    latency: Arc<Mutex<Vec<(DateTime<Utc>, u64)>>>,
+    command_outcomes: Arc<Mutex<HashMap<CommandLabels, u64>>>,
+    command_durations: Arc<Mutex<HashMap<String, Histogram>>>,
+    errors: Arc<Mutex<WindowedCounter>>,
+    latency: Arc<Mutex<Histogram>>,
     events: Arc<Mutex<HashMap<String, usize>>>,
     gauges: Arc<Mutex<HashMap<String, f64>>>,
+    caches: Arc<Mutex<HashMap<String, CacheStats>>>,
+    guild_label: bool,
 }
 
 impl Metrics {
     pub fn new() -> Self {
         Self {
             command_usage: Arc::new(Mutex::new(HashMap::new())),
-            errors: Arc::new(Mutex::new(Vec::new())),
-            latency: Arc::new(Mutex::new(Vec::new())),
+            command_outcomes: Arc::new(Mutex::new(HashMap::new())),
+            command_durations: Arc::new(Mutex::new(HashMap::new())),
+            errors: Arc::new(Mutex::new(WindowedCounter::new())),
+            latency: Arc::new(Mutex::new(Histogram::new())),
             events: Arc::new(Mutex::new(HashMap::new())),
             gauges: Arc::new(Mutex::new(HashMap::new())),
+            caches: Arc::new(Mutex::new(HashMap::new())),
+            guild_label: false,
         }
     }
 
+    /// Also counts commands per guild ID, for `metrics.guild_label`.
+    pub fn with_guild_label(mut self, guild_label: bool) -> Self {
+        self.guild_label = guild_label;
+        self
+    }
+
     pub async fn increment_command(&self, command: &str) {
         let mut usage = self.command_usage.lock().await;
         *usage.entry(command.to_string()).or_insert(0) += 1;
     }
 
+    pub async fn record_command(&self, command: &str, guild_id: Option<u64>, outcome: CommandOutcome, duration: Duration) {
+        {
+            let mut outcomes = self.command_outcomes.lock().await;
+            let labels = CommandLabels {
+                command: command.to_string(),
+                in_guild: guild_id.is_some(),
+                guild_id: guild_id.filter(|_| self.guild_label),
+                outcome,
+            };
+            *outcomes.entry(labels).or_insert(0) += 1;
+        }
+        let mut durations = self.command_durations.lock().await;
+        durations.entry(command.to_string()).or_default().record(duration.as_millis() as u64);
+    }
+
     pub async fn log_error(&self, error: &str) {
-        let mut errors = self.errors.lock().await;
-        errors.push((Utc::now(), error.to_string()));
+        log::debug!("Recorded error: {}", error);
+        self.errors.lock().await.record(1);
     }
 
     pub async fn log_latency(&self, latency: u64) {
-        let mut latencies = self.latency.lock().await;
-        latencies.push((Utc::now(), latency));
+        self.latency.lock().await.record(latency);
     }
 
     pub async fn log_event(&self, event: &str) {
@@ -43,28 +143,67 @@ impl Metrics {
         *events.entry(event.to_string()).or_insert(0) += 1;
     }
 
+    pub async fn log_events(&self, event: &str, count: usize) {
+        let mut events = self.events.lock().await;
+        *events.entry(event.to_string()).or_insert(0) += count;
+    }
+
     pub async fn set_gauge(&self, name: &str, value: f64) {
         let mut gauges = self.gauges.lock().await;
         gauges.insert(name.to_string(), value);
     }
 
+    pub async fn record_cache_event(&self, cache: &str, event: CacheEvent, count: u64) {
+        let mut caches = self.caches.lock().await;
+        let stats = caches.entry(cache.to_string()).or_default();
+        match event {
+            CacheEvent::Hit => stats.hits += count,
+            CacheEvent::Miss => stats.misses += count,
+            CacheEvent::Eviction => stats.evictions += count,
+            CacheEvent::Expiration => stats.expirations += count,
+        }
+    }
+
     pub async fn get_command_usage(&self) -> HashMap<String, usize> {
         self.command_usage.lock().await.clone()
     }
 
-    pub async fn get_error_count(&self) -> usize {
-        self.errors.lock().await.len()
+    pub async fn get_command_outcomes(&self) -> HashMap<CommandLabels, u64> {
+        self.command_outcomes.lock().await.clone()
     }
 
-    pub async fn get_average_latency(&self) -> Option<f64> {
-        let latencies = self.latency.lock().await;
-        if latencies.is_empty() {
-            None
-        } else {
-            Some(latencies.iter().map(|&(_, l)| l as f64).sum::<f64>() / latencies.len() as f64)
+    pub async fn get_command_durations(&self) -> HashMap<String, HistogramSnapshot> {
+        self.command_durations.lock().await.iter()
+            .map(|(command, histogram)| (command.clone(), histogram.snapshot()))
+            .collect()
+    }
+
+    pub async fn get_latency(&self) -> HistogramSnapshot {
+        self.latency.lock().await.snapshot()
+    }
+
+    pub async fn get_latency_percentile(&self, quantile: f64) -> Option<f64> {
+        self.latency.lock().await.quantile(quantile)
+    }
+
+    pub async fn get_error_count(&self) -> u64 {
+        self.errors.lock().await.total()
+    }
+
+    pub async fn get_error_counts(&self) -> ErrorCounts {
+        let errors = self.errors.lock().await;
+        ErrorCounts {
+            last_minute: errors.count_within(Duration::from_secs(60)),
+            last_five_minutes: errors.count_within(Duration::from_secs(300)),
+            last_hour: errors.count_within(Duration::from_secs(3600)),
+            total: errors.total(),
         }
     }
 
+    pub async fn get_average_latency(&self) -> Option<f64> {
+        self.latency.lock().await.snapshot().mean()
+    }
+
     pub async fn get_event_counts(&self) -> HashMap<String, usize> {
         self.events.lock().await.clone()
     }
@@ -72,4 +211,8 @@ impl Metrics {
     pub async fn get_gauges(&self) -> HashMap<String, f64> {
         self.gauges.lock().await.clone()
     }
-}
\ No newline at end of file
+
+    pub async fn get_cache_stats(&self) -> HashMap<String, CacheStats> {
+        self.caches.lock().await.clone()
+    }
+}
@@ -1,11 +1,18 @@
 pub mod embed_builder;
 pub mod error_handler;
 pub mod metrics;
+pub mod histogram;
+pub mod prometheus;
 pub mod cache;
 pub mod task_manager;
+pub mod cron;
+pub mod scheduler;
 pub mod rate_limiter;
 pub mod guild_data;
 pub mod logger;
+pub mod event_bus;
+pub mod settings;
+pub mod shutdown;
 
 use serenity::builder::CreateEmbed;
 use serenity::model::prelude::*;
@@ -27,8 +34,13 @@ pub async fn update_presence(ctx: &Context, status: &str) -> BotResult<()> {
     Ok(())
 }
 
+/// Formats as `1d 2h 3m 4s`, dropping leading zero units; durations under a second are shown
+/// in milliseconds.
 pub fn format_duration(duration: std::time::Duration) -> String {
     let seconds = duration.as_secs();
+    if seconds == 0 && !duration.is_zero() {
+        return format!("{}ms", duration.as_millis());
+    }
     let minutes = seconds / 60;
     let hours = minutes / 60;
     let days = hours / 24;
@@ -42,4 +54,19 @@ pub fn format_duration(duration: std::time::Duration) -> String {
     } else {
         format!("{}s", seconds)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn durations_are_formatted_from_the_largest_unit() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1s");
+        assert_eq!(format_duration(Duration::from_secs(61)), "1m 1s");
+        assert_eq!(format_duration(Duration::from_secs(3_723)), "1h 2m 3s");
+        assert_eq!(format_duration(Duration::from_secs(90_061)), "1d 1h 1m 1s");
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use crate::bot::error::{BotResult, BotError};
+use crate::config::MetricsConfig;
+use crate::utils::histogram::HistogramSnapshot;
+use crate::utils::metrics::Metrics;
+use crate::utils::task_manager::ShutdownToken;
+
+const MAX_REQUEST_SIZE: usize = 8192;
+const MAX_CONNECTIONS: usize = 16;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub struct PrometheusExporter {
+    metrics: Arc<Metrics>,
+    address: SocketAddr,
+}
+
+impl PrometheusExporter {
+    pub fn new(config: &MetricsConfig, metrics: Arc<Metrics>) -> BotResult<Self> {
+        let address = format!("{}:{}", config.bind_address, config.port)
+            .parse()
+            .map_err(|e| BotError::Config(format!("Invalid metrics bind address: {}", e)))?;
+        Ok(Self { metrics, address })
+    }
+
+    pub async fn bind(&self) -> BotResult<TcpListener> {
+        let listener = TcpListener::bind(self.address).await?;
+        log::info!("Serving Prometheus metrics on http://{}/metrics", self.address);
+        Ok(listener)
+    }
+
+    /// Answers scrapes until shutdown.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, shutdown: ShutdownToken) {
+        // Stop accepting while every slot is busy, so slow clients can't pile up tasks.
+        let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let permit = tokio::select! {
+                permit = Arc::clone(&connections).acquire_owned() => match permit {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                },
+                _ = shutdown.cancelled() => return,
+            };
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.cancelled() => return,
+            };
+            let (stream, peer) = match accepted {
+                Ok(connection) => connection,
+                Err(why) => {
+                    log::warn!("Failed to accept metrics connection: {}", why);
+                    continue;
+                }
+            };
+            let exporter = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(why) = exporter.handle_connection(stream).await {
+                    log::debug!("Metrics request from {} failed: {}", peer, why);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) -> BotResult<()> {
+        let mut buffer = vec![0; MAX_REQUEST_SIZE];
+        let read = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream, &mut buffer))
+            .await
+            .map_err(|_| BotError::Api("Timed out reading the metrics request".to_string()))??;
+
+        let request = String::from_utf8_lossy(&buffer[..read]);
+        let mut parts = request.lines().next().unwrap_or_default().split_whitespace();
+        let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+        let response = match (method, path.split('?').next().unwrap_or_default()) {
+            ("GET", "/metrics") => http_response("200 OK", CONTENT_TYPE, &self.render().await),
+            (_, "/metrics") => http_response("405 Method Not Allowed", "text/plain", "Method Not Allowed\n"),
+            _ => http_response("404 Not Found", "text/plain", "Not Found\n"),
+        };
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    pub async fn render(&self) -> String {
+        let mut output = String::new();
+
+        write_header(&mut output, "commands_total", "counter", "Command invocations by command, context, guild and outcome.");
+        let mut outcomes: Vec<_> = self.metrics.get_command_outcomes().await.into_iter().collect();
+        outcomes.sort_by(|(a, _), (b, _)| {
+            (&a.command, a.context(), a.guild_id, a.outcome.as_str()).cmp(&(&b.command, b.context(), b.guild_id, b.outcome.as_str()))
+        });
+        for (labels, count) in outcomes {
+            let guild = labels.guild_id.map(|guild_id| guild_id.to_string());
+            let mut sample_labels = vec![("command", labels.command.as_str()), ("context", labels.context())];
+            if let Some(guild) = &guild {
+                sample_labels.push(("guild", guild.as_str()));
+            }
+            sample_labels.push(("outcome", labels.outcome.as_str()));
+            write_sample(&mut output, "commands_total", &sample_labels, count as f64);
+        }
+
+        write_header(&mut output, "command_duration_milliseconds", "summary", "Command execution time in milliseconds.");
+        let durations: BTreeMap<_, _> = self.metrics.get_command_durations().await.into_iter().collect();
+        for (command, snapshot) in &durations {
+            write_summary(&mut output, "command_duration_milliseconds", &[("command", command)], snapshot);
+        }
+
+        write_header(&mut output, "events_total", "counter", "Bot events by name.");
+        let events: BTreeMap<_, _> = self.metrics.get_event_counts().await.into_iter().collect();
+        for (event, count) in events {
+            write_sample(&mut output, "events_total", &[("event", &event)], count as f64);
+        }
+
+        let errors = self.metrics.get_error_counts().await;
+        write_header(&mut output, "errors_total", "counter", "Errors logged by the bot.");
+        write_sample(&mut output, "errors_total", &[], errors.total as f64);
+        write_header(&mut output, "errors_recent", "gauge", "Errors logged within a sliding window.");
+        write_sample(&mut output, "errors_recent", &[("window", "1m")], errors.last_minute as f64);
+        write_sample(&mut output, "errors_recent", &[("window", "5m")], errors.last_five_minutes as f64);
+        write_sample(&mut output, "errors_recent", &[("window", "1h")], errors.last_hour as f64);
+
+        let latency = self.metrics.get_latency().await;
+        write_header(&mut output, "latency_milliseconds", "summary", "Recorded latencies in milliseconds.");
+        write_summary(&mut output, "latency_milliseconds", &[], &latency);
+
+        let caches: BTreeMap<_, _> = self.metrics.get_cache_stats().await.into_iter().collect();
+        write_header(&mut output, "cache_requests_total", "counter", "Cache lookups by cache and result.");
+        for (cache, stats) in &caches {
+            write_sample(&mut output, "cache_requests_total", &[("cache", cache), ("result", "hit")], stats.hits as f64);
+            write_sample(&mut output, "cache_requests_total", &[("cache", cache), ("result", "miss")], stats.misses as f64);
+        }
+        write_header(&mut output, "cache_evictions_total", "counter", "Entries evicted to stay within capacity.");
+        for (cache, stats) in &caches {
+            write_sample(&mut output, "cache_evictions_total", &[("cache", cache)], stats.evictions as f64);
+        }
+        write_header(&mut output, "cache_expirations_total", "counter", "Entries dropped after their TTL.");
+        for (cache, stats) in &caches {
+            write_sample(&mut output, "cache_expirations_total", &[("cache", cache)], stats.expirations as f64);
+        }
+
+        let gauges: BTreeMap<_, _> = self.metrics.get_gauges().await.into_iter().collect();
+        for (name, value) in gauges {
+            let name = sanitize_name(&name);
+            write_header(&mut output, &name, "gauge", "Gauge reported by the bot.");
+            write_sample(&mut output, &name, &[], value);
+        }
+
+        output
+    }
+}
+
+// Only the request line matters, so stop reading once the headers are complete.
+async fn read_request(stream: &mut TcpStream, buffer: &mut [u8]) -> BotResult<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        let n = stream.read(&mut buffer[read..]).await?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+        if buffer[..read].windows(4).any(|window| window == b"\r\n\r\n") {
+            break;
+        }
+    }
+    Ok(read)
+}
+
+fn write_summary(output: &mut String, name: &str, labels: &[(&str, &str)], snapshot: &HistogramSnapshot) {
+    if snapshot.count > 0 {
+        for (quantile, value) in [("0.5", snapshot.p50), ("0.95", snapshot.p95), ("0.99", snapshot.p99)] {
+            let mut quantile_labels = labels.to_vec();
+            quantile_labels.push(("quantile", quantile));
+            write_sample(output, name, &quantile_labels, value);
+        }
+    }
+    write_sample(output, &format!("{}_sum", name), labels, snapshot.sum as f64);
+    write_sample(output, &format!("{}_count", name), labels, snapshot.count as f64);
+}
+
+fn http_response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body
+    )
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+fn write_sample(output: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    output.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels.iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+            .collect();
+        let _ = write!(output, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(output, " {}", value);
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::metrics::CommandOutcome;
+    use crate::utils::task_manager::TaskManager;
+
+    fn exporter(metrics: Arc<Metrics>) -> Arc<PrometheusExporter> {
+        let config = MetricsConfig { enabled: true, bind_address: "127.0.0.1".to_string(), port: 0, guild_label: false };
+        Arc::new(PrometheusExporter::new(&config, metrics).unwrap())
+    }
+
+    #[tokio::test]
+    async fn commands_are_labelled_by_context_by_default() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_command("ping", Some(123456789), CommandOutcome::Ok, Duration::from_millis(5)).await;
+        metrics.record_command("ping", Some(987654321), CommandOutcome::Ok, Duration::from_millis(5)).await;
+        metrics.record_command("ping", None, CommandOutcome::Ok, Duration::from_millis(5)).await;
+
+        let output = exporter(metrics).render().await;
+        assert!(output.contains("commands_total{command=\"ping\",context=\"guild\",outcome=\"ok\"} 2"));
+        assert!(output.contains("commands_total{command=\"ping\",context=\"dm\",outcome=\"ok\"} 1"));
+        assert!(!output.contains("123456789"));
+    }
+
+    #[tokio::test]
+    async fn commands_are_labelled_by_guild_when_enabled() {
+        let metrics = Arc::new(Metrics::new().with_guild_label(true));
+        metrics.record_command("ping", Some(123456789), CommandOutcome::Ok, Duration::from_millis(5)).await;
+        metrics.record_command("ping", Some(987654321), CommandOutcome::Ok, Duration::from_millis(5)).await;
+        metrics.record_command("ping", None, CommandOutcome::UserError, Duration::from_millis(5)).await;
+
+        let output = exporter(metrics).render().await;
+        assert!(output.contains("commands_total{command=\"ping\",context=\"guild\",guild=\"123456789\",outcome=\"ok\"} 1"));
+        assert!(output.contains("commands_total{command=\"ping\",context=\"guild\",guild=\"987654321\",outcome=\"ok\"} 1"));
+        assert!(output.contains("commands_total{command=\"ping\",context=\"dm\",outcome=\"user_error\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_over_http() {
+        let exporter = exporter(Arc::new(Metrics::new()));
+        let listener = exporter.bind().await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let tasks = TaskManager::new(1);
+        let server = tokio::spawn(Arc::clone(&exporter).serve(listener, tasks.shutdown_token()));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        tasks.shutdown(Duration::ZERO).await;
+        server.await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE commands_total counter"));
+    }
+}
@@ -1,84 +1,234 @@
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
 use tokio::time::{Duration, Instant};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitScope {
+    #[default]
+    User,
+    Channel,
+    Guild,
+    Global,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub count: u32,
+    pub period: Duration,
+    pub burst: u32,
+    pub scope: RateLimitScope,
+}
+
+impl RateLimit {
+    pub fn new(count: u32, period: Duration) -> Self {
+        Self {
+            count: count.max(1),
+            period,
+            burst: count.max(1),
+            scope: RateLimitScope::User,
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    pub fn scope(mut self, scope: RateLimitScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    fn emission_interval(&self) -> Duration {
+        self.period / self.count
+    }
+
+    fn tolerance(&self) -> Duration {
+        self.emission_interval() * (self.burst - 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimitSubject {
+    pub user_id: u64,
+    pub channel_id: u64,
+    pub guild_id: Option<u64>,
+}
+
+impl RateLimitSubject {
+    pub fn user(user_id: u64) -> Self {
+        Self { user_id, ..Default::default() }
+    }
+
+    fn bucket_id(&self, scope: RateLimitScope) -> u64 {
+        match scope {
+            RateLimitScope::User => self.user_id,
+            RateLimitScope::Channel => self.channel_id,
+            // Direct messages have no guild, so they share a bucket per channel instead.
+            RateLimitScope::Guild => self.guild_id.unwrap_or(self.channel_id),
+            RateLimitScope::Global => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitResult {
+    Allowed { remaining: u32 },
+    Limited { retry_after: Duration },
+}
+
+impl RateLimitResult {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, RateLimitResult::Allowed { .. })
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RateLimitResult::Allowed { .. } => None,
+            RateLimitResult::Limited { retry_after } => Some(*retry_after),
+        }
+    }
+}
+
+#[derive(Default)]
+struct RateLimiterState {
+    limits: HashMap<String, RateLimit>,
+    // GCRA keeps a single "theoretical arrival time" per bucket instead of a request history.
+    buckets: HashMap<(String, u64), Instant>,
+}
+
 pub struct RateLimiter {
-    limits: Arc<Mutex<HashMap<String, (u32, Duration)>>>,
-    usage: Arc<Mutex<HashMap<(String, u64), Vec<Instant>>>>,
+    state: Mutex<RateLimiterState>,
 }
 
 impl RateLimiter {
     pub fn new() -> Self {
         Self {
-            limits: Arc::new(Mutex::new(HashMap::new())),
-            usage: Arc::new(Mutex::new(HashMap::new())),
+            state: Mutex::new(RateLimiterState::default()),
         }
     }
 
-    pub async fn add_limit(&self, key: &str, count: u32, duration: Duration) {
-        let mut limits = self.limits.lock().await;
-        limits.insert(key.to_string(), (count, duration));
+    pub fn add_limit(&self, key: &str, limit: RateLimit) {
+        let mut state = self.state.lock().unwrap();
+        state.limits.insert(key.to_string(), limit);
     }
 
-    pub async fn check(&self, key: &str, user_id: u64) -> bool {
-        let now = Instant::now();
-        let mut usage = self.usage.lock().await;
-        let limits = self.limits.lock().await;
-
-        let user_key = (key.to_string(), user_id);
-
-        if let Some(&(count, duration)) = limits.get(key) {
-            let times = usage.entry(user_key).or_insert_with(Vec::new);
-            times.retain(|&t| now.duration_since(t) < duration);
-            
-            if times.len() >= count as usize {
-                false
-            } else {
-                times.push(now);
-                true
-            }
-        } else {
-            true
-        }
+    pub fn remove_limit(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.limits.remove(key);
+        state.buckets.retain(|(bucket_key, _), _| bucket_key != key);
     }
 
-    pub async fn get_remaining(&self, key: &str, user_id: u64) -> Option<u32> {
+    pub fn check(&self, key: &str, subject: &RateLimitSubject) -> RateLimitResult {
+        self.check_all(&[key], subject)
+    }
+
+    /// Checks every limit in `keys` and only takes a token from each if all of them allow the
+    /// request, so a request denied by one limit does not use up the others.
+    pub fn check_all(&self, keys: &[&str], subject: &RateLimitSubject) -> RateLimitResult {
         let now = Instant::now();
-        let usage = self.usage.lock().await;
-        let limits = self.limits.lock().await;
+        let mut state = self.state.lock().unwrap();
+
+        let mut remaining = u32::MAX;
+        let mut retry_after: Option<Duration> = None;
+        let mut updates = Vec::with_capacity(keys.len());
+        for &key in keys {
+            let limit = match state.limits.get(key) {
+                Some(&limit) => limit,
+                None => continue,
+            };
 
-        let user_key = (key.to_string(), user_id);
+            let bucket = (key.to_string(), subject.bucket_id(limit.scope));
+            let tat = state.buckets.get(&bucket).copied().unwrap_or(now).max(now);
+            let tolerance = limit.tolerance();
+            let backlog = tat - now;
 
-        if let Some(&(count, duration)) = limits.get(key) {
-            if let Some(times) = usage.get(&user_key) {
-                let valid_times: Vec<_> = times.iter().filter(|&&t| now.duration_since(t) < duration).collect();
-                Some(count.saturating_sub(valid_times.len() as u32))
-            } else {
-                Some(count)
+            if backlog > tolerance {
+                retry_after = retry_after.max(Some(backlog - tolerance));
+                continue;
             }
-        } else {
-            None
+
+            let interval = limit.emission_interval();
+            remaining = remaining.min(((tolerance - backlog).as_nanos() / interval.as_nanos().max(1)) as u32);
+            updates.push((bucket, tat + interval));
         }
+
+        if let Some(retry_after) = retry_after {
+            return RateLimitResult::Limited { retry_after };
+        }
+        state.buckets.extend(updates);
+        RateLimitResult::Allowed { remaining }
+    }
+
+    pub fn get_remaining(&self, key: &str, subject: &RateLimitSubject) -> Option<u32> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+
+        let limit = state.limits.get(key)?;
+        let bucket = (key.to_string(), subject.bucket_id(limit.scope));
+        let backlog = state.buckets.get(&bucket)
+            .map(|&tat| tat.saturating_duration_since(now))
+            .unwrap_or_default();
+        let available = (limit.tolerance() + limit.emission_interval()).saturating_sub(backlog);
+        Some((available.as_nanos() / limit.emission_interval().as_nanos().max(1)) as u32)
     }
 
-    pub async fn reset(&self, key: &str, user_id: u64) {
-        let mut usage = self.usage.lock().await;
-        usage.remove(&(key.to_string(), user_id));
+    pub fn reset(&self, key: &str, subject: &RateLimitSubject) {
+        let mut state = self.state.lock().unwrap();
+        let bucket_id = match state.limits.get(key) {
+            Some(limit) => subject.bucket_id(limit.scope),
+            None => return,
+        };
+        state.buckets.remove(&(key.to_string(), bucket_id));
     }
 
-    pub async fn clean_up(&self) {
+    pub fn clean_up(&self) -> usize {
         let now = Instant::now();
-        let mut usage = self.usage.lock().await;
-        let limits = self.limits.lock().await;
-
-        usage.retain(|(key, _), times| {
-            if let Some(&(_, duration)) = limits.get(key) {
-                times.retain(|&t| now.duration_since(t) < duration);
-                !times.is_empty()
-            } else {
-                false
-            }
-        });
+        let mut state = self.state.lock().unwrap();
+        let before = state.buckets.len();
+        // A bucket whose arrival time has passed is indistinguishable from a fresh one.
+        state.buckets.retain(|_, tat| *tat > now);
+        before - state.buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_allowed_then_limited() {
+        let limiter = RateLimiter::new();
+        limiter.add_limit("ping", RateLimit::new(2, Duration::from_secs(10)));
+        let subject = RateLimitSubject::user(1);
+
+        assert_eq!(limiter.check("ping", &subject), RateLimitResult::Allowed { remaining: 1 });
+        assert_eq!(limiter.check("ping", &subject), RateLimitResult::Allowed { remaining: 0 });
+        assert!(!limiter.check("ping", &subject).is_allowed());
+        // Other users have their own bucket.
+        assert!(limiter.check("ping", &RateLimitSubject::user(2)).is_allowed());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn a_denied_request_does_not_consume_the_other_limits() {
+        let limiter = RateLimiter::new();
+        limiter.add_limit("command:ping", RateLimit::new(5, Duration::from_secs(10)));
+        limiter.add_limit("command", RateLimit::new(1, Duration::from_secs(10)));
+        let subject = RateLimitSubject::user(1);
+
+        assert!(limiter.check_all(&["command:ping", "command"], &subject).is_allowed());
+        assert_eq!(limiter.get_remaining("command:ping", &subject), Some(4));
+        for _ in 0..3 {
+            assert!(!limiter.check_all(&["command:ping", "command"], &subject).is_allowed());
+        }
+        assert_eq!(limiter.get_remaining("command:ping", &subject), Some(4));
+    }
+
+    #[test]
+    fn unknown_keys_are_unlimited() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.check("missing", &RateLimitSubject::user(1)), RateLimitResult::Allowed { remaining: u32::MAX });
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use tokio::sync::{Notify, RwLock};
+use crate::bot::error::{BotResult, BotError};
+use crate::database::models::ScheduledJob;
+use crate::database::repositories::ScheduledJobRepository;
+use crate::telemetry::random_u64;
+use crate::utils::cron::CronExpression;
+use crate::utils::metrics::Metrics;
+use crate::utils::task_manager::{ShutdownToken, TaskManager};
+
+// Upper bound on how long the scheduler sleeps, so clock jumps are picked up eventually.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const MAX_COUNTED_MISSED_RUNS: i64 = 10_000;
+// Shorter intervals would keep the scheduler loop busy, and are stored in whole seconds anyway.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Cron(CronExpression),
+    Every(Duration),
+    At(DateTime<Utc>),
+}
+
+impl Schedule {
+    pub fn cron(expression: &str) -> BotResult<Self> {
+        Ok(Schedule::Cron(expression.parse()?))
+    }
+
+    pub fn every(interval: Duration) -> Self {
+        Schedule::Every(interval)
+    }
+
+    pub fn at(time: DateTime<Utc>) -> Self {
+        Schedule::At(time)
+    }
+
+    /// The next run strictly after `after`, or `None` once a one-shot job has run.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Cron(expression) => expression.next_after(after),
+            Schedule::Every(interval) => chrono::Duration::from_std(*interval).ok().map(|interval| after + interval),
+            Schedule::At(time) => Some(*time).filter(|time| *time > after),
+        }
+    }
+
+    fn first_run(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::At(time) => Some(*time),
+            _ => self.next_after(now),
+        }
+    }
+
+    // Runs that should have happened from `first` up to `now`, inclusive.
+    fn missed_runs(&self, first: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+        let mut missed = 0;
+        let mut next = Some(first);
+        while let Some(time) = next.filter(|time| *time <= now) {
+            missed += 1;
+            if missed >= MAX_COUNTED_MISSED_RUNS {
+                break;
+            }
+            next = match self {
+                Schedule::At(_) => None,
+                _ => self.next_after(time),
+            };
+        }
+        missed
+    }
+}
+
+// Stored with the job, so a changed schedule can be told apart from the one persisted.
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Cron(expression) => write!(f, "cron:{}", expression),
+            Schedule::Every(interval) => write!(f, "every:{}s", interval.as_secs()),
+            Schedule::At(time) => write!(f, "at:{}", time.to_rfc3339()),
+        }
+    }
+}
+
+type JobHandler = Arc<dyn Fn() -> BoxFuture<'static, BotResult<()>> + Send + Sync>;
+
+pub struct JobDefinition {
+    pub name: String,
+    pub schedule: Schedule,
+    handler: JobHandler,
+    lock_timeout: Duration,
+}
+
+impl JobDefinition {
+    pub fn new<F, Fut>(name: &str, schedule: Schedule, handler: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = BotResult<()>> + Send + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            schedule,
+            handler: Arc::new(move || Box::pin(handler())),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    /// How long a run may hold the job's lock before another run is allowed to take over,
+    /// in case the process running it died.
+    pub fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+}
+
+struct RegisteredJob {
+    schedule: Schedule,
+    handler: JobHandler,
+    lock_timeout: Duration,
+    next_run: Option<DateTime<Utc>>,
+    running: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobStatus {
+    pub job: ScheduledJob,
+    pub registered: bool,
+    pub running: bool,
+}
+
+/// Runs jobs on cron, interval or one-shot schedules. Job state is persisted through the
+/// repository, so runs missed while the bot was offline are detected on registration and
+/// a job never runs twice at once, even across processes sharing the database.
+pub struct Scheduler {
+    repository: Arc<dyn ScheduledJobRepository>,
+    task_manager: Arc<TaskManager>,
+    metrics: Arc<Metrics>,
+    jobs: RwLock<HashMap<String, RegisteredJob>>,
+    wake: Notify,
+}
+
+impl Scheduler {
+    pub fn new(repository: Arc<dyn ScheduledJobRepository>, task_manager: Arc<TaskManager>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            repository,
+            task_manager,
+            metrics,
+            jobs: RwLock::new(HashMap::new()),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Registers a job, replacing any job with the same name. If the stored schedule matches and
+    /// runs were missed while the bot was offline, they are reported and the job runs once now.
+    /// Intervals shorter than a second are rejected.
+    pub async fn register(&self, definition: JobDefinition) -> BotResult<()> {
+        if let Schedule::Every(interval) = definition.schedule {
+            if interval < MIN_INTERVAL {
+                return Err(BotError::Scheduler(format!(
+                    "Job '{}' has an interval of {:?}, but intervals must be at least {:?}",
+                    definition.name, interval, MIN_INTERVAL
+                )));
+            }
+        }
+        let now = Utc::now();
+        let schedule = definition.schedule.to_string();
+        let stored = self.repository.get(&definition.name).await?;
+
+        let (next_run, missed) = match stored {
+            Some(job) if job.schedule == schedule => match job.next_run_at {
+                Some(next_run) if next_run <= now && !self.jobs.read().await.contains_key(&definition.name) => {
+                    let missed = definition.schedule.missed_runs(next_run, now);
+                    log::warn!(
+                        "Job '{}' missed {} run(s) since {} while the bot was offline, running it now",
+                        definition.name, missed, next_run
+                    );
+                    self.metrics.log_events("scheduler_missed_run", missed as usize).await;
+                    (Some(now), missed)
+                }
+                next_run => (next_run, job.missed_runs),
+            },
+            _ => (definition.schedule.first_run(now), 0),
+        };
+        self.repository.upsert(&definition.name, &schedule, next_run, missed).await?;
+
+        let mut jobs = self.jobs.write().await;
+        let running = jobs.get(&definition.name)
+            .map(|job| Arc::clone(&job.running))
+            .unwrap_or_default();
+        jobs.insert(definition.name.clone(), RegisteredJob {
+            schedule: definition.schedule,
+            handler: definition.handler,
+            lock_timeout: definition.lock_timeout,
+            next_run,
+            running,
+        });
+        drop(jobs);
+
+        log::debug!("Registered job '{}' ({}), next run {:?}", definition.name, schedule, next_run);
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Stops scheduling the job in this process. Its stored state is kept, so registering it
+    /// again later reports the runs it missed in between.
+    pub async fn unregister(&self, name: &str) -> bool {
+        self.jobs.write().await.remove(name).is_some()
+    }
+
+    /// Stops scheduling the job and forgets its stored state.
+    pub async fn remove(&self, name: &str) -> BotResult<()> {
+        self.unregister(name).await;
+        self.repository.remove(name).await
+    }
+
+    pub async fn list(&self) -> BotResult<Vec<JobStatus>> {
+        let jobs = self.jobs.read().await;
+        Ok(self.repository.list().await?
+            .into_iter()
+            .map(|job| {
+                let registered = jobs.get(&job.name);
+                JobStatus {
+                    registered: registered.is_some(),
+                    running: registered.map_or(false, |registered| registered.running.load(Ordering::Acquire)),
+                    job,
+                }
+            })
+            .collect())
+    }
+
+    /// Starts due jobs until shutdown. Jobs already running are left to finish.
+    pub async fn run(self: Arc<Self>, shutdown: ShutdownToken) {
+        while !shutdown.is_cancelled() {
+            let now = Utc::now();
+            let mut due = Vec::new();
+            let mut wake_at = now + chrono::Duration::from_std(MAX_SLEEP).unwrap_or_else(|_| chrono::Duration::seconds(60));
+            for (name, job) in self.jobs.read().await.iter() {
+                match job.next_run {
+                    Some(next_run) if next_run <= now => due.push(name.clone()),
+                    Some(next_run) => wake_at = wake_at.min(next_run),
+                    None => {}
+                }
+            }
+
+            for name in due {
+                if let Err(why) = self.start(&name, now).await {
+                    log::error!("Failed to start job '{}': {:?}", name, why);
+                }
+            }
+
+            let sleep = (wake_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = self.wake.notified() => {}
+                _ = shutdown.cancelled() => {}
+            }
+        }
+    }
+
+    async fn start(&self, name: &str, now: DateTime<Utc>) -> BotResult<()> {
+        // Advance the schedule first, so a skipped run is not retried in a tight loop.
+        let (handler, running, lock_timeout, next_run) = {
+            let mut jobs = self.jobs.write().await;
+            let job = match jobs.get_mut(name) {
+                Some(job) => job,
+                None => return Ok(()),
+            };
+            job.next_run = job.schedule.next_after(now);
+            (Arc::clone(&job.handler), Arc::clone(&job.running), job.lock_timeout, job.next_run)
+        };
+
+        if running.swap(true, Ordering::AcqRel) {
+            log::warn!("Skipping run of job '{}' because the previous run is still going", name);
+            self.metrics.log_event("scheduler_skipped_run").await;
+            return Ok(());
+        }
+
+        // A run that outlives its lock must not release the lock another process has taken since,
+        // so every lock carries a token that only this run knows.
+        let token = format!("{:016x}{:016x}", random_u64(), random_u64());
+        let locked = match self.try_lock(name, &token, now, lock_timeout, next_run).await {
+            Ok(locked) => locked,
+            Err(why) => {
+                running.store(false, Ordering::Release);
+                return Err(why);
+            }
+        };
+        if !locked {
+            running.store(false, Ordering::Release);
+            log::warn!("Skipping run of job '{}' because another run holds its lock", name);
+            self.metrics.log_event("scheduler_skipped_run").await;
+            return Ok(());
+        }
+        let mut guard = RunGuard { name: name.to_string(), token, running, repository: Arc::clone(&self.repository), completed: false };
+
+        let metrics = Arc::clone(&self.metrics);
+        let job_name = name.to_string();
+        self.task_manager.spawn(&format!("job:{}", name), async move {
+            let started = Instant::now();
+            let result = handler().await;
+            let (status, error) = match &result {
+                Ok(_) => ("ok", None),
+                Err(why) => {
+                    log::error!("Job '{}' failed: {:?}", job_name, why);
+                    ("error", Some(why.to_string()))
+                }
+            };
+            log::debug!("Job '{}' finished with status {} in {:?}", job_name, status, started.elapsed());
+            metrics.log_event(&format!("scheduler_job_{}", status)).await;
+            match guard.repository.complete(&job_name, &guard.token, Utc::now(), status, error.as_deref()).await {
+                Ok(true) => {}
+                Ok(false) => log::warn!("Job '{}' outlived its lock, which another run has taken; its result was not recorded", job_name),
+                Err(why) => log::error!("Failed to record the result of job '{}': {:?}", job_name, why),
+            }
+            guard.completed = true;
+        }).await
+    }
+
+    async fn try_lock(&self, name: &str, token: &str, now: DateTime<Utc>, lock_timeout: Duration, next_run: Option<DateTime<Utc>>) -> BotResult<bool> {
+        let locked_until = now + chrono::Duration::from_std(lock_timeout)
+            .map_err(|e| BotError::Scheduler(format!("Invalid lock timeout for job '{}': {}", name, e)))?;
+        self.repository.try_lock(name, token, now, locked_until, next_run).await
+    }
+}
+
+// Clears the running flag when a run ends, and releases the stored lock if the run was
+// cancelled (for example by shutdown) before it could record its result. Only created once
+// `token` holds the lock.
+struct RunGuard {
+    name: String,
+    token: String,
+    running: Arc<AtomicBool>,
+    repository: Arc<dyn ScheduledJobRepository>,
+    completed: bool,
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if self.completed {
+            return;
+        }
+        let name = std::mem::take(&mut self.name);
+        let token = std::mem::take(&mut self.token);
+        let repository = Arc::clone(&self.repository);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                if let Err(why) = repository.complete(&name, &token, Utc::now(), "cancelled", None).await {
+                    log::error!("Failed to release the lock of job '{}': {:?}", name, why);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::repositories::Repositories;
+
+    fn scheduler() -> Scheduler {
+        Scheduler::new(Repositories::in_memory().scheduled_jobs, Arc::new(TaskManager::new(1)), Arc::new(Metrics::new()))
+    }
+
+    #[tokio::test]
+    async fn intervals_below_a_second_are_rejected() {
+        let scheduler = scheduler();
+        for interval in [Duration::ZERO, Duration::from_millis(500)] {
+            let job = JobDefinition::new("busy", Schedule::every(interval), || async { Ok(()) });
+            assert!(matches!(scheduler.register(job).await, Err(BotError::Scheduler(_))));
+        }
+        assert!(scheduler.list().await.unwrap().is_empty());
+
+        let job = JobDefinition::new("steady", Schedule::every(Duration::from_secs(1)), || async { Ok(()) });
+        scheduler.register(job).await.unwrap();
+        assert_eq!(scheduler.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_lock_held_elsewhere_is_left_alone() {
+        let repository = Repositories::in_memory().scheduled_jobs;
+        let scheduler = Scheduler::new(Arc::clone(&repository), Arc::new(TaskManager::new(1)), Arc::new(Metrics::new()));
+        let runs = Arc::new(AtomicBool::new(false));
+        let job = JobDefinition::new("backup", Schedule::every(Duration::from_secs(3600)), {
+            let runs = Arc::clone(&runs);
+            move || {
+                runs.store(true, Ordering::Release);
+                async { Ok(()) }
+            }
+        });
+        scheduler.register(job).await.unwrap();
+
+        // Another process holds the lock.
+        let now = Utc::now();
+        assert!(repository.try_lock("backup", "other", now, now + chrono::Duration::minutes(5), None).await.unwrap());
+        scheduler.start("backup", now).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(!runs.load(Ordering::Acquire));
+        let job = repository.get("backup").await.unwrap().unwrap();
+        assert_eq!(job.lock_token.as_deref(), Some("other"));
+        assert!(job.locked_until.is_some());
+        // The skipped run does not count as running.
+        assert!(scheduler.list().await.unwrap().iter().all(|status| !status.running));
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use serenity::model::id::{ChannelId, RoleId};
+use serenity::model::permissions::Permissions;
+use tokio::sync::RwLock;
+use crate::bot::error::{BotResult, BotError};
+use crate::lang::Lang;
+use crate::utils::format_duration;
+
+#[derive(Debug, Clone)]
+pub enum SettingKind {
+    String { max_length: usize },
+    Bool,
+    Integer { min: i64, max: i64 },
+    Channel,
+    Role,
+    Duration { min: Duration, max: Duration },
+    Enum(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingValue {
+    String(String),
+    Bool(bool),
+    Integer(i64),
+    Channel(ChannelId),
+    Role(RoleId),
+    Duration(Duration),
+    Enum(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct SettingDefinition {
+    pub key: String,
+    pub kind: SettingKind,
+    pub default: SettingValue,
+    pub description: String,
+    pub required_permissions: Permissions,
+}
+
+pub struct SettingsRegistry {
+    definitions: RwLock<HashMap<String, SettingDefinition>>,
+}
+
+impl SettingKind {
+    pub fn parse(&self, input: &str) -> Result<SettingValue, String> {
+        let input = input.trim();
+        match self {
+            SettingKind::String { max_length } => {
+                if input.chars().count() > *max_length {
+                    Err(format!("must be at most {} characters", max_length))
+                } else {
+                    Ok(SettingValue::String(input.to_string()))
+                }
+            }
+            SettingKind::Bool => match input.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Ok(SettingValue::Bool(true)),
+                "false" | "no" | "off" | "0" => Ok(SettingValue::Bool(false)),
+                _ => Err("must be true or false".to_string()),
+            },
+            SettingKind::Integer { min, max } => {
+                let value: i64 = input.parse().map_err(|_| "must be a whole number".to_string())?;
+                if value < *min || value > *max {
+                    Err(format!("must be between {} and {}", min, max))
+                } else {
+                    Ok(SettingValue::Integer(value))
+                }
+            }
+            SettingKind::Channel => parse_mention(input, "<#", ">")
+                .map(|id| SettingValue::Channel(ChannelId(id)))
+                .ok_or_else(|| "must be a channel mention or ID".to_string()),
+            SettingKind::Role => parse_mention(input, "<@&", ">")
+                .map(|id| SettingValue::Role(RoleId(id)))
+                .ok_or_else(|| "must be a role mention or ID".to_string()),
+            SettingKind::Duration { min, max } => {
+                let value = parse_duration(input).ok_or_else(|| "must be a duration such as 30s, 5m or 1h30m".to_string())?;
+                if value < *min || value > *max {
+                    Err(format!("must be between {} and {}", format_duration(*min), format_duration(*max)))
+                } else {
+                    Ok(SettingValue::Duration(value))
+                }
+            }
+            SettingKind::Enum(options) => options.iter()
+                .find(|option| option.eq_ignore_ascii_case(input))
+                .map(|option| SettingValue::Enum(option.clone()))
+                .ok_or_else(|| format!("must be one of: {}", options.join(", "))),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            SettingKind::String { max_length } => format!("text (up to {} characters)", max_length),
+            SettingKind::Bool => "true/false".to_string(),
+            SettingKind::Integer { min, max } => format!("number between {} and {}", min, max),
+            SettingKind::Channel => "channel".to_string(),
+            SettingKind::Role => "role".to_string(),
+            SettingKind::Duration { min, max } => format!("duration between {} and {}", format_duration(*min), format_duration(*max)),
+            SettingKind::Enum(options) => format!("one of: {}", options.join(", ")),
+        }
+    }
+}
+
+impl SettingValue {
+    pub fn to_storage(&self) -> String {
+        match self {
+            SettingValue::String(value) | SettingValue::Enum(value) => value.clone(),
+            SettingValue::Bool(value) => value.to_string(),
+            SettingValue::Integer(value) => value.to_string(),
+            SettingValue::Channel(id) => id.0.to_string(),
+            SettingValue::Role(id) => id.0.to_string(),
+            SettingValue::Duration(value) => format!("{}s", value.as_secs()),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            SettingValue::String(value) | SettingValue::Enum(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            SettingValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            SettingValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_channel(&self) -> Option<ChannelId> {
+        match self {
+            SettingValue::Channel(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    pub fn as_role(&self) -> Option<RoleId> {
+        match self {
+            SettingValue::Role(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    pub fn as_duration(&self) -> Option<Duration> {
+        match self {
+            SettingValue::Duration(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SettingValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingValue::String(value) | SettingValue::Enum(value) => write!(f, "{}", value),
+            SettingValue::Bool(value) => write!(f, "{}", value),
+            SettingValue::Integer(value) => write!(f, "{}", value),
+            SettingValue::Channel(id) => write!(f, "<#{}>", id.0),
+            SettingValue::Role(id) => write!(f, "<@&{}>", id.0),
+            SettingValue::Duration(value) => write!(f, "{}", format_duration(*value)),
+        }
+    }
+}
+
+impl SettingDefinition {
+    pub fn new(key: &str, kind: SettingKind, default: SettingValue, description: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            kind,
+            default,
+            description: description.to_string(),
+            required_permissions: Permissions::MANAGE_GUILD,
+        }
+    }
+
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.required_permissions = permissions;
+        self
+    }
+}
+
+impl SettingsRegistry {
+    pub fn new() -> Self {
+        Self {
+            definitions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_core_settings(lang: &Lang) -> Self {
+        let core = vec![
+            SettingDefinition::new(
+                "welcome_message",
+                SettingKind::String { max_length: 2000 },
+                SettingValue::String(lang.get("events.member_join").to_string()),
+                "Message sent to new members, {user} is replaced with their name",
+            ),
+            SettingDefinition::new(
+                "goodbye_message",
+                SettingKind::String { max_length: 2000 },
+                SettingValue::String(lang.get("events.member_leave").to_string()),
+                "Message sent to members who leave, {user} is replaced with their name",
+            ),
+        ];
+
+        Self {
+            definitions: RwLock::new(core.into_iter().map(|d| (d.key.clone(), d)).collect()),
+        }
+    }
+
+    pub async fn register(&self, definition: SettingDefinition) -> BotResult<()> {
+        definition.kind.parse(&definition.default.to_storage())
+            .map_err(|e| BotError::Settings(format!("Default for '{}' {}", definition.key, e)))?;
+
+        let mut definitions = self.definitions.write().await;
+        if definitions.contains_key(&definition.key) {
+            return Err(BotError::Settings(format!("Setting '{}' is already registered", definition.key)));
+        }
+        definitions.insert(definition.key.clone(), definition);
+        Ok(())
+    }
+
+    pub async fn unregister(&self, key: &str) {
+        let mut definitions = self.definitions.write().await;
+        definitions.remove(key);
+    }
+
+    pub async fn get(&self, key: &str) -> Option<SettingDefinition> {
+        self.definitions.read().await.get(key).cloned()
+    }
+
+    pub async fn list(&self) -> Vec<SettingDefinition> {
+        let mut definitions: Vec<SettingDefinition> = self.definitions.read().await.values().cloned().collect();
+        definitions.sort_by(|a, b| a.key.cmp(&b.key));
+        definitions
+    }
+
+    pub async fn validate(&self, key: &str, input: &str) -> BotResult<SettingValue> {
+        let definition = self.get(key).await
+            .ok_or_else(|| BotError::Settings(format!("Unknown setting '{}'", key)))?;
+        definition.kind.parse(input)
+            .map_err(|e| BotError::Settings(format!("'{}' {}", key, e)))
+    }
+}
+
+fn parse_mention(input: &str, prefix: &str, suffix: &str) -> Option<u64> {
+    let id = input.strip_prefix(prefix)
+        .and_then(|rest| rest.strip_suffix(suffix))
+        .unwrap_or(input);
+    id.parse().ok().filter(|&id| id != 0)
+}
+
+fn parse_duration(input: &str) -> Option<Duration> {
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().ok()?;
+        number.clear();
+        let multiplier = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return None,
+        };
+        // Rejects input such as "999999999999999999d" instead of overflowing.
+        total = value.checked_mul(multiplier).and_then(|seconds| total.checked_add(seconds))?;
+    }
+
+    // "0s" is zero just like "0"; whether zero is allowed is up to the setting's minimum.
+    if number.is_empty() && !input.is_empty() {
+        Some(Duration::from_secs(total))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse_units_and_plain_seconds() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Some(Duration::from_secs(172_800)));
+        assert_eq!(parse_duration("1m1s"), Some(Duration::from_secs(61)));
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("5m3"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("0h0m"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn overflowing_durations_are_rejected() {
+        assert_eq!(parse_duration("999999999999999999d"), None);
+        assert_eq!(parse_duration("18446744073709551615s1s"), None);
+        assert_eq!(parse_duration("99999999999999999999s"), None);
+
+        let kind = SettingKind::Duration { min: Duration::from_secs(1), max: Duration::from_secs(3600) };
+        assert!(kind.parse("999999999999999999d").is_err());
+    }
+}
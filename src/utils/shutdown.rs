@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Tracks in-flight interactions and refuses new ones once shutdown has begun.
+pub struct ShutdownCoordinator {
+    accepting: AtomicBool,
+    in_flight: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+}
+
+/// Held for the duration of one interaction; dropping it marks the interaction as finished.
+pub struct InFlightGuard {
+    in_flight: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        Self {
+            accepting: AtomicBool::new(true),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            idle: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Returns `None` once shutdown has started, so the caller can turn the interaction away.
+    pub fn try_begin(&self) -> Option<InFlightGuard> {
+        if !self.accepting.load(Ordering::Acquire) {
+            return None;
+        }
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        // Re-check so an interaction racing with `begin_shutdown` is either counted or refused.
+        if !self.accepting.load(Ordering::Acquire) {
+            drop(InFlightGuard { in_flight: Arc::clone(&self.in_flight), idle: Arc::clone(&self.idle) });
+            return None;
+        }
+        Some(InFlightGuard {
+            in_flight: Arc::clone(&self.in_flight),
+            idle: Arc::clone(&self.idle),
+        })
+    }
+
+    pub fn begin_shutdown(&self) {
+        self.accepting.store(false, Ordering::Release);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        !self.accepting.load(Ordering::Acquire)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Waits for in-flight interactions to finish, returning `false` if the deadline passed first.
+    pub async fn drain(&self, deadline: Duration) -> bool {
+        let wait = async {
+            loop {
+                let idle = self.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        tokio::time::timeout(deadline, wait).await.is_ok()
+    }
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves on SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(why) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for Ctrl+C: {}", why);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(why) => {
+                log::error!("Failed to listen for SIGTERM: {}", why);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => log::info!("Received Ctrl+C"),
+        _ = terminate => log::info!("Received SIGTERM"),
+    }
+}
@@ -1,76 +1,442 @@
 use tokio::task::JoinHandle;
+use std::any::Any;
 use std::collections::{HashMap, VecDeque};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use serde_json::json;
+use tokio::sync::{watch, Mutex};
 use crate::bot::error::{BotResult, BotError};
+use crate::telemetry::{random_u64, TelemetryManager};
 use std::future::Future;
 
+// Finished tasks are kept for introspection, up to this many.
+const MAX_FINISHED_TASKS: usize = 100;
+
+type BoxedTask = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+type TaskMap = Arc<Mutex<HashMap<String, JoinHandle<()>>>>;
+type TaskQueue = Arc<Mutex<VecDeque<(String, BoxedTask)>>>;
+type ShutdownSignal = Arc<watch::Sender<bool>>;
+type Telemetry = Option<Arc<TelemetryManager>>;
+// Never held across an await, so it can be read without an async context.
+type TaskStats = Arc<std::sync::Mutex<HashMap<String, TaskInfo>>>;
+
+/// Handed to long-running tasks so they can stop between steps once shutdown starts. Tasks
+/// still running when the grace period ends are aborted.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownToken {
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once shutdown has started.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                // The manager is gone, so nothing will ever ask this task to stop.
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Never,
+    /// Restart when the task returns an error or panics.
+    OnFailure,
+    /// Restart whenever the task ends, until shutdown.
+    Always,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskOptions {
+    pub restart: RestartPolicy,
+    /// Restarts allowed in a row; a run lasting at least `max_backoff` resets the count.
+    pub max_restarts: Option<u32>,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Cancel a task with the same name instead of failing to spawn.
+    pub replace_existing: bool,
+}
+
+impl Default for TaskOptions {
+    fn default() -> Self {
+        Self {
+            restart: RestartPolicy::OnFailure,
+            max_restarts: Some(10),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            replace_existing: false,
+        }
+    }
+}
+
+impl TaskOptions {
+    pub fn restart(policy: RestartPolicy) -> Self {
+        Self { restart: policy, ..Self::default() }
+    }
+
+    pub fn max_restarts(mut self, max_restarts: Option<u32>) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    pub fn replace_existing(mut self) -> Self {
+        self.replace_existing = true;
+        self
+    }
+
+    // Exponential backoff with "equal jitter": half the delay is fixed, the other half random.
+    fn backoff_for(&self, failures: u32) -> Duration {
+        let delay = self.initial_backoff
+            .checked_mul(2u32.saturating_pow(failures))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        let half = delay / 2;
+        half + half.mul_f64(random_u64() as f64 / u64::MAX as f64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl TaskState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Queued => "queued",
+            TaskState::Running => "running",
+            TaskState::Completed => "completed",
+            TaskState::Failed => "failed",
+            TaskState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, TaskState::Queued | TaskState::Running)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub name: String,
+    pub state: TaskState,
+    pub spawned_at: DateTime<Utc>,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Runs of this task name so far, including supervised restarts and earlier spawns.
+    pub run_count: u32,
+    pub last_error: Option<String>,
+    /// How long the task waited for a free slot before it first started.
+    pub queue_wait: Option<Duration>,
+}
+
+impl TaskInfo {
+    /// Time spent in the queue, still counting while the task is queued.
+    pub fn waited(&self) -> Duration {
+        self.queue_wait.unwrap_or_else(|| (Utc::now() - self.spawned_at).to_std().unwrap_or_default())
+    }
+}
+
+// Everything a spawned task needs to report back to its manager.
+#[derive(Clone)]
+struct Shared {
+    tasks: TaskMap,
+    queue: TaskQueue,
+    shutdown: ShutdownSignal,
+    telemetry: Telemetry,
+    stats: TaskStats,
+}
+
+impl Shared {
+    fn update(&self, name: &str, f: impl FnOnce(&mut TaskInfo)) {
+        if let Some(info) = self.stats.lock().unwrap().get_mut(name) {
+            f(info);
+        }
+    }
+
+    fn record_spawn(&self, name: &str, state: TaskState) {
+        let mut stats = self.stats.lock().unwrap();
+        // Names such as `job:backup` are spawned again for every run, so the count carries over.
+        let run_count = stats.get(name).map_or(0, |info| info.run_count);
+        stats.insert(name.to_string(), TaskInfo {
+            name: name.to_string(),
+            state,
+            spawned_at: Utc::now(),
+            last_started_at: None,
+            finished_at: None,
+            run_count,
+            last_error: None,
+            queue_wait: None,
+        });
+
+        let finished = stats.values().filter(|info| info.state.is_finished()).count();
+        if finished > MAX_FINISHED_TASKS {
+            let mut oldest: Vec<(DateTime<Utc>, String)> = stats.values()
+                .filter(|info| info.state.is_finished())
+                .map(|info| (info.finished_at.unwrap_or(info.spawned_at), info.name.clone()))
+                .collect();
+            oldest.sort();
+            for (_, name) in oldest.into_iter().take(finished - MAX_FINISHED_TASKS) {
+                stats.remove(&name);
+            }
+        }
+    }
+
+    fn record_start(&self, name: &str) {
+        self.update(name, |info| {
+            let now = Utc::now();
+            info.state = TaskState::Running;
+            info.last_started_at = Some(now);
+            info.run_count += 1;
+            if info.queue_wait.is_none() {
+                info.queue_wait = Some((now - info.spawned_at).to_std().unwrap_or_default());
+            }
+        });
+    }
+
+    fn record_finish(&self, name: &str, state: TaskState, error: Option<String>) {
+        self.update(name, |info| {
+            // A supervisor that gave up has already marked its task as failed.
+            if info.state.is_finished() {
+                return;
+            }
+            info.state = state;
+            info.finished_at = Some(Utc::now());
+            if error.is_some() {
+                info.last_error = error;
+            }
+        });
+    }
+}
+
 pub struct TaskManager {
-    tasks: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
-    queue: Arc<Mutex<VecDeque<(String, Box<dyn Future<Output = ()> + Send + 'static>)>>>,
+    shared: Shared,
     max_concurrent_tasks: usize,
 }
 
 impl TaskManager {
     pub fn new(max_concurrent_tasks: usize) -> Self {
+        let (shutdown, _) = watch::channel(false);
         Self {
-            tasks: Arc::new(Mutex::new(HashMap::new())),
-            queue: Arc::new(Mutex::new(VecDeque::new())),
+            shared: Shared {
+                tasks: Arc::new(Mutex::new(HashMap::new())),
+                queue: Arc::new(Mutex::new(VecDeque::new())),
+                shutdown: Arc::new(shutdown),
+                telemetry: None,
+                stats: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            },
             max_concurrent_tasks,
         }
     }
 
+    /// Reports task panics and restarts to telemetry.
+    pub fn with_telemetry(mut self, telemetry: Arc<TelemetryManager>) -> Self {
+        self.shared.telemetry = Some(telemetry);
+        self
+    }
+
+    pub fn max_concurrent_tasks(&self) -> usize {
+        self.max_concurrent_tasks
+    }
+
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        ShutdownToken { receiver: self.shared.shutdown.subscribe() }
+    }
+
     pub async fn spawn<F>(&self, name: &str, future: F) -> BotResult<()>
     where
         F: Future<Output = ()> + Send + 'static,
     {
-        let mut tasks = self.tasks.lock().await;
+        if *self.shared.shutdown.borrow() {
+            return Err(BotError::Internal(format!("Task '{}' was spawned during shutdown", name)));
+        }
+        let mut tasks = self.shared.tasks.lock().await;
+        let mut queue = self.shared.queue.lock().await;
+        if tasks.contains_key(name) || queue.iter().any(|(queued, _)| queued == name) {
+            return Err(BotError::Internal(format!("Task '{}' already exists", name)));
+        }
         if tasks.len() >= self.max_concurrent_tasks {
-            let mut queue = self.queue.lock().await;
-            queue.push_back((name.to_string(), Box::new(future)));
-            Ok(())
+            queue.push_back((name.to_string(), Box::pin(future)));
+            self.shared.record_spawn(name, TaskState::Queued);
+            log::debug!("Queued task '{}' behind {} running task(s)", name, tasks.len());
         } else {
-            self.spawn_task(name, future, &mut tasks).await
+            drop(queue);
+            self.shared.record_spawn(name, TaskState::Running);
+            Self::spawn_task(name, Box::pin(future), &mut tasks, &self.shared);
         }
+        Ok(())
     }
 
-    async fn spawn_task<F>(&self, name: &str, future: F, tasks: &mut HashMap<String, JoinHandle<()>>) -> BotResult<()>
+    /// Spawns a task built by `factory`, starting it again according to `options.restart`
+    /// whenever it returns, fails or panics. Each run gets a `ShutdownToken` and should return
+    /// once it is cancelled; nothing is restarted after that.
+    pub async fn spawn_supervised<F, Fut>(&self, name: &str, options: TaskOptions, factory: F) -> BotResult<()>
     where
-        F: Future<Output = ()> + Send + 'static,
+        F: Fn(ShutdownToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = BotResult<()>> + Send + 'static,
     {
-        if tasks.contains_key(name) {
-            return Err(BotError::Internal(format!("Task '{}' already exists", name)));
+        if self.is_running(name).await {
+            if !options.replace_existing {
+                return Err(BotError::Internal(format!(
+                    "Task '{}' is already running; use TaskOptions::replace_existing to replace it",
+                    name
+                )));
+            }
+            log::info!("Replacing task '{}'", name);
+            self.cancel(name).await?;
         }
-        let queue = self.queue.clone();
-        let tasks_clone = self.tasks.clone();
+        let supervisor = Self::supervise(name.to_string(), options, factory, self.shared.clone());
+        self.spawn(name, supervisor).await
+    }
+
+    async fn supervise<F, Fut>(name: String, options: TaskOptions, factory: F, shared: Shared)
+    where
+        F: Fn(ShutdownToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = BotResult<()>> + Send + 'static,
+    {
+        let shutdown = ShutdownToken { receiver: shared.shutdown.subscribe() };
+        let mut restarts = 0u32;
+        loop {
+            let started = Instant::now();
+            let failure = match AssertUnwindSafe(factory(shutdown.clone())).catch_unwind().await {
+                Ok(Ok(())) => None,
+                Ok(Err(why)) => {
+                    log::error!("Task '{}' failed: {:?}", name, why);
+                    Some(why.to_string())
+                }
+                Err(panic) => Some(format!("panicked: {}", Self::report_panic(&name, panic, &shared.telemetry).await)),
+            };
+            if let Some(failure) = &failure {
+                shared.update(&name, |info| info.last_error = Some(failure.clone()));
+            }
+
+            let restart = !shutdown.is_cancelled() && match options.restart {
+                RestartPolicy::Never => false,
+                RestartPolicy::OnFailure => failure.is_some(),
+                RestartPolicy::Always => true,
+            };
+            if !restart {
+                match failure {
+                    Some(_) => shared.record_finish(&name, TaskState::Failed, None),
+                    None => log::debug!("Task '{}' finished", name),
+                }
+                return;
+            }
+
+            if started.elapsed() >= options.max_backoff {
+                restarts = 0;
+            }
+            if options.max_restarts.map_or(false, |max| restarts >= max) {
+                log::error!("Task '{}' was restarted {} times in a row, giving up", name, restarts);
+                let message = format!("Task '{}' gave up after {} restarts", name, restarts);
+                shared.record_finish(&name, TaskState::Failed, Some(message.clone()));
+                if let Some(telemetry) = &shared.telemetry {
+                    telemetry.log_error("task_gave_up", &message).await.unwrap_or_else(|e| log::error!("Failed to log error: {:?}", e));
+                }
+                return;
+            }
+
+            let delay = options.backoff_for(restarts);
+            restarts += 1;
+            log::warn!("Restarting task '{}' in {:?} (restart {})", name, delay, restarts);
+            if let Some(telemetry) = &shared.telemetry {
+                let data = json!({
+                    "task": name,
+                    "restart": restarts,
+                    "delay_ms": delay.as_millis() as u64,
+                    "failed": failure.is_some(),
+                });
+                telemetry.log_event_with("task_restart", data).await.unwrap_or_else(|e| log::error!("Failed to log event: {:?}", e));
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = shutdown.cancelled() => {
+                    log::debug!("Task '{}' stopped for shutdown before restarting", name);
+                    return;
+                }
+            }
+            shared.record_start(&name);
+        }
+    }
+
+    async fn report_panic(name: &str, panic: Box<dyn Any + Send>, telemetry: &Telemetry) -> String {
+        let message = panic.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        log::error!("Task '{}' panicked: {}", name, message);
+        if let Some(telemetry) = telemetry {
+            telemetry.log_error("task_panic", &format!("Task '{}' panicked: {}", name, message)).await
+                .unwrap_or_else(|e| log::error!("Failed to log error: {:?}", e));
+        }
+        message
+    }
+
+    fn spawn_task(name: &str, future: BoxedTask, tasks: &mut HashMap<String, JoinHandle<()>>, shared: &Shared) {
+        let shared_clone = shared.clone();
         let name_clone = name.to_string();
+        shared.record_start(name);
+        // Shutdown never interrupts a task here: it runs to completion or until `shutdown`
+        // aborts it after the grace period. Long-running tasks watch their `ShutdownToken`.
         let handle = tokio::spawn(async move {
-            future.await;
-            Self::task_completed(name_clone, queue, tasks_clone).await;
+            // A panic is caught here so the task is still removed and the queue keeps moving.
+            match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(()) => shared_clone.record_finish(&name_clone, TaskState::Completed, None),
+                Err(panic) => {
+                    let message = Self::report_panic(&name_clone, panic, &shared_clone.telemetry).await;
+                    shared_clone.record_finish(&name_clone, TaskState::Failed, Some(format!("panicked: {}", message)));
+                }
+            }
+            Self::task_completed(name_clone, shared_clone).await;
         });
         tasks.insert(name.to_string(), handle);
-        Ok(())
     }
 
-    async fn task_completed(name: String, queue: Arc<Mutex<VecDeque<(String, Box<dyn Future<Output = ()> + Send + 'static>)>>>, tasks: Arc<Mutex<HashMap<String, JoinHandle<()>>>>) {
-        let mut tasks = tasks.lock().await;
+    async fn task_completed(name: String, shared: Shared) {
+        let mut tasks = shared.tasks.lock().await;
         tasks.remove(&name);
-        
-        let mut queue = queue.lock().await;
-        if let Some((next_name, next_future)) = queue.pop_front() {
-            let _ = Self::spawn_task(&next_name, next_future, &mut tasks).await;
+
+        if *shared.shutdown.borrow() {
+            return;
+        }
+        let mut queue_guard = shared.queue.lock().await;
+        if let Some((next_name, next_future)) = queue_guard.pop_front() {
+            drop(queue_guard);
+            Self::spawn_task(&next_name, next_future, &mut tasks, &shared);
         }
     }
 
     pub async fn cancel(&self, name: &str) -> BotResult<()> {
-        let mut tasks = self.tasks.lock().await;
+        let mut tasks = self.shared.tasks.lock().await;
         if let Some(handle) = tasks.remove(name) {
             handle.abort();
+            self.shared.record_finish(name, TaskState::Cancelled, None);
             Ok(())
         } else {
-            let mut queue = self.queue.lock().await;
+            let mut queue = self.shared.queue.lock().await;
             if let Some(index) = queue.iter().position(|(n, _)| n == name) {
                 queue.remove(index);
+                self.shared.record_finish(name, TaskState::Cancelled, None);
                 Ok(())
             } else {
                 Err(BotError::Internal(format!("Task '{}' not found", name)))
@@ -79,36 +445,143 @@ impl TaskManager {
     }
 
     pub async fn cancel_all(&self) {
-        let mut tasks = self.tasks.lock().await;
-        for (_, handle) in tasks.drain() {
+        let mut tasks = self.shared.tasks.lock().await;
+        for (name, handle) in tasks.drain() {
             handle.abort();
+            self.shared.record_finish(&name, TaskState::Cancelled, None);
+        }
+        let mut queue = self.shared.queue.lock().await;
+        for (name, _) in queue.drain(..) {
+            self.shared.record_finish(&name, TaskState::Cancelled, None);
         }
-        let mut queue = self.queue.lock().await;
-        queue.clear();
     }
 
+    /// Cancels every `ShutdownToken`, waits up to `grace` for running tasks to finish and aborts
+    /// the rest. Queued tasks never start.
+    pub async fn shutdown(&self, grace: Duration) {
+        let _ = self.shared.shutdown.send(true);
+        for (name, _) in self.shared.queue.lock().await.drain(..) {
+            self.shared.record_finish(&name, TaskState::Cancelled, None);
+        }
+
+        let deadline = tokio::time::Instant::now() + grace;
+        loop {
+            let remaining = self.shared.tasks.lock().await.len();
+            if remaining == 0 {
+                return;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                log::warn!("Aborting {} task(s) that did not stop within {:?}", remaining, grace);
+                self.cancel_all().await;
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Whether the task is running or waiting in the queue.
     pub async fn is_running(&self, name: &str) -> bool {
-        let tasks = self.tasks.lock().await;
-        tasks.contains_key(name) || self.queue.lock().await.iter().any(|(n, _)| n == name)
+        let tasks = self.shared.tasks.lock().await;
+        tasks.contains_key(name) || self.shared.queue.lock().await.iter().any(|(n, _)| n == name)
     }
 
     pub async fn get_running_tasks(&self) -> Vec<String> {
-        let tasks = self.tasks.lock().await;
-        let queue = self.queue.lock().await;
-        let mut running_tasks: Vec<String> = tasks.keys().cloned().collect();
-        running_tasks.extend(queue.iter().map(|(n, _)| n.clone()));
-        running_tasks
+        let tasks = self.shared.tasks.lock().await;
+        tasks.keys().cloned().collect()
+    }
+
+    /// Queued task names, in the order they will start.
+    pub async fn get_queued_tasks(&self) -> Vec<String> {
+        let queue = self.shared.queue.lock().await;
+        queue.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    pub fn get_task_info(&self, name: &str) -> Option<TaskInfo> {
+        self.shared.stats.lock().unwrap().get(name).cloned()
+    }
+
+    /// Every known task: running first, then queued in start order, then finished tasks,
+    /// most recent first.
+    pub fn get_task_infos(&self) -> Vec<TaskInfo> {
+        let mut infos: Vec<TaskInfo> = self.shared.stats.lock().unwrap().values().cloned().collect();
+        let rank = |info: &TaskInfo| match info.state {
+            TaskState::Running => 0,
+            TaskState::Queued => 1,
+            _ => 2,
+        };
+        infos.sort_by(|a, b| {
+            rank(a).cmp(&rank(b)).then_with(|| match a.state {
+                TaskState::Running => a.name.cmp(&b.name),
+                TaskState::Queued => a.spawned_at.cmp(&b.spawned_at),
+                _ => b.finished_at.cmp(&a.finished_at),
+            })
+        });
+        infos
     }
 }
 
 impl Drop for TaskManager {
     fn drop(&mut self) {
-        let tasks = self.tasks.clone();
-        tokio::spawn(async move {
-            let mut tasks = tasks.lock().await;
+        // Aborting is synchronous, so this works even if the runtime is already shutting down.
+        if let Ok(mut tasks) = self.shared.tasks.try_lock() {
             for (_, handle) in tasks.drain() {
                 handle.abort();
             }
-        });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn supervised_tasks_stop_on_their_token_and_are_not_restarted() {
+        let tasks = TaskManager::new(2);
+        let runs = Arc::new(AtomicU32::new(0));
+        let options = TaskOptions::restart(RestartPolicy::Always).backoff(Duration::ZERO, Duration::ZERO);
+        tasks.spawn_supervised("loop", options, {
+            let runs = Arc::clone(&runs);
+            move |shutdown| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    shutdown.cancelled().await;
+                    Ok(())
+                }
+            }
+        }).await.unwrap();
+        tokio::task::yield_now().await;
+
+        tasks.shutdown(Duration::from_secs(5)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(tasks.get_task_info("loop").unwrap().state, TaskState::Completed);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn running_steps_finish_within_the_grace_period() {
+        let tasks = TaskManager::new(2);
+        let finished = Arc::new(AtomicBool::new(false));
+        tasks.spawn("step", {
+            let finished = Arc::clone(&finished);
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                finished.store(true, Ordering::SeqCst);
+            }
+        }).await.unwrap();
+
+        tasks.shutdown(Duration::from_secs(5)).await;
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(tasks.get_task_info("step").unwrap().state, TaskState::Completed);
+    }
+
+    #[tokio::test]
+    async fn tasks_past_the_grace_period_are_aborted() {
+        let tasks = TaskManager::new(2);
+        tasks.spawn("stuck", std::future::pending()).await.unwrap();
+
+        tasks.shutdown(Duration::from_millis(50)).await;
+        assert!(tasks.get_running_tasks().await.is_empty());
+        assert_eq!(tasks.get_task_info("stuck").unwrap().state, TaskState::Cancelled);
+    }
+}